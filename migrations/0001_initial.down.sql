@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS image_metas;
+DROP TABLE IF EXISTS is_tagging_album;
+DROP TABLE IF EXISTS albums;
+DROP TABLE IF EXISTS users;
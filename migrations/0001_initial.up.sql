@@ -1,6 +1,5 @@
-/* DROP TYPE IF EXISTS ROLE; /
-
-/ CREATE TYPE ROLE AS ENUM ('admin', 'tagger'); */
+-- Baseline schema. Written with IF NOT EXISTS so that databases created by
+-- the old schema.sql bootstrap can adopt the migration history unchanged.
 CREATE TABLE IF NOT EXISTS users (
     id SERIAL PRIMARY KEY,
     username    TEXT UNIQUE NOT NULL,
@@ -8,18 +7,11 @@ CREATE TABLE IF NOT EXISTS users (
     password    TEXT NOT NULL,
     role    TEXT NOT NULL,
     date_created DATE NOT NULL DEFAULT CURRENT_DATE,
-    last_modified DATE NOT NULL DEFAULT CURRENT_DATE
+    last_modified DATE NOT NULL DEFAULT CURRENT_DATE,
+    CONSTRAINT namechk CHECK (char_length(username) <= 64 AND char_length(username) >= 4),
+    CONSTRAINT userchk CHECK (char_length(nickname) <= 64 AND char_length(nickname) >= 4)
 );
 
-ALTER TABLE users DROP CONSTRAINT IF EXISTS namechk;
-ALTER TABLE users
-  ADD CONSTRAINT namechk CHECK (char_length(username) <= 64 AND char_length(username) >= 4);
-
-
-ALTER TABLE users DROP CONSTRAINT IF EXISTS userchk;
-ALTER TABLE users
-  ADD CONSTRAINT userchk CHECK (char_length(nickname) <= 64 AND char_length(nickname) >= 4);
-
 CREATE TABLE IF NOT EXISTS albums (
     id SERIAL PRIMARY KEY,
     title VARCHAR(300) NOT NULL,
@@ -56,8 +48,3 @@ CREATE TABLE IF NOT EXISTS image_metas (
     date_modified DATE NOT NULL DEFAULT CURRENT_DATE,
     FOREIGN KEY (album_id) REFERENCES albums (id)
 );
-
-
-
-
-
@@ -0,0 +1,10 @@
+DROP INDEX IF EXISTS image_metas_geometry_type_idx;
+ALTER TABLE image_metas
+    DROP CONSTRAINT coordinateschk,
+    DROP COLUMN coordinates,
+    DROP COLUMN width,
+    DROP COLUMN height;
+
+UPDATE image_metas SET legacy_coordinates = '' WHERE legacy_coordinates IS NULL;
+ALTER TABLE image_metas ALTER COLUMN legacy_coordinates SET NOT NULL;
+ALTER TABLE image_metas RENAME COLUMN legacy_coordinates TO coordinates;
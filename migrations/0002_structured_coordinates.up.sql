@@ -0,0 +1,13 @@
+-- Free text coordinates cannot be validated or queried. Keep the old values
+-- for manual recovery and store typed geometry as jsonb from now on.
+ALTER TABLE image_metas RENAME COLUMN coordinates TO legacy_coordinates;
+ALTER TABLE image_metas ALTER COLUMN legacy_coordinates DROP NOT NULL;
+UPDATE image_metas SET legacy_coordinates = NULL WHERE legacy_coordinates = '';
+
+ALTER TABLE image_metas
+    ADD COLUMN coordinates JSONB,
+    ADD COLUMN width INT,
+    ADD COLUMN height INT,
+    ADD CONSTRAINT coordinateschk CHECK (coordinates IS NULL OR jsonb_typeof(coordinates) = 'object');
+
+CREATE INDEX image_metas_geometry_type_idx ON image_metas ((coordinates->>'type'));
@@ -0,0 +1,13 @@
+ALTER TABLE image_metas
+    ADD COLUMN tag VARCHAR(100),
+    ADD COLUMN coordinates JSONB,
+    ADD CONSTRAINT coordinateschk CHECK (coordinates IS NULL OR jsonb_typeof(coordinates) = 'object');
+CREATE INDEX image_metas_geometry_type_idx ON image_metas ((coordinates->>'type'));
+
+-- Only one annotation per image fits into the old layout, keep the oldest
+UPDATE image_metas SET tag = a.tag, coordinates = a.coordinates
+    FROM (SELECT DISTINCT ON (image_id) image_id, tag, coordinates FROM annotations ORDER BY image_id, id) a
+    WHERE image_metas.id = a.image_id;
+UPDATE image_metas SET tag = '' WHERE tag IS NULL;
+
+DROP TABLE annotations;
@@ -0,0 +1,26 @@
+-- An image can carry any number of labeled shapes
+CREATE TABLE annotations (
+    id SERIAL PRIMARY KEY,
+    image_id INT NOT NULL,
+    tag VARCHAR(100) NOT NULL,
+    coordinates JSONB NOT NULL,
+    users_id INT,
+    date_created DATE NOT NULL DEFAULT CURRENT_DATE,
+    date_modified DATE NOT NULL DEFAULT CURRENT_DATE,
+    CONSTRAINT annotationschk CHECK (jsonb_typeof(coordinates) = 'object'),
+    FOREIGN KEY (image_id) REFERENCES image_metas (id) ON DELETE CASCADE,
+    FOREIGN KEY (users_id) REFERENCES users (id) ON DELETE SET NULL
+);
+
+CREATE INDEX annotations_image_id_idx ON annotations (image_id);
+CREATE INDEX annotations_geometry_type_idx ON annotations ((coordinates->>'type'));
+
+INSERT INTO annotations (image_id, tag, coordinates)
+    SELECT id, tag, coordinates FROM image_metas
+    WHERE coordinates IS NOT NULL AND tag IS NOT NULL AND tag <> '';
+
+DROP INDEX image_metas_geometry_type_idx;
+ALTER TABLE image_metas
+    DROP CONSTRAINT coordinateschk,
+    DROP COLUMN coordinates,
+    DROP COLUMN tag;
@@ -0,0 +1 @@
+ALTER TABLE image_metas DROP COLUMN locked_by;
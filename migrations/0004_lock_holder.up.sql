@@ -0,0 +1,4 @@
+-- Remember who holds the tagging lock of a photo
+ALTER TABLE image_metas
+    ADD COLUMN locked_by INT,
+    ADD FOREIGN KEY (locked_by) REFERENCES users (id) ON DELETE SET NULL;
@@ -0,0 +1 @@
+ALTER TABLE image_metas DROP COLUMN original_filename;
@@ -0,0 +1,3 @@
+-- Name of the file as uploaded, photos are stored under generated names.
+-- Used to match imported annotation files to photos.
+ALTER TABLE image_metas ADD COLUMN original_filename VARCHAR(255);
@@ -0,0 +1,6 @@
+DROP INDEX image_metas_checksum_idx;
+
+ALTER TABLE image_metas
+    DROP COLUMN mime_type,
+    DROP COLUMN byte_size,
+    DROP COLUMN checksum;
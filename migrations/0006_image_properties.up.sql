@@ -0,0 +1,8 @@
+-- Properties of the stored file. Photos uploaded before stay NULL until the
+-- backfill job (backend backfill) has read their files.
+ALTER TABLE image_metas
+    ADD COLUMN mime_type VARCHAR(50),
+    ADD COLUMN byte_size BIGINT,
+    ADD COLUMN checksum CHAR(64);
+
+CREATE INDEX image_metas_checksum_idx ON image_metas (checksum);
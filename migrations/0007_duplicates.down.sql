@@ -0,0 +1,4 @@
+DROP TABLE duplicate_dismissals;
+
+ALTER TABLE image_metas
+    DROP COLUMN perceptual_hash;
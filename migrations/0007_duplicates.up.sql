@@ -0,0 +1,12 @@
+-- Difference hash of the downscaled image for finding near-duplicates, NULL
+-- until set on upload or by the backfill job
+ALTER TABLE image_metas
+    ADD COLUMN perceptual_hash BIGINT;
+
+-- Pairs of photos an owner has reviewed and kept both, image_id is the smaller id
+CREATE TABLE duplicate_dismissals (
+    image_id INT NOT NULL REFERENCES image_metas(id) ON DELETE CASCADE,
+    other_image_id INT NOT NULL REFERENCES image_metas(id) ON DELETE CASCADE,
+    PRIMARY KEY (image_id, other_image_id),
+    CHECK (image_id < other_image_id)
+);
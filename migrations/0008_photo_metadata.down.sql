@@ -0,0 +1,8 @@
+ALTER TABLE albums
+    DROP COLUMN strip_metadata;
+
+ALTER TABLE image_metas
+    DROP COLUMN orientation,
+    DROP COLUMN captured_at,
+    DROP COLUMN camera_make,
+    DROP COLUMN camera_model;
@@ -0,0 +1,12 @@
+-- EXIF data read on upload. The orientation is the one of the uploaded file,
+-- stored files are always rotated upright. Capture time is the local time of
+-- the camera, EXIF does not know the time zone.
+ALTER TABLE image_metas
+    ADD COLUMN orientation SMALLINT,
+    ADD COLUMN captured_at TIMESTAMP,
+    ADD COLUMN camera_make VARCHAR(100),
+    ADD COLUMN camera_model VARCHAR(100);
+
+-- Remove EXIF and XMP data like the GPS location from uploaded photos
+ALTER TABLE albums
+    ADD COLUMN strip_metadata BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1 @@
+DROP INDEX image_metas_album_file_path;
@@ -0,0 +1,25 @@
+-- Photos are named after their id, no two rows of an album may point to the
+-- same file.
+-- Concurrent uploads used to pick the same next file name, the later upload
+-- overwrote the file of the earlier one. Which of the photos the file shows
+-- can not be told here, so the migration stops and lists them. Delete the
+-- wrong rows and start the backend again.
+DO $$
+DECLARE
+    shared_files TEXT;
+BEGIN
+    SELECT string_agg(format('album %s file %s: photos %s', album_id, file_path, ids), '; ')
+    INTO shared_files
+    FROM (
+        SELECT album_id, file_path, string_agg(id::TEXT, ', ' ORDER BY id) AS ids
+        FROM image_metas
+        GROUP BY album_id, file_path
+        HAVING count(*) > 1
+    ) AS duplicates;
+    IF shared_files IS NOT NULL THEN
+        RAISE EXCEPTION 'Several photos point to the same file, keep one of each: %', shared_files;
+    END IF;
+END
+$$;
+
+CREATE UNIQUE INDEX image_metas_album_file_path ON image_metas (album_id, file_path);
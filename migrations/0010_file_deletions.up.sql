@@ -0,0 +1,12 @@
+-- Files of deleted photos and albums. Rows are written in the transaction
+-- that deletes the image metas, the files are removed afterwards by a
+-- background job that retries failures. A NULL file_path stands for the whole
+-- album folder.
+CREATE TABLE file_deletions (
+    id SERIAL PRIMARY KEY,
+    album_id INT NOT NULL,
+    file_path TEXT,
+    attempts INT NOT NULL DEFAULT 0,
+    last_error TEXT,
+    date_created TIMESTAMP NOT NULL DEFAULT now()
+);
@@ -0,0 +1,6 @@
+-- Albums and photos in the trash are restored
+DROP INDEX image_metas_deleted_at_idx;
+DROP INDEX albums_deleted_at_idx;
+
+ALTER TABLE image_metas DROP COLUMN deleted_at;
+ALTER TABLE albums DROP COLUMN deleted_at;
@@ -0,0 +1,8 @@
+-- Deleted albums and photos stay in the trash until they are restored or the
+-- retention period ([trash] in Settings.toml) has passed. Purged rows are
+-- deleted and their files queued in file_deletions.
+ALTER TABLE albums ADD COLUMN deleted_at TIMESTAMP;
+ALTER TABLE image_metas ADD COLUMN deleted_at TIMESTAMP;
+
+CREATE INDEX albums_deleted_at_idx ON albums (deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX image_metas_deleted_at_idx ON image_metas (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,9 @@
+DROP TRIGGER image_metas_counters_update ON image_metas;
+DROP TRIGGER image_metas_counters_insert_delete ON image_metas;
+DROP FUNCTION update_album_counters();
+
+ALTER TABLE albums
+    ALTER COLUMN image_number DROP NOT NULL,
+    ALTER COLUMN tagged_number DROP NOT NULL;
+
+ALTER TABLE albums DROP COLUMN verified_number;
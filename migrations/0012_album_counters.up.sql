@@ -0,0 +1,62 @@
+-- Photo counters of albums, kept up to date by triggers on image_metas.
+-- Photos in the trash are not counted. Counters can be recomputed by an admin
+-- (POST /api/admin/maintenance/counters or backend counters).
+ALTER TABLE albums ADD COLUMN verified_number INT NOT NULL DEFAULT 0;
+
+UPDATE albums SET
+    image_number = counts.image_number,
+    tagged_number = counts.tagged_number,
+    verified_number = counts.verified_number
+FROM (
+    SELECT albums.id,
+           COUNT(image_metas.id) AS image_number,
+           COUNT(image_metas.id) FILTER (WHERE image_metas.tagged) AS tagged_number,
+           COUNT(image_metas.id) FILTER (WHERE image_metas.verified) AS verified_number
+    FROM albums LEFT JOIN image_metas ON image_metas.album_id = albums.id AND image_metas.deleted_at IS NULL
+    GROUP BY albums.id
+) AS counts
+WHERE albums.id = counts.id;
+
+ALTER TABLE albums
+    ALTER COLUMN image_number SET NOT NULL,
+    ALTER COLUMN tagged_number SET NOT NULL;
+
+CREATE FUNCTION update_album_counters() RETURNS trigger AS $$
+BEGIN
+    -- OLD is not assigned on INSERT and NEW not on DELETE
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        IF OLD.deleted_at IS NULL THEN
+            UPDATE albums SET
+                image_number = image_number - 1,
+                tagged_number = tagged_number - COALESCE(OLD.tagged, FALSE)::INT,
+                verified_number = verified_number - COALESCE(OLD.verified, FALSE)::INT
+            WHERE id = OLD.album_id;
+        END IF;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        IF NEW.deleted_at IS NULL THEN
+            UPDATE albums SET
+                image_number = image_number + 1,
+                tagged_number = tagged_number + COALESCE(NEW.tagged, FALSE)::INT,
+                verified_number = verified_number + COALESCE(NEW.verified, FALSE)::INT
+            WHERE id = NEW.album_id;
+        END IF;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER image_metas_counters_insert_delete
+    AFTER INSERT OR DELETE ON image_metas
+    FOR EACH ROW EXECUTE PROCEDURE update_album_counters();
+
+-- Only changes of counted columns lock the album row, so taggers of the same
+-- album do not wait for each other when they take photos
+CREATE TRIGGER image_metas_counters_update
+    AFTER UPDATE OF album_id, tagged, verified, deleted_at ON image_metas
+    FOR EACH ROW
+    WHEN (OLD.album_id IS DISTINCT FROM NEW.album_id
+          OR OLD.tagged IS DISTINCT FROM NEW.tagged
+          OR OLD.verified IS DISTINCT FROM NEW.verified
+          OR OLD.deleted_at IS DISTINCT FROM NEW.deleted_at)
+    EXECUTE PROCEDURE update_album_counters();
@@ -0,0 +1,14 @@
+-- Login sessions. The cookie holds a random session token, only its SHA-256
+-- hash is stored. Deleting a row logs the session out on its next request.
+CREATE TABLE sessions (
+    id SERIAL PRIMARY KEY,
+    token_hash CHAR(64) UNIQUE NOT NULL,
+    users_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    user_agent TEXT,
+    ip_address TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    last_seen_at TIMESTAMP NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX sessions_users_id_idx ON sessions (users_id);
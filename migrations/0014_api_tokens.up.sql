@@ -0,0 +1,15 @@
+-- Personal access tokens, sent as "Authorization: Bearer <token>". Like
+-- sessions only the SHA-256 hash of the token is stored. A NULL expires_at
+-- never expires.
+CREATE TABLE api_tokens (
+    id SERIAL PRIMARY KEY,
+    users_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    name VARCHAR(100) NOT NULL,
+    token_hash CHAR(64) UNIQUE NOT NULL,
+    scopes TEXT[] NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP,
+    last_used_at TIMESTAMP
+);
+
+CREATE INDEX api_tokens_users_id_idx ON api_tokens (users_id);
@@ -0,0 +1,5 @@
+ALTER TABLE users ALTER COLUMN role TYPE TEXT USING role::TEXT;
+
+UPDATE users SET role = 'user' WHERE role IN ('reviewer', 'viewer');
+
+DROP TYPE user_role;
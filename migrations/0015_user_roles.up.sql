@@ -0,0 +1,7 @@
+-- Roles are an enum instead of free text. Reviewers verify photos of all
+-- albums, viewers can only look at albums and photos.
+CREATE TYPE user_role AS ENUM ('admin', 'reviewer', 'user', 'viewer');
+
+UPDATE users SET role = 'user' WHERE role NOT IN ('admin', 'user');
+
+ALTER TABLE users ALTER COLUMN role TYPE user_role USING role::user_role;
@@ -0,0 +1,11 @@
+DROP TABLE album_members;
+DROP TYPE album_role;
+
+CREATE TABLE is_tagging_album (
+    users_id INT NOT NULL,
+    album_id INT NOT NULL,
+    in_progress bool NOT NULL,
+    PRIMARY KEY ( users_id, album_id),
+    FOREIGN KEY (users_id) REFERENCES users (id),
+    FOREIGN KEY (album_id) REFERENCES albums (id)
+);
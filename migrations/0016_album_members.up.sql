@@ -0,0 +1,16 @@
+-- Users the owner of an album invited to work on it. is_tagging_album was
+-- never used and is replaced by this table.
+DROP TABLE is_tagging_album;
+
+CREATE TYPE album_role AS ENUM ('tagger', 'reviewer', 'co_owner');
+
+CREATE TABLE album_members (
+    album_id INT NOT NULL REFERENCES albums (id) ON DELETE CASCADE,
+    users_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    role album_role NOT NULL,
+    invited_by INT REFERENCES users (id) ON DELETE SET NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (album_id, users_id)
+);
+
+CREATE INDEX album_members_users_id ON album_members (users_id);
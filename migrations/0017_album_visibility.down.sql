@@ -0,0 +1,5 @@
+DROP TABLE album_share_links;
+
+ALTER TABLE albums DROP COLUMN visibility;
+
+DROP TYPE album_visibility;
@@ -0,0 +1,21 @@
+-- Private albums are seen by their owner, members, admins and reviewers,
+-- internal albums by every logged in user and public albums by anyone.
+-- Existing albums were readable by everyone and stay readable for logged in
+-- users, new albums are private.
+CREATE TYPE album_visibility AS ENUM ('private', 'internal', 'public');
+
+ALTER TABLE albums ADD COLUMN visibility album_visibility NOT NULL DEFAULT 'internal';
+ALTER TABLE albums ALTER COLUMN visibility SET DEFAULT 'private';
+
+-- Anonymous read access to one album. The link holds a random token, only
+-- its SHA-256 hash is stored.
+CREATE TABLE album_share_links (
+    id SERIAL PRIMARY KEY,
+    album_id INT NOT NULL REFERENCES albums (id) ON DELETE CASCADE,
+    token_hash CHAR(64) UNIQUE NOT NULL,
+    created_by INT REFERENCES users (id) ON DELETE SET NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX album_share_links_album_id ON album_share_links (album_id);
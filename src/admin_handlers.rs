@@ -1,15 +1,16 @@
-use crate::errors::HandlerError;
-use crate::user_models::{CreateUser, UpdateUserAdmin, User};
+use crate::errors::{DBError, HandlerError};
+use crate::user_models::{CreateUser, UpdateUserAdmin, User, PhotoSizeQuery, ConsistencyRepair};
 use actix_web::http::StatusCode;
-use actix_web::{web, HttpResponse, Result};
+use actix_web::{web, HttpRequest, HttpResponse, Result};
 use deadpool_postgres::Pool;
 use log::{error, info};
 
+use crate::config::Trash;
 use crate::db;
-use std::fs;
-
-use actix_files::NamedFile;
-use std::path::PathBuf;
+use crate::derivatives;
+use crate::handlers::serve_file;
+use crate::maintenance;
+use crate::storage::Storage;
 
 pub async fn create_user(
     pool: web::Data<Pool>,
@@ -60,7 +61,7 @@ pub async fn update_user(
     let new_user = User {
         nickname: data.nickname.clone(),
         password: data.password.clone(),
-        role: data.role.clone(),
+        role: data.role,
         ..user
     };
 
@@ -75,6 +76,31 @@ pub async fn update_user(
     Ok(HttpResponse::build(StatusCode::OK).json(result))
 }
 
+// log out all sessions of a user, e.g. when the account was compromised
+pub async fn delete_user_sessions(
+    pool: web::Data<Pool>,
+    data: web::Path<(i32,)>,
+) -> Result<HttpResponse, HandlerError> {
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured : {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    match db::delete_sessions_of_user(&client, data.0, None).await {
+        Ok(deleted) => {
+            info!("Logged out {} session(s) of user {}", deleted, data.0);
+            Ok(HttpResponse::build(StatusCode::OK).json(format!("Success delete {} session(s)", deleted)))
+        }
+        Err(e) => {
+            error!("Error occured deleting sessions: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
 pub async fn delete_user(
     pool: web::Data<Pool>,
     data: web::Path<(i32,)>,
@@ -102,9 +128,11 @@ pub async fn delete_user(
 
 pub async fn get_photo(
     pool: web::Data<Pool>,
-    tagify_albums_path: web::Data<String,>,
+    storage: web::Data<Box<dyn Storage>>,
     parameters: web::Path<(i32, i32)>,
-) -> Result<NamedFile, HandlerError> {
+    query: web::Query<PhotoSizeQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, HandlerError> {
     let client = match pool.get().await {
         Ok(item) => item,
         Err(e) => {
@@ -114,7 +142,6 @@ pub async fn get_photo(
     };
     let album_id = parameters.0;
     let image_id = parameters.1;
-    let album_path = format!("{}{}/", tagify_albums_path.to_string(), &album_id);
 
     // Check if image exists in db:
     let file_path_db = db::get_image_file_path_with_id_from_album(&client, &album_id, &image_id).await;
@@ -125,12 +152,6 @@ pub async fn get_photo(
     }
 
     // Check album exist
-    if !std::path::Path::new(&album_path).exists() {
-        error!("Error occured : album with id={} not found on disk", &album_id);
-        return Err(HandlerError::BadClientData {
-            field: "Album not found".to_string()
-        });
-    }
     if  !db::check_album_exist_by_id(&client, &album_id).await {
         error!("Error occured : album with id={} not found in db", &album_id);
         return Err(HandlerError::BadClientData {
@@ -138,33 +159,13 @@ pub async fn get_photo(
         });
     }
 
-    let filepath = format!("{}{}", album_path, file_path_db);
-    // Check file exist
-    if !std::path::Path::new(&filepath).exists() {
-        error!("Error occured : image file with id={} not found on disk", &filepath);
-        return Err(HandlerError::BadClientData {
-            field: "File not found".to_string()
-        });
-    }
-
-    let path: PathBuf = filepath.parse().unwrap();
-    println!("{:?}", path);
-
-    let r = NamedFile::open(&path);
-    match &r {
-        Ok(_) => info!("success open file {:?}", &path),
-        Err(e) => {
-            error!("unsuccess open: {:?}", e);
-            return Err(HandlerError::InternalError);
-        },
-    };
-
-    Ok(r.unwrap())
+    // Downscaled copies of photos uploaded before are created on first request
+    let key = derivatives::get_or_generate(storage.get_ref().as_ref(), album_id, &file_path_db, query.size).await;
+    serve_file(storage.get_ref().as_ref(), &req, &key).await
 }
 
 pub async fn delete_photo(
     pool: web::Data<Pool>,
-    tagify_albums_path: web::Data<String,>,
     parameters: web::Path<(i32, i32)>,
 ) -> Result<HttpResponse, HandlerError> {
     let client = match pool.get().await {
@@ -176,56 +177,24 @@ pub async fn delete_photo(
     };
     let album_id = parameters.0;
     let image_id = parameters.1;
-    let album_path = format!("{}{}/", tagify_albums_path.to_string(), &album_id);
-
-    // Check if image exists in db:
-    let file_path_db = db::get_image_file_path_with_id_from_album(&client, &album_id, &image_id).await;
-    if file_path_db == "".to_string() {
-        return Err(HandlerError::BadClientData {
-            field: "Id of image not found in db".to_string()
-        });
-    }
 
     // Check album exist
-    if !std::path::Path::new(&album_path).exists() {
-        error!("Error occured : album with id={} not found on disk", &album_id);
-        return Err(HandlerError::BadClientData {
-            field: "Album not found".to_string()
-        });
-    }
     if  !db::check_album_exist_by_id(&client, &album_id).await {
-        error!("Error occured : album with id={} not found in db", &album_id);
-        return Err(HandlerError::BadClientData {
-            field: "Album not found".to_string()
+        return Err(HandlerError::NotFound {
+            err_message: format!("Album {} not found", album_id)
         });
     }
 
-    // Check file exist
-    let filepath = format!("{}{}", album_path, file_path_db);
-    if !std::path::Path::new(&filepath).exists() {
-        error!("Error occured : image file with id={} not found on disk", &filepath);
-        return Err(HandlerError::BadClientData {
-            field: "File not found".to_string()
-        });
-    }
-
-    // Delete file 
-    match fs::remove_file(&filepath) {
-        Ok(_) => info!("Deleted file "),
-        Err(e) => {
-            error!("Error deleting file {}: {:?}", &filepath, e);
-            return Err(HandlerError::InternalError);
-        }
-    }
-    
-    // Delete from db
-    match db::delete_image_meta(
-        &client, 
+    // Move to the trash, the photo is purged after the retention period
+    match db::trash_image_meta(
+        &client,
+        &album_id,
         &image_id,
     ).await {
-        Ok(_) => info!("Delete meta id={} from album {} success", &image_id, &album_id),
+        Ok(_) => info!("Moved image id={} of album {} to the trash", &image_id, &album_id),
+        Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
         Err(e) => {
-            error!("Delete meta id={} from album {} success: {:?}", &image_id, &album_id, e);
+            error!("Delete meta id={} from album {} failed: {:?}", &image_id, &album_id, e);
             return Err(HandlerError::InternalError);
         }
     };
@@ -233,6 +202,28 @@ pub async fn delete_photo(
     Ok(HttpResponse::build(StatusCode::OK).json(format!("Success delete image id={}",  &image_id)))
 }
 
+// albums and photos of all users in the trash
+pub async fn get_trash(
+    pool: web::Data<Pool>,
+    trash: web::Data<Trash>,
+) -> Result<HttpResponse, HandlerError> {
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured : {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    match db::get_trash(&client, None, trash.retention_days).await {
+        Ok(content) => Ok(HttpResponse::build(StatusCode::OK).json(content)),
+        Err(e) => {
+            error!("Error occured get trash: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
 // get api/admin/users -> get all users data
 // should i also list admin ?
 pub async fn get_all_users(
@@ -255,4 +246,94 @@ pub async fn get_all_users(
     };
 
     Ok(HttpResponse::build(StatusCode::OK).json(result))
-}
\ No newline at end of file
+}
+// fill in size, type, checksum and perceptual hash of photos uploaded before these were recorded
+pub async fn backfill_image_properties(
+    pool: web::Data<Pool>,
+    storage: web::Data<Box<dyn Storage>>,
+) -> Result<HttpResponse, HandlerError> {
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured : {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    match maintenance::backfill_image_properties(&client, storage.get_ref().as_ref()).await {
+        Ok(report) => Ok(HttpResponse::build(StatusCode::OK).json(report)),
+        Err(e) => {
+            error!("Error occured during backfill: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+// set the photo counters of all albums to the actual numbers, lists the corrected albums
+pub async fn recompute_album_counters(
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, HandlerError> {
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured : {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    match db::recompute_album_counters(&client).await {
+        Ok(albums) => {
+            info!("Corrected photo counters of {} album(s)", albums.len());
+            Ok(HttpResponse::build(StatusCode::OK).json(albums))
+        }
+        Err(e) => {
+            error!("Error occured recomputing album counters: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+// compare album files with the image metas, without repairs
+pub async fn check_consistency(
+    pool: web::Data<Pool>,
+    storage: web::Data<Box<dyn Storage>>,
+) -> Result<HttpResponse, HandlerError> {
+    run_consistency_check(pool, storage, ConsistencyRepair::default()).await
+}
+
+// compare album files with the image metas and repair the differences
+pub async fn repair_consistency(
+    pool: web::Data<Pool>,
+    storage: web::Data<Box<dyn Storage>>,
+    data: web::Json<ConsistencyRepair>,
+) -> Result<HttpResponse, HandlerError> {
+    run_consistency_check(pool, storage, data.into_inner()).await
+}
+
+async fn run_consistency_check(
+    pool: web::Data<Pool>,
+    storage: web::Data<Box<dyn Storage>>,
+    repair: ConsistencyRepair,
+) -> Result<HttpResponse, HandlerError> {
+    let mut client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured : {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    match maintenance::check_consistency(&mut client, storage.get_ref().as_ref(), &repair).await {
+        Ok(report) => {
+            // Remove derivatives of deleted image metas right away
+            if !report.deleted_image_metas.is_empty() {
+                maintenance::spawn_file_deletions(pool, storage);
+            }
+            Ok(HttpResponse::build(StatusCode::OK).json(report))
+        }
+        Err(e) => {
+            error!("Error occured during consistency check: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
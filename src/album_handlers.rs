@@ -1,23 +1,43 @@
 
-use crate::album_models::{CreateAlbum, AlbumsPreview, UpdateAlbum, TagPhoto, VerifyPhoto};
-use crate::user_models::{User};
+use crate::album_models::{
+    Album, CreateAlbum, AlbumsPreview, UpdateAlbum, TagPhoto, VerifyPhoto, CreateAnnotation,
+    Tag, RenameTag, MergeTags, PhotoLock, ExportQuery, ImportFileReport, ImportReport, DuplicatesQuery,
+    DuplicatePair, DuplicateAction, ResolveDuplicate, AlbumRole, AddAlbumMember, UpdateAlbumMember,
+    CreateShareLink, CreatedShareLink, PhotoImport
+};
+use crate::config::{Tagging, Trash};
+use crate::user_models::{Role, User};
 
 use crate::errors::{HandlerError, DBError};
-use crate::my_identity_service::Identity;
+use crate::my_identity_service::{new_share_token, token_hash, Identity};
+use crate::permissions;
 use actix_web::http::StatusCode;
-use actix_web::{web, HttpResponse, Result};
+use actix_files::NamedFile;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
 use deadpool_postgres::Pool;
 use log::{error, info};
 
 use crate::db;
+use crate::handlers;
+use crate::export::{self, ExportImage};
+use crate::image_utils;
+use crate::storage::{self, Storage};
+use crate::import::{self, ImportFormat};
+use actix_multipart::Multipart;
+use futures::TryStreamExt;
+use std::collections::{BTreeSet, HashMap, HashSet};
 
 pub async fn create_album(
     pool: web::Data<Pool>,
     data: web::Json<CreateAlbum>,
     id: Identity,
-    tagify_albums_path: web::Data<String,>,
 ) -> Result<HttpResponse, HandlerError> {
     let user: User = id.identity();
+    if !permissions::can_create_album(&user) {
+        return Err(HandlerError::PermissionDenied {
+            err_message: "Viewers can not create albums".to_string()
+        });
+    }
     let first_photo = String::from("default_path");
 
     let client = match pool.get().await {
@@ -33,18 +53,8 @@ pub async fn create_album(
             error!("Error occured after create_album: {}", e);
             return Err(HandlerError::InternalError);
         }
-        Ok(album) => {
-            //TODO create album folder on photo_server
-            let path = format!("{}{}", tagify_albums_path.to_string(), &album.id);
-            match std::fs::create_dir_all(&path) {
-                Ok(_) => info!("Created folder for album with id={}", &album.id),
-                Err(e) => {
-                    error!("Error creating folder for album with id={}: {:?}", &album.id, e);
-                    return Err(HandlerError::InternalError);
-                }
-            }
-            album
-        },
+        // The storage creates the album folder with the first photo
+        Ok(album) => album,
     };
 
     Ok(HttpResponse::build(StatusCode::OK).json(result))
@@ -75,9 +85,38 @@ pub async fn get_own_albums(
     Ok(HttpResponse::build(StatusCode::OK).json(result))
 }
 
+// Album must exist and be visible to the user, None for anonymous requests.
+// Albums the user can not see are reported as missing
+pub async fn get_visible_album(
+    client: &deadpool_postgres::Client,
+    album_id: i32,
+    user: Option<&User>,
+) -> Result<Album, HandlerError> {
+    let result = match user {
+        Some(user) => db::get_album_with_role(client, album_id, user.id).await,
+        None => db::get_album_by_id(client, album_id).await.map(|album| (album, None)),
+    };
+    let (album, member) = match result {
+        Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
+        Err(e) => {
+            error!("Error occured get album: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+        Ok(item) => item,
+    };
+
+    if !permissions::can_view_album(user, &album, member) {
+        return Err(HandlerError::NotFound {
+            err_message: format!("Album {} not found", album_id)
+        });
+    }
+    Ok(album)
+}
+
 pub async fn get_album_by_id(
     pool: web::Data<Pool>,
     album_id: web::Path<(i32,)>,
+    id: Identity,
 ) -> Result<HttpResponse, HandlerError> {
     let client = match pool.get().await {
         Ok(item) => item,
@@ -87,24 +126,19 @@ pub async fn get_album_by_id(
         }
     };
 
-    // TODO ERROR album with this id does not exists
-    let result = match db::get_album_by_id(&client, album_id.0).await {
-        Err(e) => {
-            error!("Error occured get users albums: {}", e);
-            return Err(HandlerError::InternalError);
-        }
-        Ok(item) => item,
-    };
+    let result = get_visible_album(&client, album_id.0, id.user().as_ref()).await?;
 
     Ok(HttpResponse::build(StatusCode::OK).json(result))
 }
 
 
 
-// gets all albums data (id, title, description, first_photo)
+// gets data (id, title, description, first_photo) of all albums the user can see
 pub async fn get_all_albums(
-    pool: web::Data<Pool>
+    pool: web::Data<Pool>,
+    id: Identity,
 ) -> Result<HttpResponse, HandlerError> {
+    let user = id.user();
     
     let client = match pool.get().await {
         Ok(item) => item,
@@ -114,7 +148,12 @@ pub async fn get_all_albums(
         }
     };
 
-    let albums: AlbumsPreview = match db::get_all_albums(client).await {
+    let user_id = user.as_ref().map(|user| user.id);
+    let see_all = match &user {
+        Some(user) => permissions::can_view_all_albums(user),
+        None => false,
+    };
+    let albums: AlbumsPreview = match db::get_all_albums(client, user_id, see_all).await {
         Ok(albums) => albums,
         Err(e) => match e {
             DBError::PostgresError(e) => {
@@ -135,6 +174,12 @@ pub async fn get_all_albums(
                     field: err.to_owned(),
                 });
             }
+            DBError::NotFound { err } => {
+                error!("Error occured: {}", err);
+                return Err(HandlerError::NotFound {
+                    err_message: err.to_owned(),
+                });
+            }
         },
     };
     Ok(HttpResponse::build(StatusCode::OK).json(albums)) 
@@ -145,7 +190,8 @@ pub async fn get_all_albums(
 // get 20 next photos from album (start at 20 * index)
 pub async fn get_photos_from_album(
     pool: web::Data<Pool>,
-    data : web::Path<(i32, i32)>
+    data : web::Path<(i32, i32)>,
+    id: Identity,
 ) -> Result<HttpResponse, HandlerError> {
   
   let client = match pool.get().await {
@@ -156,8 +202,9 @@ pub async fn get_photos_from_album(
         }
     };
 
-    // TODO catch error if album with id does not exists
+    get_visible_album(&client, data.0, id.user().as_ref()).await?;
   let result = match db::get_photos_from_album(client, &data.0, &data.1).await {
+        Err(DBError::BadArgs { err }) => return Err(HandlerError::BadClientData { field: err }),
         Err(e) => {
             error!("Error occured : {}", e);
               return Err(HandlerError::InternalError);
@@ -186,6 +233,7 @@ pub async fn get_photos_from_album(
       };
 
     let result = match db::get_album_by_id(&client, album_id.0).await {
+        Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
         Err(e) => {
             error!("Error occured get users albums: {}", e);
             return Err(HandlerError::InternalError);
@@ -193,19 +241,23 @@ pub async fn get_photos_from_album(
         Ok(item) => item,
     };
 
-    if user.id == result.users_id || user.role == "admin" {
-        println!("usunie album");
-        match db::delete_album(&client, album_id.0).await {
-            Err(e) => {
-                error!("Error occured: {}", e);
-                return Err(HandlerError::InternalError);
-            }
-            Ok(result) => result,
-        };
-    } else {
-        //TODO ERROR you are not owner of this album
+    if !permissions::can_delete_album(&user, &result) {
+        return Err(HandlerError::PermissionDenied {
+            err_message: format!("Only owner can delete album {}", album_id.0)
+        });
     }
-    Ok(HttpResponse::new(StatusCode::OK))
+
+    // Move to the trash, the album is purged after the retention period
+    match db::trash_album(&client, album_id.0).await {
+        Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+        Ok(result) => result,
+    };
+
+    Ok(HttpResponse::build(StatusCode::OK).json(format!("Success delete album id={}", album_id.0)))
 }
 
 
@@ -227,7 +279,8 @@ pub async fn update_album_by_id(
         }
     };
   
-    let result = match db::get_album_by_id(&client, album_id.0).await {
+    let (result, member) = match db::get_album_with_role(&client, album_id.0, user.id).await {
+        Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
         Err(e) => {
             error!("Error occured get users albums: {}", e);
 
@@ -236,27 +289,156 @@ pub async fn update_album_by_id(
         Ok(item) => item,
     };
 
+    if !permissions::can_edit_album(&user, &result, member) {
+        return Err(HandlerError::PermissionDenied {
+            err_message: format!("You can not change album {}", album_id.0)
+        });
+    }
+
+    match db::update_album(&client, album_id.0, &data).await {
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+        Ok(num_updated) => num_updated,
+    };
+    Ok(HttpResponse::new(StatusCode::OK))
+}
 
-    if user.id == result.users_id || user.role == "admin" {
-        match db::update_album(&client, album_id.0, &data).await {
-            Err(e) => {
-                error!("Error occured: {}", e);
-                return Err(HandlerError::InternalError);
-            }
-            Ok(num_updated) => num_updated,
-        };
+// Width and height of a photo, used to validate annotation coordinates
+async fn photo_size(
+    client: &deadpool_postgres::Client,
+    storage: &dyn Storage,
+    photo_id: &i32,
+) -> Result<(i32, i32), HandlerError> {
+    let image_meta = match db::get_image_meta(client, photo_id).await {
+        Err(e) => {
+            error!("Error occured : {}", e);
+            return Err(HandlerError::BadClientData {
+                field: "Id of image not found in db".to_string()
+            });
+        }
+        Ok(item) => item,
+    };
+
+    if let (Some(width), Some(height)) = (image_meta.width, image_meta.height) {
+        return Ok((width, height));
+    }
+
+    // Images uploaded before dimensions were recorded are measured on first use
+    let key = storage::photo_key(image_meta.album_id, &image_meta.file_path);
+    let content = match storage.get(&key).await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Could not read {}: {}", key, e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+    let (width, height) = match image_utils::inspect_image(&content) {
+        Ok((_, dimensions)) => dimensions,
+        Err(e) => {
+            error!("Could not read dimensions of {}: {}", key, e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+    if let Err(e) = db::set_image_dimensions(client, &image_meta.id, width, height).await {
+        error!("Error occured : {}", e);
+    }
+    Ok((width, height))
+}
+
+// Labels the album of the photo allows for annotations
+async fn photo_tags(
+    client: &deadpool_postgres::Client,
+    photo_id: &i32,
+) -> Result<Vec<String>, HandlerError> {
+    match db::get_album_tags_of_photo(client, photo_id).await {
+        Err(e) => {
+            error!("Error occured : {}", e);
+            Err(HandlerError::BadClientData {
+                field: "Id of image not found in db".to_string()
+            })
+        }
+        Ok(tags) => Ok(tags),
+    }
+}
+
+fn validate_annotation(
+    annotation: &CreateAnnotation,
+    size: (i32, i32),
+    tags: &[String],
+) -> Result<(), HandlerError> {
+    if !tags.contains(&annotation.tag) {
+        return Err(HandlerError::BadClientData {
+            field: format!("tag: unknown label '{}', allowed labels are [{}]", annotation.tag, tags.join(", "))
+        });
+    }
+    match annotation.coordinates.validate(size.0, size.1) {
+        Ok(()) => Ok(()),
+        Err(err) => Err(HandlerError::BadClientData {
+            field: format!("coordinates: {}", err)
+        }),
+    }
+}
+
+fn check_can_tag(user: &User, album: &Album, member: Option<AlbumRole>) -> Result<(), HandlerError> {
+    if permissions::can_tag(user, album, member) {
+        Ok(())
     } else {
-        //TODO ERROR you are not owner of this album
+        Err(HandlerError::PermissionDenied {
+            err_message: format!("Not allowed to tag photos of album {}", album.id)
+        })
+    }
+}
+
+// Only the tagger holding the lock of a photo may change it. Returns the
+// album of the photo and the role of the tagger in it
+async fn check_photo_lock(
+    client: &deadpool_postgres::Client,
+    photo_id: &i32,
+    user: &User,
+    tagging: &Tagging,
+) -> Result<(Album, Option<AlbumRole>), HandlerError> {
+    // The membership could have ended since the lock was taken
+    let (album, member) = match db::get_album_of_photo(client, photo_id, user.id).await {
+        Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
+        Err(e) => {
+            error!("Error occured get album of photo: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+        Ok(item) => item,
+    };
+    check_can_tag(user, &album, member)?;
+    match db::get_photo_lock(client, photo_id, user.id, tagging.lock_lease_secs).await {
+        Err(e) => {
+            error!("Error occured : {}", e);
+            Err(HandlerError::BadClientData {
+                field: "Id of image not found in db".to_string()
+            })
+        }
+        Ok(PhotoLock::Held) => Ok((album, member)),
+        Ok(PhotoLock::HeldByOther) => Err(HandlerError::PermissionDenied {
+            err_message: format!("Image {} is locked by another user", photo_id)
+        }),
+        Ok(PhotoLock::Expired) => {
+            error!("Error occured : timeout");
+            Err(HandlerError::BadClientData {
+                field: "timeout".to_string()
+            })
+        }
     }
-    Ok(HttpResponse::new(StatusCode::OK))
 }
 
 // tag photo + set coordinates
 pub async fn tag_photo_by_id(
     pool: web::Data<Pool>,
+    storage: web::Data<Box<dyn Storage>>,
     data_id : web::Path<(i32,)>,
     data: web::Json<TagPhoto>,
+    tagging: web::Data<Tagging>,
+    id: Identity,
 ) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
   
   let client = match pool.get().await {
         Ok(item) => item,
@@ -266,8 +448,14 @@ pub async fn tag_photo_by_id(
         }
     };
 
-    
-     match db::tag_photo_by_id(client, &data_id.0, &data).await {
+    check_photo_lock(&client, &data_id.0, &user, &tagging).await?;
+    let size = photo_size(&client, storage.get_ref().as_ref(), &data_id.0).await?;
+    let tags = photo_tags(&client, &data_id.0).await?;
+    for annotation in &data.annotations {
+        validate_annotation(annotation, size, &tags)?;
+    }
+
+     match db::tag_photo_by_id(client, &data_id.0, user.id, tagging.lock_lease_secs, &data).await {
         Err(e) => {
             error!("Error occured : {}", e);
               return Err(HandlerError::InternalError);
@@ -285,12 +473,119 @@ pub async fn tag_photo_by_id(
  
 }
 
+// add a single annotation to a photo locked for tagging
+pub async fn add_annotation(
+    pool: web::Data<Pool>,
+    storage: web::Data<Box<dyn Storage>>,
+    data_id: web::Path<(i32,)>,
+    data: web::Json<CreateAnnotation>,
+    tagging: web::Data<Tagging>,
+    id: Identity,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+    let photo_id = data_id.0;
+
+    let mut client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    check_photo_lock(&client, &photo_id, &user, &tagging).await?;
+    let size = photo_size(&client, storage.get_ref().as_ref(), &photo_id).await?;
+    let tags = photo_tags(&client, &photo_id).await?;
+    validate_annotation(&data, size, &tags)?;
+
+    match db::create_annotation(&mut client, &photo_id, user.id, tagging.lock_lease_secs, &data).await {
+        Err(DBError::BadArgs { err }) => Err(HandlerError::BadClientData { field: err }),
+        Err(e) => {
+            error!("Error occured : {}", e);
+            Err(HandlerError::InternalError)
+        }
+        Ok(annotation) => Ok(HttpResponse::build(StatusCode::OK).json(annotation)),
+    }
+}
+
+pub async fn update_annotation(
+    pool: web::Data<Pool>,
+    storage: web::Data<Box<dyn Storage>>,
+    data_id: web::Path<(i32, i32)>,
+    data: web::Json<CreateAnnotation>,
+    tagging: web::Data<Tagging>,
+    id: Identity,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+    let (photo_id, annotation_id) = (data_id.0, data_id.1);
+
+    let mut client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    check_photo_lock(&client, &photo_id, &user, &tagging).await?;
+    let size = photo_size(&client, storage.get_ref().as_ref(), &photo_id).await?;
+    let tags = photo_tags(&client, &photo_id).await?;
+    validate_annotation(&data, size, &tags)?;
+
+    match db::update_annotation(&mut client, &photo_id, &annotation_id, user.id, tagging.lock_lease_secs, &data).await {
+        Err(DBError::BadArgs { err }) => Err(HandlerError::BadClientData { field: err }),
+        Err(e) => {
+            error!("Error occured : {}", e);
+            Err(HandlerError::InternalError)
+        }
+        Ok(Some(annotation)) => Ok(HttpResponse::build(StatusCode::OK).json(annotation)),
+        Ok(None) => Err(HandlerError::BadClientData {
+            field: format!("Annotation {} not found on image {}", annotation_id, photo_id)
+        }),
+    }
+}
+
+pub async fn delete_annotation(
+    pool: web::Data<Pool>,
+    data_id: web::Path<(i32, i32)>,
+    tagging: web::Data<Tagging>,
+    id: Identity,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+    let (photo_id, annotation_id) = (data_id.0, data_id.1);
+
+    let mut client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    check_photo_lock(&client, &photo_id, &user, &tagging).await?;
+
+    match db::delete_annotation(&mut client, &photo_id, &annotation_id, user.id, tagging.lock_lease_secs).await {
+        Err(DBError::BadArgs { err }) => Err(HandlerError::BadClientData { field: err }),
+        Err(e) => {
+            error!("Error occured : {}", e);
+            Err(HandlerError::InternalError)
+        }
+        Ok(true) => Ok(HttpResponse::new(StatusCode::OK)),
+        Ok(false) => Err(HandlerError::BadClientData {
+            field: format!("Annotation {} not found on image {}", annotation_id, photo_id)
+        }),
+    }
+}
+
 // verify_photo
 pub async fn verify_photo_by_id(
     pool: web::Data<Pool>,
     data_id : web::Path<(i32,)>,
     data: web::Json<VerifyPhoto>,
+    tagging: web::Data<Tagging>,
+    id: Identity,
 ) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
   
   let client = match pool.get().await {
         Ok(item) => item,
@@ -300,8 +595,14 @@ pub async fn verify_photo_by_id(
         }
     };
 
+    let (album, member) = check_photo_lock(&client, &data_id.0, &user, &tagging).await?;
+    if !permissions::can_verify(&user, &album, member) {
+        return Err(HandlerError::PermissionDenied {
+            err_message: format!("Not allowed to verify photos of album {}", album.id)
+        });
+    }
   
-     match db::verify_photo_by_id(client, &data_id.0, data.verified).await {
+     match db::verify_photo_by_id(client, &data_id.0, user.id, tagging.lock_lease_secs, data.verified).await {
         Err(e) => {
             error!("Error occured : {}", e);
               return Err(HandlerError::InternalError);
@@ -319,11 +620,14 @@ pub async fn verify_photo_by_id(
 
 }
 
-// get next 20 photos for tagging 
+// get next batch of photos for tagging 
 pub async fn get_photos_for_tagging(
     pool: web::Data<Pool>,
-    data : web::Path<(i32, )>
+    data : web::Path<(i32, )>,
+    tagging: web::Data<Tagging>,
+    id: Identity,
 ) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
   
   let client = match pool.get().await {
         Ok(item) => item,
@@ -333,9 +637,19 @@ pub async fn get_photos_for_tagging(
         }
     };
 
+    let (album, member) = match db::get_album_with_role(&client, data.0, user.id).await {
+        Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
+        Err(e) => {
+            error!("Error occured get album: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+        Ok(item) => item,
+    };
+    check_can_tag(&user, &album, member)?;
+
     
     
-  let result = match db::get_photos_for_tagging(client, &data.0).await {
+  let result = match db::get_photos_for_tagging(client, &data.0, user.id, tagging.lock_lease_secs, tagging.batch_size).await {
         Err(e) => {
             error!("Error occured : {}", e);
               return Err(HandlerError::InternalError);
@@ -346,4 +660,1037 @@ pub async fn get_photos_for_tagging(
    Ok(HttpResponse::build(StatusCode::OK).json(result)) 
   
     
-}
\ No newline at end of file
+}
+
+
+// heartbeat of a tagger, extends the lock of a photo the user holds
+pub async fn renew_photo_lock(
+    pool: web::Data<Pool>,
+    data_id: web::Path<(i32,)>,
+    tagging: web::Data<Tagging>,
+    id: Identity,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    check_photo_lock(&client, &data_id.0, &user, &tagging).await?;
+
+    match db::renew_photo_lock(&client, &data_id.0, user.id, tagging.lock_lease_secs).await {
+        Err(e) => {
+            error!("Error occured : {}", e);
+            Err(HandlerError::InternalError)
+        }
+        Ok(Some(lease)) => Ok(HttpResponse::build(StatusCode::OK).json(lease)),
+        Ok(None) => Err(HandlerError::BadClientData {
+            field: "timeout".to_string()
+        }),
+    }
+}
+
+// hand a photo back to the tagging queue before the lock runs out
+pub async fn release_photo_lock(
+    pool: web::Data<Pool>,
+    data_id: web::Path<(i32,)>,
+    id: Identity,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    match db::release_photo_lock(&client, &data_id.0, user.id).await {
+        Err(e) => {
+            error!("Error occured : {}", e);
+            Err(HandlerError::InternalError)
+        }
+        Ok(true) => Ok(HttpResponse::new(StatusCode::OK)),
+        Ok(false) => Err(HandlerError::PermissionDenied {
+            err_message: format!("Image {} is not locked by you", data_id.0)
+        }),
+    }
+}
+
+// Album must exist and the user must be allowed to edit it
+async fn get_own_album(
+    client: &deadpool_postgres::Client,
+    album_id: i32,
+    user: &User,
+) -> Result<Album, HandlerError> {
+    let (album, member) = match db::get_album_with_role(client, album_id, user.id).await {
+        Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
+        Err(e) => {
+            error!("Error occured get album: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+        Ok(item) => item,
+    };
+
+    if !permissions::can_edit_album(user, &album, member) {
+        return Err(HandlerError::PermissionDenied {
+            err_message: format!("Only owner can manage album {}", album_id)
+        });
+    }
+    Ok(album)
+}
+
+fn tag_change_response(result: Result<Album, DBError>) -> Result<HttpResponse, HandlerError> {
+    match result {
+        Ok(album) => Ok(HttpResponse::build(StatusCode::OK).json(album)),
+        Err(DBError::BadArgs { err }) => Err(HandlerError::BadClientData { field: err }),
+        Err(e) => {
+            error!("Error occured : {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+// add a label to the album vocabulary
+pub async fn add_album_tag(
+    pool: web::Data<Pool>,
+    album_id: web::Path<(i32,)>,
+    id: Identity,
+    data: web::Json<Tag>,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    let mut client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    get_own_album(&client, album_id.0, &user).await?;
+    tag_change_response(db::add_album_tag(&mut client, album_id.0, &data.tag).await)
+}
+
+// rename a label, existing annotations are relabeled
+pub async fn rename_album_tag(
+    pool: web::Data<Pool>,
+    album_id: web::Path<(i32,)>,
+    id: Identity,
+    data: web::Json<RenameTag>,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    let mut client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    get_own_album(&client, album_id.0, &user).await?;
+    tag_change_response(db::rename_album_tag(&mut client, album_id.0, &data.from, &data.to).await)
+}
+
+// merge several labels into one, existing annotations are relabeled
+pub async fn merge_album_tags(
+    pool: web::Data<Pool>,
+    album_id: web::Path<(i32,)>,
+    id: Identity,
+    data: web::Json<MergeTags>,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    let mut client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    get_own_album(&client, album_id.0, &user).await?;
+    tag_change_response(db::merge_album_tags(&mut client, album_id.0, &data.from, &data.into).await)
+}
+
+// retire a label, annotations using it are deleted
+pub async fn retire_album_tag(
+    pool: web::Data<Pool>,
+    album_id: web::Path<(i32,)>,
+    id: Identity,
+    data: web::Json<Tag>,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    let mut client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    get_own_album(&client, album_id.0, &user).await?;
+    tag_change_response(db::retire_album_tag(&mut client, album_id.0, &data.tag).await)
+}
+
+// export the verified photos of an album as COCO, Pascal VOC or YOLO dataset
+pub async fn export_album(
+    pool: web::Data<Pool>,
+    album_id: web::Path<(i32,)>,
+    id: Identity,
+    query: web::Query<ExportQuery>,
+    storage: web::Data<Box<dyn Storage>>,
+    req: HttpRequest,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    let album = get_own_album(&client, album_id.0, &user).await?;
+
+    let image_metas = match db::get_verified_image_metas(&client, &album.id).await {
+        Err(e) => {
+            error!("Error occured get verified images: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+        Ok(item) => item,
+    };
+    let photo_ids: Vec<i32> = image_metas.iter().map(|meta| meta.id).collect();
+    let annotations = match db::get_annotations_of_photos(&client, &photo_ids).await {
+        Err(e) => {
+            error!("Error occured get annotations: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+        Ok(item) => item,
+    };
+    let mut annotations_of_photo: HashMap<i32, Vec<_>> = HashMap::new();
+    for annotation in annotations {
+        annotations_of_photo.entry(annotation.image_id).or_default().push(annotation);
+    }
+
+    let mut images = Vec::new();
+    for meta in image_metas {
+        let (width, height) = match (meta.width, meta.height) {
+            (Some(width), Some(height)) => (width, height),
+            _ => photo_size(&client, storage.get_ref().as_ref(), &meta.id).await?,
+        };
+        images.push(ExportImage {
+            id: meta.id,
+            annotations: annotations_of_photo.remove(&meta.id).unwrap_or_default(),
+            file_path: meta.file_path,
+            width,
+            height,
+        });
+    }
+
+    let label_files = export::label_files(query.format, &album, &images);
+    let image_paths: Vec<String> = if query.images {
+        images.iter().map(|image| image.file_path.clone()).collect()
+    } else {
+        Vec::new()
+    };
+
+    // The zip is spooled to a temporary file and served from there
+    let archive_path = std::env::temp_dir().join(format!("tagify_export_{}", rand::random::<u64>()));
+    let archive = write_export(storage.get_ref().as_ref(), &archive_path, album.id, label_files, image_paths).await;
+    // Still readable through the open file
+    let _ = std::fs::remove_file(&archive_path);
+    let archive = archive?;
+    info!("Exported {} verified images of album {}", images.len(), album.id);
+
+    let file_name = format!("album_{}_{}.zip", album.id, query.format.name());
+    let file = match NamedFile::from_file(archive, &file_name) {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured serving export of album {}: {}", album.id, e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+    // Every export is a new file, caching headers would be meaningless
+    match file.use_etag(false).use_last_modified(false).into_response(&req) {
+        Ok(item) => Ok(item),
+        Err(e) => {
+            error!("Error occured serving export of album {}: {}", album.id, e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+async fn write_export(
+    storage: &dyn Storage,
+    archive_path: &std::path::Path,
+    album_id: i32,
+    label_files: Vec<(String, Vec<u8>)>,
+    image_paths: Vec<String>,
+) -> Result<std::fs::File, HandlerError> {
+    let path = archive_path.to_path_buf();
+    let mut zip = match web::block(move || export::create_zip(&path, label_files)).await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured writing export of album {}: {}", album_id, e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    for file_path in image_paths {
+        let key = storage::photo_key(album_id, &file_path);
+        let content = match storage.get(&key).await {
+            Ok(item) => item,
+            Err(e) => {
+                error!("Error occured reading {} for export: {}", key, e);
+                return Err(HandlerError::InternalError);
+            }
+        };
+        let name = format!("images/{}", file_path);
+        zip = match web::block(move || export::add_image(&mut zip, &name, &content).map(|_| zip)).await {
+            Ok(item) => item,
+            Err(e) => {
+                error!("Error occured writing export of album {}: {}", album_id, e);
+                return Err(HandlerError::InternalError);
+            }
+        };
+    }
+
+    match web::block(move || zip.finish()).await {
+        Ok(item) => Ok(item),
+        Err(e) => {
+            error!("Error occured writing export of album {}: {}", album_id, e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+// import annotation files (COCO, Pascal VOC, YOLO or CSV) into the unverified
+// photos of an album, YOLO class ids are resolved with an uploaded classes.txt
+// or otherwise with the order of the album tags
+// Annotation files are parsed in memory
+const MAX_IMPORT_FILE_BYTES: usize = 16 << 20;
+const MAX_IMPORT_BYTES: usize = 64 << 20;
+
+pub async fn import_annotations(
+    pool: web::Data<Pool>,
+    album_id: web::Path<(i32,)>,
+    id: Identity,
+    mut payload: Multipart,
+    storage: web::Data<Box<dyn Storage>>,
+    tagging: web::Data<Tagging>,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    let mut client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    let album = get_own_album(&client, album_id.0, &user).await?;
+
+    let mut files = Vec::new();
+    let mut total_bytes = 0;
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let file_name = match field.content_disposition().and_then(|cd| cd.get_filename().map(String::from)) {
+            Some(item) => item,
+            None => {
+                info!("Field without filename in payload. Skip.");
+                continue;
+            }
+        };
+        let max_bytes = MAX_IMPORT_FILE_BYTES.min(MAX_IMPORT_BYTES - total_bytes);
+        let content = match handlers::read_field(&mut field, max_bytes).await? {
+            Some(item) => item,
+            None if max_bytes < MAX_IMPORT_FILE_BYTES => {
+                return Err(HandlerError::BadClientData {
+                    field: format!("Annotation files are larger than {} MiB together", MAX_IMPORT_BYTES >> 20)
+                });
+            }
+            None => {
+                return Err(HandlerError::BadClientData {
+                    field: format!("{} is larger than {} MiB", file_name, MAX_IMPORT_FILE_BYTES >> 20)
+                });
+            }
+        };
+        total_bytes += content.len();
+        files.push((file_name, content));
+    }
+
+    let classes: Vec<String> = match files.iter().position(|(name, _)| name == "classes.txt") {
+        Some(i) => String::from_utf8_lossy(&files.remove(i).1)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect(),
+        None => album.tags.clone(),
+    };
+
+    let photos = match db::get_image_metas_of_album(&client, &album.id).await {
+        Err(e) => {
+            error!("Error occured get images of album: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+        Ok(item) => item,
+    };
+    let verified: HashSet<i32> = match db::get_verified_image_metas(&client, &album.id).await {
+        Err(e) => {
+            error!("Error occured get verified images: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+        Ok(item) => item.iter().map(|meta| meta.id).collect(),
+    };
+
+    let mut reports = Vec::new();
+    let mut annotations_of_photo: HashMap<i32, Vec<CreateAnnotation>> = HashMap::new();
+    for (file_name, content) in files {
+        let mut report = ImportFileReport {
+            file: file_name.clone(),
+            format: None,
+            matched: 0,
+            skipped: Vec::new(),
+            invalid: Vec::new(),
+        };
+        let format = match ImportFormat::of_file(&file_name) {
+            Some(format) => format,
+            None => {
+                report.invalid.push("unsupported file type, expected .json, .xml, .txt or .csv".to_string());
+                reports.push(report);
+                continue;
+            }
+        };
+        report.format = Some(format.name().to_string());
+        let parsed = match import::parse(format, &file_name, &content, &classes) {
+            Ok(item) => item,
+            Err(e) => {
+                report.invalid.push(format!("could not read file: {}", e));
+                reports.push(report);
+                continue;
+            }
+        };
+        report.invalid = parsed.invalid;
+
+        for entry in parsed.entries {
+            let photo = match import::find_photo(&photos, &entry.image) {
+                Some(photo) => photo,
+                None => {
+                    report.skipped.push(format!("{}: no such photo in album", entry.image));
+                    continue;
+                }
+            };
+            if verified.contains(&photo.id) {
+                report.skipped.push(format!("{}: photo is already verified", entry.image));
+                continue;
+            }
+            let size = match (photo.width, photo.height) {
+                (Some(width), Some(height)) => (width, height),
+                _ => photo_size(&client, storage.get_ref().as_ref(), &photo.id).await?,
+            };
+            let annotation = CreateAnnotation {
+                tag: entry.tag,
+                coordinates: entry.coordinates,
+            };
+            if let Err(HandlerError::BadClientData { field }) = validate_annotation(&annotation, size, &album.tags) {
+                report.invalid.push(format!("{}: {}", entry.image, field));
+                continue;
+            }
+            annotations_of_photo.entry(photo.id).or_default().push(annotation);
+            report.matched += 1;
+        }
+        reports.push(report);
+    }
+
+    let mut photos_updated = 0;
+    let mut photos_locked = Vec::new();
+    for (photo_id, annotations) in annotations_of_photo {
+        match db::import_annotations(&mut client, &photo_id, user.id, tagging.lock_lease_secs, &annotations).await {
+            Ok(PhotoImport::Imported) => photos_updated += 1,
+            Ok(PhotoImport::Verified) => info!("Image {} was verified during import. Skip.", photo_id),
+            Ok(PhotoImport::Locked) => photos_locked.push(photo_id),
+            Err(e) => {
+                error!("Error occured import annotations of image {}: {}", photo_id, e);
+                return Err(HandlerError::InternalError);
+            }
+        }
+    }
+    info!("Imported annotations for {} images of album {}", photos_updated, album.id);
+
+    Ok(HttpResponse::build(StatusCode::OK).json(ImportReport {
+        files: reports,
+        photos_updated,
+        photos_locked,
+    }))
+}
+
+// pairs of photos in the album with identical content or similar perceptual
+// hashes, pairs dismissed before are left out
+pub async fn get_duplicates(
+    pool: web::Data<Pool>,
+    album_id: web::Path<(i32,)>,
+    id: Identity,
+    query: web::Query<DuplicatesQuery>,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    let album = get_own_album(&client, album_id.0, &user).await?;
+
+    let photos = match db::get_image_metas_of_album(&client, &album.id).await {
+        Err(e) => {
+            error!("Error occured get images of album: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+        Ok(item) => item,
+    };
+    let dismissed: HashSet<(i32, i32)> = match db::get_duplicate_dismissals(&client, &album.id).await {
+        Err(e) => {
+            error!("Error occured get duplicate dismissals: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+        Ok(item) => item.into_iter().collect(),
+    };
+
+    // Photos are ordered by id, so the first of a pair has the smaller id
+    let hashed: Vec<usize> = (0..photos.len()).filter(|i| photos[*i].perceptual_hash.is_some()).collect();
+    let hashes: Vec<i64> = hashed.iter().filter_map(|i| photos[*i].perceptual_hash).collect();
+    let mut candidates: BTreeSet<(usize, usize)> = image_utils::similar_hashes(&hashes, query.max_distance)
+        .into_iter()
+        .map(|(i, j, _)| (hashed[i], hashed[j]))
+        .collect();
+    let mut with_checksum: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, photo) in photos.iter().enumerate() {
+        if let Some(checksum) = &photo.checksum {
+            with_checksum.entry(checksum).or_default().push(i);
+        }
+    }
+    for identical in with_checksum.values() {
+        for (k, i) in identical.iter().enumerate() {
+            candidates.extend(identical[k + 1..].iter().map(|j| (*i, *j)));
+        }
+    }
+
+    let mut pairs = Vec::new();
+    for (i, j) in candidates {
+        let (photo, other) = (&photos[i], &photos[j]);
+        if dismissed.contains(&(photo.id, other.id)) {
+            continue;
+        }
+        let distance = match (photo.perceptual_hash, other.perceptual_hash) {
+            (Some(a), Some(b)) => Some(image_utils::hash_distance(a, b)),
+            _ => None,
+        };
+        pairs.push(DuplicatePair {
+            photo: photo.clone(),
+            other: other.clone(),
+            identical: photo.checksum.is_some() && photo.checksum == other.checksum,
+            distance,
+        });
+    }
+
+    Ok(HttpResponse::build(StatusCode::OK).json(pairs))
+}
+
+// keep both photos of a reported pair or delete the other one
+pub async fn resolve_duplicate(
+    pool: web::Data<Pool>,
+    album_id: web::Path<(i32,)>,
+    id: Identity,
+    data: web::Json<ResolveDuplicate>,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    let album = get_own_album(&client, album_id.0, &user).await?;
+
+    if data.keep == data.other {
+        return Err(HandlerError::BadClientData {
+            field: "keep and other must be different photos".to_string()
+        });
+    }
+    let mut photos = Vec::new();
+    for photo_id in &[data.keep, data.other] {
+        match db::get_image_meta(&client, photo_id).await {
+            Ok(photo) if photo.album_id == album.id => photos.push(photo),
+            _ => {
+                return Err(HandlerError::BadClientData {
+                    field: format!("Photo {} not found in album {}", photo_id, album.id)
+                });
+            }
+        }
+    }
+
+    match data.action {
+        DuplicateAction::Dismiss => {
+            if let Err(e) = db::dismiss_duplicate(&client, &data.keep, &data.other).await {
+                error!("Error occured dismiss duplicate: {}", e);
+                return Err(HandlerError::InternalError);
+            }
+            Ok(HttpResponse::build(StatusCode::OK).json(format!(
+                "Photos {} and {} are no longer reported as duplicates", data.keep, data.other
+            )))
+        }
+        DuplicateAction::DeleteOther => {
+            match db::trash_image_meta(&client, &album.id, &data.other).await {
+                Ok(_) => {}
+                Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
+                Err(e) => {
+                    error!("Error occured delete image {}: {}", data.other, e);
+                    return Err(HandlerError::InternalError);
+                }
+            }
+            info!("Deleted photo {} as duplicate of {} in album {}", data.other, data.keep, album.id);
+            Ok(HttpResponse::build(StatusCode::OK).json(format!("Success delete image id={}", data.other)))
+        }
+    }
+}
+
+// albums and photos of the user in the trash
+pub async fn get_own_trash(
+    pool: web::Data<Pool>,
+    trash: web::Data<Trash>,
+    id: Identity,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    match db::get_trash(&client, Some(user.id), trash.retention_days).await {
+        Ok(content) => Ok(HttpResponse::build(StatusCode::OK).json(content)),
+        Err(e) => {
+            error!("Error occured get trash: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+// take an album out of the trash, with its photos
+pub async fn restore_album(
+    pool: web::Data<Pool>,
+    album_id: web::Path<(i32,)>,
+    id: Identity,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    let album = match db::get_trashed_album(&client, album_id.0).await {
+        Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
+        Err(e) => {
+            error!("Error occured get album: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+        Ok(item) => item,
+    };
+
+    if !permissions::can_delete_album(&user, &album) {
+        return Err(HandlerError::PermissionDenied {
+            err_message: format!("Only owner can restore album {}", album_id.0)
+        });
+    }
+
+    match db::restore_album(&client, album_id.0).await {
+        Ok(album) => {
+            info!("Restored album {} from the trash", album.id);
+            Ok(HttpResponse::build(StatusCode::OK).json(album))
+        }
+        Err(DBError::NotFound { err }) => Err(HandlerError::NotFound { err_message: err }),
+        Err(e) => {
+            error!("Error occured restore album: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+// take a photo out of the trash, its album has to be restored first
+pub async fn restore_photo(
+    pool: web::Data<Pool>,
+    parameters: web::Path<(i32, i32)>,
+    id: Identity,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+    let album_id = parameters.0;
+    let image_id = parameters.1;
+
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    let (album, member) = match db::get_album_with_role(&client, album_id, user.id).await {
+        Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
+        Err(e) => {
+            error!("Error occured get album: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+        Ok(item) => item,
+    };
+
+    if !permissions::can_edit_album(&user, &album, member) {
+        return Err(HandlerError::PermissionDenied {
+            err_message: format!("Only owners can restore images of album {}", album_id)
+        });
+    }
+
+    match db::restore_image_meta(&client, &album_id, &image_id).await {
+        Ok(()) => {
+            info!("Restored image id={} of album {} from the trash", image_id, album_id);
+            Ok(HttpResponse::build(StatusCode::OK).json(format!("Success restore image id={}", image_id)))
+        }
+        Err(DBError::NotFound { err }) => Err(HandlerError::NotFound { err_message: err }),
+        Err(e) => {
+            error!("Error occured restore image {}: {}", image_id, e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+// users invited to the album, only visible to its owners
+pub async fn get_album_members(
+    pool: web::Data<Pool>,
+    album_id: web::Path<(i32,)>,
+    id: Identity,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    get_own_album(&client, album_id.0, &user).await?;
+    match db::get_album_members(&client, album_id.0).await {
+        Ok(members) => Ok(HttpResponse::build(StatusCode::OK).json(members)),
+        Err(e) => {
+            error!("Error occured get album members: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+// invite a user to the album as tagger, reviewer or co-owner
+pub async fn add_album_member(
+    pool: web::Data<Pool>,
+    album_id: web::Path<(i32,)>,
+    id: Identity,
+    data: web::Json<AddAlbumMember>,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    let album = get_own_album(&client, album_id.0, &user).await?;
+    let invited = match db::get_user_by_name(&client, &data.username).await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured get user {}: {}", data.username, e);
+            return Err(HandlerError::NotFound {
+                err_message: format!("User {} not found", data.username)
+            });
+        }
+    };
+    if invited.id == album.users_id {
+        return Err(HandlerError::BadClientData {
+            field: format!("{} owns album {}", data.username, album.id)
+        });
+    }
+    if invited.role == Role::Viewer {
+        return Err(HandlerError::BadClientData {
+            field: format!("{} is a viewer and can not work on albums", data.username)
+        });
+    }
+
+    match db::add_album_member(&client, album.id, invited.id, data.role, user.id).await {
+        Ok(member) => {
+            info!("User {} invited user {} to album {}", user.id, invited.id, album.id);
+            Ok(HttpResponse::build(StatusCode::CREATED).json(member))
+        }
+        Err(DBError::BadArgs { err }) => Err(HandlerError::BadClientData { field: err }),
+        Err(e) => {
+            error!("Error occured add album member: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+// change the role of a member
+pub async fn update_album_member(
+    pool: web::Data<Pool>,
+    parameters: web::Path<(i32, i32)>,
+    id: Identity,
+    data: web::Json<UpdateAlbumMember>,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+    let album_id = parameters.0;
+    let member_id = parameters.1;
+
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    get_own_album(&client, album_id, &user).await?;
+    match db::update_album_member(&client, album_id, member_id, data.role).await {
+        Ok(member) => Ok(HttpResponse::build(StatusCode::OK).json(member)),
+        Err(DBError::NotFound { err }) => Err(HandlerError::NotFound { err_message: err }),
+        Err(e) => {
+            error!("Error occured update album member: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+// remove a member, members can also leave the album themselves
+pub async fn delete_album_member(
+    pool: web::Data<Pool>,
+    parameters: web::Path<(i32, i32)>,
+    id: Identity,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+    let album_id = parameters.0;
+    let member_id = parameters.1;
+
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    if member_id != user.id {
+        get_own_album(&client, album_id, &user).await?;
+    }
+    match db::delete_album_member(&client, album_id, member_id).await {
+        Ok(()) => {
+            info!("User {} removed user {} from album {}", user.id, member_id, album_id);
+            Ok(HttpResponse::build(StatusCode::OK).json(format!("Success remove member id={}", member_id)))
+        }
+        Err(DBError::NotFound { err }) => Err(HandlerError::NotFound { err_message: err }),
+        Err(e) => {
+            error!("Error occured delete album member: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+// albums of other users the user was invited to, with the role
+pub async fn get_assigned_albums(
+    pool: web::Data<Pool>,
+    id: Identity,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    match db::get_assigned_albums(&client, user.id).await {
+        Ok(albums) => Ok(HttpResponse::build(StatusCode::OK).json(albums)),
+        Err(e) => {
+            error!("Error occured get assigned albums: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+// links that show the album without login, only visible to its owners
+pub async fn get_share_links(
+    pool: web::Data<Pool>,
+    album_id: web::Path<(i32,)>,
+    id: Identity,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    get_own_album(&client, album_id.0, &user).await?;
+    match db::get_share_links(&client, album_id.0).await {
+        Ok(links) => Ok(HttpResponse::build(StatusCode::OK).json(links)),
+        Err(e) => {
+            error!("Error occured get share links: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+pub async fn create_share_link(
+    pool: web::Data<Pool>,
+    album_id: web::Path<(i32,)>,
+    id: Identity,
+    data: web::Json<CreateShareLink>,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    if data.expires_in_days < 1 || data.expires_in_days > 365 {
+        return Err(HandlerError::BadClientData {
+            field: "expires_in_days must be between 1 and 365".to_string()
+        });
+    }
+
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    get_own_album(&client, album_id.0, &user).await?;
+    let (token, token_hash) = new_share_token();
+    match db::create_share_link(&client, album_id.0, user.id, &token_hash, data.expires_in_days).await {
+        Ok(share_link) => {
+            info!("User {} shared album {} until {}", user.id, album_id.0, share_link.expires_at);
+            Ok(HttpResponse::build(StatusCode::CREATED).json(CreatedShareLink { token, share_link }))
+        }
+        Err(e) => {
+            error!("Error occured create share link: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+pub async fn delete_share_link(
+    pool: web::Data<Pool>,
+    parameters: web::Path<(i32, i32)>,
+    id: Identity,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+    let album_id = parameters.0;
+    let link_id = parameters.1;
+
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    get_own_album(&client, album_id, &user).await?;
+    match db::delete_share_link(&client, album_id, link_id).await {
+        Ok(()) => Ok(HttpResponse::build(StatusCode::OK).json(format!("Success delete share link id={}", link_id))),
+        Err(DBError::NotFound { err }) => Err(HandlerError::NotFound { err_message: err }),
+        Err(e) => {
+            error!("Error occured delete share link: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+// Album of a share link, revoked and expired links are reported as missing
+pub async fn get_album_of_share_link(
+    client: &deadpool_postgres::Client,
+    token: &str,
+) -> Result<Album, HandlerError> {
+    match db::get_shared_album(client, &token_hash(token)).await {
+        Ok(album) => Ok(album),
+        Err(DBError::NotFound { err }) => Err(HandlerError::NotFound { err_message: err }),
+        Err(e) => {
+            error!("Error occured get shared album: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+// album of a share link, no login needed
+pub async fn get_shared_album(
+    pool: web::Data<Pool>,
+    token: web::Path<(String,)>,
+) -> Result<HttpResponse, HandlerError> {
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    let album = get_album_of_share_link(&client, &token.0).await?;
+    Ok(HttpResponse::build(StatusCode::OK).json(album))
+}
+
+// get 20 next photos of the album of a share link (start at 20 * index)
+pub async fn get_shared_photos(
+    pool: web::Data<Pool>,
+    parameters: web::Path<(String, i32)>,
+) -> Result<HttpResponse, HandlerError> {
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    let album = get_album_of_share_link(&client, &parameters.0).await?;
+    match db::get_photos_from_album(client, &album.id, &parameters.1).await {
+        Ok(photos) => Ok(HttpResponse::build(StatusCode::OK).json(photos)),
+        Err(DBError::BadArgs { err }) => Err(HandlerError::BadClientData { field: err }),
+        Err(e) => {
+            error!("Error occured : {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
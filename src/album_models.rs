@@ -1,8 +1,11 @@
+use crate::user_models::ImageMeta;
+
+use postgres_types::{FromSql, ToSql};
 use serde::{Deserialize, Serialize};
 use tokio_pg_mapper_derive::PostgresMapper;
 
 use chrono::offset::Utc;
-use chrono::{DateTime};
+use chrono::{DateTime, NaiveDateTime};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PostgresMapper)]
 #[pg_mapper(table = "albums")]
@@ -11,22 +14,58 @@ pub struct Album {
     pub title: String,
     pub description: String,
     pub tags: Vec<String>,
+    // Photos outside the trash, maintained by triggers on image_metas
     pub image_number: i32,
     pub tagged_number: i32,
+    pub verified_number: i32,
     pub users_id: i32,
     pub first_photo: String,
+    pub strip_metadata: bool,
+    pub visibility: Visibility,
+}
+
+// Who can see the album and its photos, see permissions.rs
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSql, FromSql)]
+#[serde(rename_all = "lowercase")]
+#[postgres(name = "album_visibility")]
+pub enum Visibility {
+    #[postgres(name = "private")]
+    Private,
+    #[postgres(name = "internal")]
+    Internal,
+    #[postgres(name = "public")]
+    Public,
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Visibility::Private
+    }
+}
+
+fn default_true() -> bool {
+    true
 }
 
+// strip_metadata removes EXIF data like the GPS location from uploaded photos
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CreateAlbum {
     pub title: String,
     pub description: String,
     pub tags: Vec<String>,
+    #[serde(default = "default_true")]
+    pub strip_metadata: bool,
+    #[serde(default)]
+    pub visibility: Visibility,
 }
+// strip_metadata and visibility are left unchanged if missing, strip_metadata
+// applies to photos uploaded afterwards
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct UpdateAlbum {
     pub title: String,
     pub description: String,
+    pub strip_metadata: Option<bool>,
+    pub visibility: Option<Visibility>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -40,6 +79,185 @@ pub struct Tag {
     pub tag: String,
 }
 
+// Renames a label of the album and of all its annotations
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RenameTag {
+    pub from: String,
+    pub to: String,
+}
+
+// Relabels all annotations using one of `from` with `into`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MergeTags {
+    pub from: Vec<String>,
+    pub into: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Coco,
+    Voc,
+    Yolo,
+}
+
+impl ExportFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            ExportFormat::Coco => "coco",
+            ExportFormat::Voc => "voc",
+            ExportFormat::Yolo => "yolo",
+        }
+    }
+}
+
+// e.g. ?format=yolo&images=true, without images only the label files are exported
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportQuery {
+    pub format: ExportFormat,
+    #[serde(default)]
+    pub images: bool,
+}
+
+// Outcome of one uploaded annotation file, skipped entries refer to photos
+// that are not in the album or already verified
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportFileReport {
+    pub file: String,
+    pub format: Option<String>,
+    pub matched: usize,
+    pub skipped: Vec<String>,
+    pub invalid: Vec<String>,
+}
+
+// Photos a tagger holds at the time of the import keep their annotations
+// and are listed in photos_locked
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub files: Vec<ImportFileReport>,
+    pub photos_updated: usize,
+    pub photos_locked: Vec<i32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhotoImport {
+    Imported,
+    Verified,
+    Locked,
+}
+
+fn default_max_distance() -> u32 {
+    6
+}
+
+// e.g. ?max_distance=4, photos whose perceptual hashes differ in at most
+// max_distance of 64 bits are reported as near-duplicates
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicatesQuery {
+    #[serde(default = "default_max_distance")]
+    pub max_distance: u32,
+}
+
+// Two photos of an album that look alike, identical if the content is the same.
+// `photo` has the smaller id
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicatePair {
+    pub photo: ImageMeta,
+    pub other: ImageMeta,
+    pub identical: bool,
+    pub distance: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateAction {
+    // Keep both photos and stop reporting the pair
+    Dismiss,
+    DeleteOther,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolveDuplicate {
+    pub keep: i32,
+    pub other: i32,
+    pub action: DuplicateAction,
+}
+
+// Role of an invited user in an album, see permissions.rs
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSql, FromSql)]
+#[serde(rename_all = "snake_case")]
+#[postgres(name = "album_role")]
+pub enum AlbumRole {
+    // Tags photos
+    #[postgres(name = "tagger")]
+    Tagger,
+    // Tags and verifies photos
+    #[postgres(name = "reviewer")]
+    Reviewer,
+    // Manages the album like its owner, but can not delete it
+    #[postgres(name = "co_owner")]
+    CoOwner,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PostgresMapper)]
+#[pg_mapper(table = "album_members")]
+pub struct AlbumMember {
+    pub users_id: i32,
+    pub username: String,
+    pub nickname: String,
+    pub role: AlbumRole,
+    pub invited_by: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddAlbumMember {
+    pub username: String,
+    pub role: AlbumRole,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateAlbumMember {
+    pub role: AlbumRole,
+}
+
+// Album of another user the user was invited to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssignedAlbum {
+    #[serde(flatten)]
+    pub album: Album,
+    pub role: AlbumRole,
+}
+
+// Read access to an album without login until expires_at
+#[derive(Debug, Clone, Serialize, Deserialize, PostgresMapper)]
+#[pg_mapper(table = "album_share_links")]
+pub struct ShareLink {
+    pub id: i32,
+    pub album_id: i32,
+    pub created_by: Option<i32>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+fn default_share_days() -> i32 {
+    7
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateShareLink {
+    #[serde(default = "default_share_days")]
+    pub expires_in_days: i32,
+}
+
+// The token is only shown once, on creation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatedShareLink {
+    pub token: String,
+    #[serde(flatten)]
+    pub share_link: ShareLink,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PostgresMapper)]
 #[pg_mapper(table = "albums")]
 pub struct AlbumPreview {
@@ -54,17 +272,151 @@ pub struct AlbumsPreview {
     pub  albums: Vec<AlbumPreview> ,
 }
 
+// Albums and photos in the trash are purged at purge_at
+#[derive(Debug, Clone, Serialize, Deserialize, PostgresMapper)]
+#[pg_mapper(table = "albums")]
+pub struct TrashedAlbum {
+    pub id: i32,
+    pub title: String,
+    pub description: String,
+    pub users_id: i32,
+    pub deleted_at: NaiveDateTime,
+    pub purge_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PostgresMapper)]
+#[pg_mapper(table = "image_metas")]
+pub struct TrashedPhoto {
+    pub id: i32,
+    pub album_id: i32,
+    pub file_path: String,
+    pub original_filename: Option<String>,
+    pub deleted_at: NaiveDateTime,
+    pub purge_at: NaiveDateTime,
+}
+
+// Photos of albums in the trash are only listed with their album
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashContent {
+    pub albums: Vec<TrashedAlbum>,
+    pub photos: Vec<TrashedPhoto>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PostgresMapper)]
 #[pg_mapper(table = "image_metas")]
 pub struct PhotoPreview {
     pub id: i32,
-    pub file_path: String
+    pub file_path: String,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub mime_type: Option<String>,
+    pub byte_size: Option<i64>,
+    pub checksum: Option<String>,
+}
+
+// Whether coordinates are fractions of the image size (0.0 - 1.0) or pixels
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoordinateUnits {
+    Relative,
+    Pixel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Shape {
+    // (x, y) is the top left corner
+    BoundingBox { x: f64, y: f64, width: f64, height: f64 },
+    Polygon { points: Vec<Point> },
+    Point { x: f64, y: f64 },
+}
+
+// e.g. {"units": "relative", "type": "bounding_box", "x": 0.1, "y": 0.2, "width": 0.5, "height": 0.3}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Geometry {
+    pub units: CoordinateUnits,
+    #[serde(flatten)]
+    pub shape: Shape,
+}
+
+impl Geometry {
+    // Check that the geometry is well formed and lies inside an image of the given size
+    pub fn validate(&self, image_width: i32, image_height: i32) -> Result<(), String> {
+        let (max_x, max_y) = match self.units {
+            CoordinateUnits::Relative => (1.0, 1.0),
+            CoordinateUnits::Pixel => {
+                if image_width <= 0 || image_height <= 0 {
+                    return Err("pixel coordinates need the size of the image".to_string());
+                }
+                (f64::from(image_width), f64::from(image_height))
+            }
+        };
+        let check_point = |x: f64, y: f64| -> Result<(), String> {
+            if !x.is_finite() || !y.is_finite() {
+                return Err("coordinates must be finite numbers".to_string());
+            }
+            if x < 0.0 || y < 0.0 || x > max_x || y > max_y {
+                return Err(format!(
+                    "point ({}, {}) lies outside of the image ({} x {})",
+                    x, y, max_x, max_y
+                ));
+            }
+            Ok(())
+        };
+
+        match &self.shape {
+            Shape::BoundingBox { x, y, width, height } => {
+                if !(*width > 0.0 && *height > 0.0) {
+                    return Err("bounding box width and height must be positive".to_string());
+                }
+                check_point(*x, *y)?;
+                check_point(x + width, y + height)
+            }
+            Shape::Polygon { points } => {
+                if points.len() < 3 {
+                    return Err("polygon needs at least 3 points".to_string());
+                }
+                points.iter().try_for_each(|p| check_point(p.x, p.y))?;
+                // Shoelace formula, zero when all points lie on one line
+                let doubled_area: f64 = points
+                    .iter()
+                    .zip(points.iter().cycle().skip(1))
+                    .map(|(a, b)| a.x * b.y - b.x * a.y)
+                    .sum();
+                if doubled_area == 0.0 {
+                    return Err("polygon must enclose an area".to_string());
+                }
+                Ok(())
+            }
+            Shape::Point { x, y } => check_point(*x, *y),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Annotation {
+    pub id: i32,
+    pub image_id: i32,
+    pub tag: String,
+    pub coordinates: Geometry,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TagPhoto {
+pub struct CreateAnnotation {
     pub tag: String,
-    pub coordinates: String
+    pub coordinates: Geometry,
+}
+
+// Replaces all annotations of a photo and marks it as tagged
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagPhoto {
+    pub annotations: Vec<CreateAnnotation>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -76,8 +428,134 @@ pub struct VerifyPhoto {
 pub struct PhotoToTag {
     pub id: i32,
     pub file_path: String,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub mime_type: Option<String>,
+    pub byte_size: Option<i64>,
+    pub checksum: Option<String>,
     pub tagged: bool,
-    pub tag: String,
+    pub annotations: Vec<Annotation>,
     pub timestamp: DateTime<Utc>,
+    pub locked_until: DateTime<Utc>,
 }
 
+// Lock state of a photo as seen by one tagger
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhotoLock {
+    Held,
+    HeldByOther,
+    Expired,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockLease {
+    pub photo_id: i32,
+    pub locked_until: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn geometry(units: CoordinateUnits, shape: Shape) -> Geometry {
+        Geometry { units, shape }
+    }
+
+    fn polygon(points: &[(f64, f64)]) -> Shape {
+        Shape::Polygon {
+            points: points.iter().map(|&(x, y)| Point { x, y }).collect(),
+        }
+    }
+
+    #[test]
+    fn accepts_shapes_inside_the_image() {
+        let shapes = vec![
+            (CoordinateUnits::Relative, Shape::BoundingBox { x: 0.1, y: 0.2, width: 0.5, height: 0.3 }),
+            (CoordinateUnits::Relative, Shape::BoundingBox { x: 0.0, y: 0.0, width: 1.0, height: 1.0 }),
+            (CoordinateUnits::Relative, polygon(&[(0.0, 0.0), (1.0, 0.0), (0.5, 1.0)])),
+            (CoordinateUnits::Relative, Shape::Point { x: 1.0, y: 1.0 }),
+            (CoordinateUnits::Pixel, Shape::BoundingBox { x: 10.0, y: 20.0, width: 300.0, height: 180.0 }),
+            (CoordinateUnits::Pixel, polygon(&[(0.0, 0.0), (640.0, 0.0), (640.0, 480.0), (0.0, 480.0)])),
+            (CoordinateUnits::Pixel, Shape::Point { x: 640.0, y: 480.0 }),
+        ];
+        for (units, shape) in shapes {
+            let geometry = geometry(units, shape);
+            assert_eq!(geometry.validate(640, 480), Ok(()), "{:?}", geometry);
+        }
+    }
+
+    #[test]
+    fn rejects_shapes_outside_the_image() {
+        let shapes = vec![
+            (CoordinateUnits::Relative, Shape::BoundingBox { x: 0.6, y: 0.2, width: 0.5, height: 0.3 }),
+            (CoordinateUnits::Relative, Shape::BoundingBox { x: -0.1, y: 0.2, width: 0.5, height: 0.3 }),
+            (CoordinateUnits::Relative, polygon(&[(0.0, 0.0), (1.1, 0.0), (0.5, 1.0)])),
+            (CoordinateUnits::Relative, Shape::Point { x: 0.5, y: 1.5 }),
+            // Pixel coordinates given as relative
+            (CoordinateUnits::Relative, Shape::Point { x: 20.0, y: 20.0 }),
+            (CoordinateUnits::Pixel, Shape::BoundingBox { x: 600.0, y: 20.0, width: 41.0, height: 10.0 }),
+            (CoordinateUnits::Pixel, polygon(&[(0.0, 0.0), (640.0, 0.0), (640.0, 481.0)])),
+            (CoordinateUnits::Pixel, Shape::Point { x: -1.0, y: 0.0 }),
+        ];
+        for (units, shape) in shapes {
+            let geometry = geometry(units, shape);
+            assert!(geometry.validate(640, 480).is_err(), "{:?}", geometry);
+        }
+    }
+
+    #[test]
+    fn rejects_bounding_boxes_without_size() {
+        for (width, height) in &[(0.0, 0.5), (0.5, 0.0), (-0.2, 0.5), (f64::NAN, 0.5)] {
+            let shape = Shape::BoundingBox { x: 0.1, y: 0.1, width: *width, height: *height };
+            assert!(geometry(CoordinateUnits::Relative, shape).validate(640, 480).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_nan_and_infinite_coordinates() {
+        for value in &[f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let shapes = vec![
+                Shape::Point { x: *value, y: 0.5 },
+                Shape::Point { x: 0.5, y: *value },
+                Shape::BoundingBox { x: *value, y: 0.1, width: 0.2, height: 0.2 },
+                Shape::BoundingBox { x: 0.1, y: 0.1, width: 0.2, height: *value },
+                polygon(&[(0.0, 0.0), (*value, 0.0), (0.5, 1.0)]),
+            ];
+            for shape in shapes {
+                for units in &[CoordinateUnits::Relative, CoordinateUnits::Pixel] {
+                    let geometry = geometry(*units, shape.clone());
+                    assert!(geometry.validate(640, 480).is_err(), "{:?}", geometry);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_degenerate_polygons() {
+        let polygons = vec![
+            polygon(&[]),
+            polygon(&[(0.1, 0.1), (0.9, 0.9)]),
+            // All points on one line
+            polygon(&[(0.1, 0.1), (0.5, 0.5), (0.9, 0.9)]),
+            polygon(&[(0.2, 0.2), (0.2, 0.2), (0.2, 0.2), (0.2, 0.2)]),
+            // Goes back and forth on the same edge
+            polygon(&[(0.1, 0.1), (0.9, 0.1), (0.1, 0.1), (0.9, 0.1)]),
+        ];
+        for shape in polygons {
+            let geometry = geometry(CoordinateUnits::Relative, shape);
+            assert!(geometry.validate(640, 480).is_err(), "{:?}", geometry);
+        }
+    }
+
+    #[test]
+    fn pixel_units_need_image_dimensions() {
+        let pixel = geometry(CoordinateUnits::Pixel, Shape::Point { x: 0.0, y: 0.0 });
+        assert!(pixel.validate(0, 0).is_err());
+        assert!(pixel.validate(640, 0).is_err());
+        assert!(pixel.validate(0, 480).is_err());
+
+        // Relative coordinates do not depend on the size
+        let relative = geometry(CoordinateUnits::Relative, Shape::Point { x: 0.5, y: 0.5 });
+        assert_eq!(relative.validate(0, 0), Ok(()));
+    }
+}
@@ -0,0 +1,429 @@
+use crate::image_utils;
+use crate::photo_metadata;
+use crate::user_models::{ArchiveUploadReport, CreateImageMeta};
+
+use flate2::read::GzDecoder;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/*
+ * Bulk upload of photos packed in a zip or tar.gz archive.
+ *
+ * Folders inside the archive are flattened, only the sanitized file name of an
+ * entry is kept as original file name. Entries are prepared like single
+ * uploads, rotated upright and stripped of metadata if the album is set to,
+ * and named after their id with the extension of their detected image type
+ * when they are stored.
+ *
+ * Entries larger than max_entry_bytes are skipped. Archives with more entries
+ * or more extracted bytes than allowed are rejected as a whole, whatever their
+ * headers claim.
+ */
+struct ArchiveLimits {
+    max_entry_bytes: u64,
+    max_total_bytes: u64,
+    max_entries: usize,
+}
+
+const ARCHIVE_LIMITS: ArchiveLimits = ArchiveLimits {
+    max_entry_bytes: 64 << 20,
+    max_total_bytes: 4 << 30,
+    max_entries: 10_000,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveKind {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveKind {
+    pub fn of_file(file_name: &str) -> Option<ArchiveKind> {
+        let file_name = file_name.to_lowercase();
+        if file_name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+// What is already stored in the album, entries must not repeat it
+pub struct AlbumContent {
+    pub original_filenames: HashSet<String>,
+    pub checksums: HashSet<String>,
+}
+
+pub struct Extraction {
+    // file_path is the name in the staging folder
+    pub image_metas: Vec<CreateImageMeta>,
+    pub report: ArchiveUploadReport,
+}
+
+struct Extractor<'a> {
+    album_id: i32,
+    // Local folder the images are extracted to before they are stored
+    staging_path: &'a Path,
+    existing: AlbumContent,
+    strip_metadata: bool,
+    limits: &'a ArchiveLimits,
+    entries: usize,
+    total_bytes: u64,
+    extraction: Extraction,
+}
+
+impl<'a> Extractor<'a> {
+    fn add(&mut self, entry_name: &str, content: &[u8]) -> io::Result<()> {
+        let base_name = Path::new(entry_name)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let filename_clean = sanitize_filename::sanitize(&base_name);
+
+        let (image_type, (width, height)) = match image_utils::inspect_image(content) {
+            Ok(item) if !filename_clean.is_empty() => item,
+            _ => {
+                self.extraction.report.not_images.push(entry_name.to_string());
+                return Ok(());
+            }
+        };
+        let prepared = match photo_metadata::prepare(content.to_vec(), image_type, self.strip_metadata) {
+            Ok(item) => item,
+            Err(_) => {
+                self.extraction.report.not_images.push(entry_name.to_string());
+                return Ok(());
+            }
+        };
+        let (content, image_type, exif) = (prepared.content.as_slice(), prepared.image_type, prepared.exif);
+        let (width, height) = photo_metadata::upright_size(width, height, exif.orientation);
+        // Same name or same content as a photo of the album or an earlier entry
+        let checksum = image_utils::checksum(content);
+        if self.existing.original_filenames.contains(&filename_clean) || self.existing.checksums.contains(&checksum) {
+            self.extraction.report.duplicates.push(entry_name.to_string());
+            return Ok(());
+        }
+        self.existing.original_filenames.insert(filename_clean.clone());
+        self.existing.checksums.insert(checksum.clone());
+
+        let file_path = format!("{}.{}", self.extraction.image_metas.len(), image_type.extension());
+        std::fs::write(self.staging_path.join(&file_path), content)?;
+
+        self.extraction.image_metas.push(CreateImageMeta {
+            album_id: self.album_id,
+            file_path,
+            original_filename: Some(filename_clean),
+            width: Some(width),
+            height: Some(height),
+            mime_type: Some(image_type.mime_type().to_string()),
+            byte_size: Some(content.len() as i64),
+            checksum: Some(checksum),
+            perceptual_hash: image_utils::perceptual_hash(content),
+            orientation: exif.orientation,
+            captured_at: exif.captured_at,
+            camera_make: exif.camera_make,
+            camera_model: exif.camera_model,
+        });
+        self.extraction.report.added.push(entry_name.to_string());
+        Ok(())
+    }
+
+    // Read an entry into content, false if it is larger than allowed
+    fn read_entry(&mut self, entry_name: &str, entry: impl Read, content: &mut Vec<u8>) -> io::Result<bool> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("more than {} entries", self.limits.max_entries),
+            ));
+        }
+
+        content.clear();
+        let read = entry.take(self.limits.max_entry_bytes + 1).read_to_end(content)? as u64;
+        self.total_bytes += read;
+        if self.total_bytes > self.limits.max_total_bytes {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("more than {} bytes extracted", self.limits.max_total_bytes),
+            ));
+        }
+        if read > self.limits.max_entry_bytes {
+            self.extraction.report.too_large.push(entry_name.to_string());
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn add_all(&mut self, kind: ArchiveKind, archive: File) -> io::Result<()> {
+        let mut content = Vec::new();
+        match kind {
+            ArchiveKind::Zip => {
+                let mut zip = zip::ZipArchive::new(archive)?;
+                for i in 0..zip.len() {
+                    let entry = zip.by_index(i)?;
+                    if entry.is_dir() {
+                        continue;
+                    }
+                    let entry_name = entry.name().to_string();
+                    if self.read_entry(&entry_name, entry, &mut content)? {
+                        self.add(&entry_name, &content)?;
+                    }
+                }
+            }
+            ArchiveKind::TarGz => {
+                let mut tar = tar::Archive::new(GzDecoder::new(archive));
+                for entry in tar.entries()? {
+                    let entry = entry?;
+                    if !entry.header().entry_type().is_file() {
+                        continue;
+                    }
+                    let entry_name = entry.path()?.to_string_lossy().into_owned();
+                    if self.read_entry(&entry_name, entry, &mut content)? {
+                        self.add(&entry_name, &content)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Write the images of the archive into the staging folder, blocking. The
+// caller stores them and removes the staging folder.
+pub fn extract(
+    kind: ArchiveKind,
+    archive_name: &str,
+    archive_path: &Path,
+    album_id: i32,
+    staging_path: &Path,
+    existing: AlbumContent,
+    strip_metadata: bool,
+) -> io::Result<Extraction> {
+    let archive = File::open(archive_path)?;
+    std::fs::create_dir_all(staging_path)?;
+    let mut extractor = Extractor {
+        album_id,
+        staging_path,
+        existing,
+        strip_metadata,
+        limits: &ARCHIVE_LIMITS,
+        entries: 0,
+        total_bytes: 0,
+        extraction: Extraction {
+            image_metas: Vec::new(),
+            report: ArchiveUploadReport {
+                archive: archive_name.to_string(),
+                added: Vec::new(),
+                not_images: Vec::new(),
+                duplicates: Vec::new(),
+                too_large: Vec::new(),
+                not_stored: Vec::new(),
+                error: None,
+            },
+        },
+    };
+
+    extractor.add_all(kind, archive)?;
+    Ok(extractor.extraction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageOutputFormat};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    // PNG of the given size, different sizes give different checksums
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut content = Vec::new();
+        DynamicImage::new_rgb8(width, height).write_to(&mut content, ImageOutputFormat::Png).unwrap();
+        content
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tagify_test_{}_{}", name, rand::random::<u64>()))
+    }
+
+    fn zip_file(entries: &[(&str, &[u8])]) -> PathBuf {
+        let path = temp_path("archive.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        for (name, content) in entries {
+            zip.start_file(*name, zip::write::FileOptions::default()).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap();
+        path
+    }
+
+    fn tar_gz_file(entries: &[(&str, &[u8])]) -> PathBuf {
+        let path = temp_path("archive.tar.gz");
+        let encoder = flate2::write::GzEncoder::new(File::create(&path).unwrap(), flate2::Compression::fast());
+        let mut tar = tar::Builder::new(encoder);
+        for (name, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            // set_path refuses "..", which archives from elsewhere may still contain
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_cksum();
+            tar.append(&header, *content).unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+        path
+    }
+
+    fn no_content() -> AlbumContent {
+        AlbumContent {
+            original_filenames: HashSet::new(),
+            checksums: HashSet::new(),
+        }
+    }
+
+    fn run(kind: ArchiveKind, archive_path: &Path, existing: AlbumContent, limits: &ArchiveLimits) -> io::Result<Extraction> {
+        let staging_path = temp_path("staging");
+        std::fs::create_dir_all(&staging_path).unwrap();
+        let mut extractor = Extractor {
+            album_id: 1,
+            staging_path: &staging_path,
+            existing,
+            strip_metadata: true,
+            limits,
+            entries: 0,
+            total_bytes: 0,
+            extraction: Extraction {
+                image_metas: Vec::new(),
+                report: ArchiveUploadReport {
+                    archive: "test".to_string(),
+                    added: Vec::new(),
+                    not_images: Vec::new(),
+                    duplicates: Vec::new(),
+                    too_large: Vec::new(),
+                    not_stored: Vec::new(),
+                    error: None,
+                },
+            },
+        };
+        let result = extractor.add_all(kind, File::open(archive_path).unwrap());
+        for image_meta in &extractor.extraction.image_metas {
+            assert!(staging_path.join(&image_meta.file_path).is_file());
+        }
+        std::fs::remove_dir_all(&staging_path).unwrap();
+        std::fs::remove_file(archive_path).unwrap();
+        result.map(|_| extractor.extraction)
+    }
+
+    fn original_filenames(extraction: &Extraction) -> Vec<&str> {
+        extraction
+            .image_metas
+            .iter()
+            .map(|meta| meta.original_filename.as_deref().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn kind_follows_extension() {
+        assert_eq!(ArchiveKind::of_file("photos.ZIP"), Some(ArchiveKind::Zip));
+        assert_eq!(ArchiveKind::of_file("photos.tar.gz"), Some(ArchiveKind::TarGz));
+        assert_eq!(ArchiveKind::of_file("photos.tgz"), Some(ArchiveKind::TarGz));
+        assert_eq!(ArchiveKind::of_file("photos.tar"), None);
+        assert_eq!(ArchiveKind::of_file("photos.gz"), None);
+    }
+
+    #[test]
+    fn flattens_and_sanitizes_entry_names() {
+        let (first, second, third) = (png(4, 3), png(5, 3), png(6, 3));
+        let archive = zip_file(&[
+            ("holiday/beach/sun?.png", &first),
+            ("../../etc/escape.png", &second),
+            ("notes.txt", b"no image"),
+            ("camera\\shot:1.png", &third),
+        ]);
+        let extraction = run(ArchiveKind::Zip, &archive, no_content(), &ARCHIVE_LIMITS).unwrap();
+
+        assert_eq!(original_filenames(&extraction), vec!["sun.png", "escape.png", "camerashot1.png"]);
+        assert_eq!(extraction.report.added, vec!["holiday/beach/sun?.png", "../../etc/escape.png", "camera\\shot:1.png"]);
+        assert_eq!(extraction.report.not_images, vec!["notes.txt"]);
+        // Staged under their position, the final name follows the id
+        let file_paths: Vec<&str> = extraction.image_metas.iter().map(|meta| meta.file_path.as_str()).collect();
+        assert_eq!(file_paths, vec!["0.png", "1.png", "2.png"]);
+    }
+
+    #[test]
+    fn entries_without_file_name_are_no_images() {
+        let content = png(4, 3);
+        let archive = tar_gz_file(&[("photos/..", &content), ("photos/ok.png", &content)]);
+        let extraction = run(ArchiveKind::TarGz, &archive, no_content(), &ARCHIVE_LIMITS).unwrap();
+
+        assert_eq!(extraction.report.not_images, vec!["photos/.."]);
+        assert_eq!(original_filenames(&extraction), vec!["ok.png"]);
+    }
+
+    #[test]
+    fn skips_duplicates_of_the_album_and_the_archive() {
+        let (first, second, third) = (png(4, 3), png(5, 3), png(6, 3));
+        let existing = AlbumContent {
+            original_filenames: vec!["stored.png".to_string()].into_iter().collect(),
+            checksums: vec![image_utils::checksum(&third)].into_iter().collect(),
+        };
+        let archive = tar_gz_file(&[
+            ("a/first.png", &first),
+            ("b/first.png", &second),
+            ("copy.png", &first),
+            ("stored.png", &second),
+            ("third.png", &third),
+        ]);
+        let extraction = run(ArchiveKind::TarGz, &archive, existing, &ARCHIVE_LIMITS).unwrap();
+
+        assert_eq!(extraction.report.added, vec!["a/first.png"]);
+        assert_eq!(extraction.report.duplicates, vec!["b/first.png", "copy.png", "stored.png", "third.png"]);
+    }
+
+    #[test]
+    fn skips_entries_above_the_size_limit() {
+        let (small, large) = (png(4, 3), png(64, 64));
+        let limits = ArchiveLimits {
+            max_entry_bytes: small.len() as u64,
+            ..ARCHIVE_LIMITS
+        };
+        for kind in &[ArchiveKind::Zip, ArchiveKind::TarGz] {
+            let entries: &[(&str, &[u8])] = &[("large.png", &large), ("small.png", &small)];
+            let archive = match kind {
+                ArchiveKind::Zip => zip_file(entries),
+                ArchiveKind::TarGz => tar_gz_file(entries),
+            };
+            let extraction = run(*kind, &archive, no_content(), &limits).unwrap();
+            assert_eq!(extraction.report.too_large, vec!["large.png"]);
+            assert_eq!(extraction.report.added, vec!["small.png"]);
+        }
+    }
+
+    #[test]
+    fn rejects_archives_above_the_limits() {
+        let (first, second, third) = (png(4, 3), png(5, 3), png(6, 3));
+        let entries: &[(&str, &[u8])] = &[("1.png", &first), ("2.png", &second), ("3.png", &third)];
+
+        let limits = ArchiveLimits { max_entries: 2, ..ARCHIVE_LIMITS };
+        let error = run(ArchiveKind::Zip, &zip_file(entries), no_content(), &limits).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let limits = ArchiveLimits {
+            max_total_bytes: (first.len() + second.len()) as u64,
+            ..ARCHIVE_LIMITS
+        };
+        let error = run(ArchiveKind::TarGz, &tar_gz_file(entries), no_content(), &limits).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // Exactly at the limits
+        let limits = ArchiveLimits {
+            max_entries: 3,
+            max_total_bytes: (first.len() + second.len() + third.len()) as u64,
+            ..ARCHIVE_LIMITS
+        };
+        let extraction = run(ArchiveKind::Zip, &zip_file(entries), no_content(), &limits).unwrap();
+        assert_eq!(extraction.report.added.len(), 3);
+    }
+}
@@ -0,0 +1,175 @@
+use deadpool_postgres::Pool;
+use log::error;
+
+use crate::db;
+use crate::maintenance;
+use crate::migrations;
+use crate::storage::Storage;
+use crate::user_models::{ConsistencyRepair, OrphanFileAction};
+
+/*
+ * Maintenance commands that run instead of the http server, e.g.
+ *   backend migrations status
+ * Returns the process exit code.
+ */
+const USAGE: &str = "Usage: backend [migrations status|up|down] [backfill] [counters] \
+    [consistency [--orphan-files import|quarantine|delete] [--delete-missing]]";
+
+// Options of the consistency command, None if they are invalid
+fn consistency_repair(options: &[&str]) -> Option<ConsistencyRepair> {
+    let mut repair = ConsistencyRepair::default();
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match *option {
+            "--orphan-files" => {
+                repair.orphan_files = Some(match options.next() {
+                    Some(&"import") => OrphanFileAction::Import,
+                    Some(&"quarantine") => OrphanFileAction::Quarantine,
+                    Some(&"delete") => OrphanFileAction::Delete,
+                    _ => return None,
+                });
+            }
+            "--delete-missing" => repair.delete_missing = true,
+            _ => return None,
+        }
+    }
+    Some(repair)
+}
+
+pub async fn run(args: &[String], pool: &Pool, storage: &dyn Storage) -> i32 {
+    let mut client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Could not connect to database err: {}", e);
+            return 1;
+        }
+    };
+
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    match args.as_slice() {
+        ["migrations", "status"] => match migrations::pending(&client).await {
+            Ok(pending) => {
+                if pending.is_empty() {
+                    println!("Database schema is up to date");
+                    return 0;
+                }
+                for migration in &pending {
+                    println!("pending: {}", migration.name);
+                }
+                // Non-zero so that deploy scripts can detect pending migrations
+                2
+            }
+            Err(e) => {
+                error!("Could not read migration state err: {:?}", e);
+                1
+            }
+        },
+        ["migrations", "up"] => match migrations::run_pending(&mut client).await {
+            Ok(applied) => {
+                println!("Applied {} migration(s)", applied.len());
+                0
+            }
+            Err(e) => {
+                error!("Failed to apply migrations err: {:?}", e);
+                1
+            }
+        },
+        ["migrations", "down"] => match migrations::revert_last(&mut client).await {
+            Ok(Some(version)) => {
+                println!("Reverted migration {}", version);
+                0
+            }
+            Ok(None) => {
+                println!("No migration to revert");
+                0
+            }
+            Err(e) => {
+                error!("Failed to revert migration err: {:?}", e);
+                1
+            }
+        },
+        ["backfill"] => match maintenance::backfill_image_properties(&client, storage).await {
+            Ok(report) => {
+                println!("Updated {} image(s)", report.updated);
+                for file in &report.missing_files {
+                    println!("missing: {}", file);
+                }
+                for file in &report.not_images {
+                    println!("not an image: {}", file);
+                }
+                0
+            }
+            Err(e) => {
+                error!("Backfill failed err: {:?}", e);
+                1
+            }
+        },
+        ["counters"] => match db::recompute_album_counters(&client).await {
+            Ok(albums) => {
+                for album in &albums {
+                    println!(
+                        "album {}: {} photo(s), {} tagged, {} verified",
+                        album.id, album.image_number, album.tagged_number, album.verified_number
+                    );
+                }
+                println!("Corrected counters of {} album(s)", albums.len());
+                0
+            }
+            Err(e) => {
+                error!("Recomputing album counters failed err: {:?}", e);
+                1
+            }
+        },
+        ["consistency", options @ ..] => {
+            let repair = match consistency_repair(options) {
+                Some(item) => item,
+                None => {
+                    eprintln!("{}", USAGE);
+                    return 1;
+                }
+            };
+            match maintenance::check_consistency(&mut client, storage, &repair).await {
+                Ok(report) => {
+                    for key in &report.orphan_files {
+                        println!("file without image meta: {}", key);
+                    }
+                    for missing in &report.missing_files {
+                        println!("missing file: {}/{} (image {})", missing.album_id, missing.file_path, missing.image_id);
+                    }
+                    for key in &report.imported {
+                        println!("imported: {}", key);
+                    }
+                    for key in &report.quarantined {
+                        println!("quarantined: {}", key);
+                    }
+                    for key in &report.deleted_files {
+                        println!("deleted file: {}", key);
+                    }
+                    for image_id in &report.deleted_image_metas {
+                        println!("deleted image meta: {}", image_id);
+                    }
+                    for failure in &report.failed {
+                        println!("failed: {}", failure);
+                    }
+                    let repaired = report.imported.len() + report.quarantined.len() + report.deleted_files.len();
+                    let consistent = repaired == report.orphan_files.len()
+                        && report.deleted_image_metas.len() == report.missing_files.len();
+                    // Non-zero so that cron jobs can alert on differences left
+                    if consistent {
+                        0
+                    } else {
+                        2
+                    }
+                }
+                Err(e) => {
+                    error!("Consistency check failed err: {}", e);
+                    1
+                }
+            }
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            1
+        }
+    }
+}
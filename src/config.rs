@@ -14,6 +14,84 @@ pub struct TagifyData {
     pub path: String,
 }
 
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct Tagging {
+    pub lock_lease_secs: i64,
+    pub batch_size: i64,
+}
+
+impl Default for Tagging {
+    fn default() -> Self {
+        Tagging {
+            lock_lease_secs: 15 * 60,
+            batch_size: 20,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct Trash {
+    pub retention_days: i32,
+}
+
+impl Default for Trash {
+    fn default() -> Self {
+        Trash {
+            retention_days: 30,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    Local,
+    S3,
+}
+
+impl Default for StorageBackend {
+    fn default() -> Self {
+        StorageBackend::Local
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct S3 {
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+    pub prefix: String,
+    pub presign_reads: bool,
+    pub presign_expiry_secs: u64,
+}
+
+impl Default for S3 {
+    fn default() -> Self {
+        S3 {
+            endpoint: "http://localhost:9000".to_string(),
+            region: "us-east-1".to_string(),
+            bucket: "tagify".to_string(),
+            access_key: String::new(),
+            secret_key: String::new(),
+            prefix: "albums/".to_string(),
+            presign_reads: false,
+            presign_expiry_secs: 300,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Storage {
+    pub backend: StorageBackend,
+    pub s3: S3,
+}
+
 #[derive(Deserialize, Clone)]
 pub struct MyConfig {
     pub postgres: deadpool_postgres::Config,
@@ -21,6 +99,12 @@ pub struct MyConfig {
     pub default_admin: CreateUser,
     pub default_user: CreateUser,
     pub tagify_data: TagifyData,
+    #[serde(default)]
+    pub tagging: Tagging,
+    #[serde(default)]
+    pub storage: Storage,
+    #[serde(default)]
+    pub trash: Trash,
 }
 
 impl MyConfig {
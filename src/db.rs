@@ -2,22 +2,25 @@
 
 use crate::album_models::{
     Album, CreateAlbum, AlbumsPreview, AlbumPreview, UpdateAlbum, 
-    PhotoPreview, TagPhoto, PhotoToTag
+    PhotoPreview, TagPhoto, PhotoToTag, Annotation, CreateAnnotation, Geometry,
+    PhotoLock, LockLease, TrashContent, TrashedAlbum, TrashedPhoto,
+    AlbumMember, AlbumRole, AssignedAlbum, ShareLink, PhotoImport
 };
 use crate::errors::DBError;
 use crate::user_models::{
     CreateUser, Hash, User, CreateImageMeta,
-    SendUser
+    SendUser, ImageMeta, FileDeletion, Session, ApiToken, TokenScope
 };
 
 use actix_web::Result;
 use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_postgres::types::Json;
 use log::{error, info};
 
-use chrono::offset::Utc;
+use chrono::offset::{TimeZone, Utc};
 
 pub async fn get_user_by_name(
-    client: deadpool_postgres::Client,
+    client: &deadpool_postgres::Client,
     username: &str,
 ) -> Result<User, DBError> {
     // Query data
@@ -37,6 +40,7 @@ pub async fn get_user(client: &deadpool_postgres::Client, id: i32) -> Result<Use
     Ok(User::from_row_ref(&result)?)
 }
 
+// All sessions of the user are logged out
 pub async fn update_user(client: &deadpool_postgres::Client, user: &User) -> Result<User, DBError> {
     if user.password.len() < 4 {
         return Err(DBError::BadArgs {
@@ -51,7 +55,8 @@ pub async fn update_user(client: &deadpool_postgres::Client, user: &User) -> Res
 
     let result = client
         .query_one(
-            "UPDATE users SET nickname=$1, password=$2, role=$3 WHERE id=$4 RETURNING *",
+            "WITH revoked AS (DELETE FROM sessions WHERE users_id=$4)
+             UPDATE users SET nickname=$1, password=$2, role=$3 WHERE id=$4 RETURNING *",
             &[&user.nickname, &hashed_pwd, &user.role, &user.id],
         )
         .await?;
@@ -69,7 +74,12 @@ pub async fn update_user_nickname(client: &deadpool_postgres::Client, user: &Use
     Ok(User::from_row_ref(&result)?)
 }
 
-pub async fn update_user_password(client: &deadpool_postgres::Client, user: &User) -> Result<User, DBError> {
+// All other sessions of the user than keep_session are logged out
+pub async fn update_user_password(
+    client: &deadpool_postgres::Client,
+    user: &User,
+    keep_session: Option<i32>,
+) -> Result<User, DBError> {
     if user.password.len() < 4 {
         return Err(DBError::BadArgs {
             err: "Password is too short".to_owned(),
@@ -83,8 +93,9 @@ pub async fn update_user_password(client: &deadpool_postgres::Client, user: &Use
 
     let result = client
         .query_one(
-            "UPDATE users SET  password=$1 WHERE id=$2 RETURNING *",
-            &[&hashed_pwd, &user.id],
+            "WITH revoked AS (DELETE FROM sessions WHERE users_id=$2 AND ($3::INT IS NULL OR id <> $3))
+             UPDATE users SET  password=$1 WHERE id=$2 RETURNING *",
+            &[&hashed_pwd, &user.id, &keep_session],
         )
         .await?;
     Ok(User::from_row_ref(&result)?)
@@ -118,6 +129,266 @@ pub async fn delete_user(
     Ok(User::from_row_ref(&result)?)
 }
 
+//sessions
+pub async fn create_session(
+    client: &deadpool_postgres::Client,
+    user_id: i32,
+    token_hash: &str,
+    user_agent: Option<&str>,
+    ip_address: Option<&str>,
+    lifetime_days: i32,
+) -> Result<i32, DBError> {
+    let result = client
+        .query_one(
+            "INSERT INTO sessions (token_hash, users_id, user_agent, ip_address, expires_at)
+             VALUES ($1, $2, $3, $4, now() + make_interval(days => $5)) RETURNING id",
+            &[&token_hash, &user_id, &user_agent, &ip_address, &lifetime_days],
+        )
+        .await?;
+    Ok(result.get(0))
+}
+
+// User of an unexpired session and the session id. last_seen_at is written
+// at most once a minute per session.
+pub async fn get_session_user(
+    client: &deadpool_postgres::Client,
+    token_hash: &str,
+) -> Result<Option<(i32, User)>, DBError> {
+    let result = client
+        .query_opt(
+            "SELECT users.*, sessions.id AS session_id, sessions.last_seen_at < now() - INTERVAL '1 minute' AS stale
+             FROM sessions JOIN users ON users.id = sessions.users_id
+             WHERE sessions.token_hash = $1 AND sessions.expires_at > now()",
+            &[&token_hash],
+        )
+        .await?;
+    let row = match result {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+    let session_id: i32 = row.get("session_id");
+    let stale: bool = row.get("stale");
+    if stale {
+        client
+            .execute("UPDATE sessions SET last_seen_at = now() WHERE id = $1", &[&session_id])
+            .await?;
+    }
+    Ok(Some((session_id, User::from_row_ref(&row)?)))
+}
+
+pub async fn get_sessions_of_user(
+    client: &deadpool_postgres::Client,
+    user_id: i32,
+    current_session: Option<i32>,
+) -> Result<Vec<Session>, DBError> {
+    let rows = client
+        .query(
+            "SELECT id, user_agent, ip_address, created_at, last_seen_at, expires_at, COALESCE(id = $2, false) AS current
+             FROM sessions WHERE users_id = $1 AND expires_at > now() ORDER BY last_seen_at DESC",
+            &[&user_id, &current_session],
+        )
+        .await?;
+    let mut sessions = Vec::new();
+    for row in rows.iter() {
+        sessions.push(Session::from_row_ref(row)?);
+    }
+    Ok(sessions)
+}
+
+pub async fn delete_session(
+    client: &deadpool_postgres::Client,
+    user_id: i32,
+    session_id: i32,
+) -> Result<(), DBError> {
+    let deleted = client
+        .execute("DELETE FROM sessions WHERE id = $1 AND users_id = $2", &[&session_id, &user_id])
+        .await?;
+    if deleted == 0 {
+        return Err(DBError::NotFound { err: format!("Session {} not found", session_id) });
+    }
+    Ok(())
+}
+
+// Log out all sessions of the user except keep_session, returns the number of sessions
+pub async fn delete_sessions_of_user(
+    client: &deadpool_postgres::Client,
+    user_id: i32,
+    keep_session: Option<i32>,
+) -> Result<u64, DBError> {
+    let deleted = client
+        .execute(
+            "DELETE FROM sessions WHERE users_id = $1 AND ($2::INT IS NULL OR id <> $2)",
+            &[&user_id, &keep_session],
+        )
+        .await?;
+    Ok(deleted)
+}
+
+pub async fn delete_expired_sessions(
+    client: &deadpool_postgres::Client,
+) -> Result<u64, DBError> {
+    let deleted = client.execute("DELETE FROM sessions WHERE expires_at <= now()", &[]).await?;
+    Ok(deleted)
+}
+
+//album share links
+pub async fn create_share_link(
+    client: &deadpool_postgres::Client,
+    album_id: i32,
+    created_by: i32,
+    token_hash: &str,
+    expires_in_days: i32,
+) -> Result<ShareLink, DBError> {
+    let result = client
+        .query_one(
+            "INSERT INTO album_share_links (album_id, created_by, token_hash, expires_at)
+             VALUES ($1, $2, $3, now() + make_interval(days => $4)) RETURNING *",
+            &[&album_id, &created_by, &token_hash, &expires_in_days],
+        )
+        .await?;
+    Ok(ShareLink::from_row_ref(&result)?)
+}
+
+pub async fn get_share_links(
+    client: &deadpool_postgres::Client,
+    album_id: i32,
+) -> Result<Vec<ShareLink>, DBError> {
+    let rows = client
+        .query(
+            "SELECT * FROM album_share_links WHERE album_id = $1 AND expires_at > now() ORDER BY created_at",
+            &[&album_id],
+        )
+        .await?;
+    let mut links = Vec::new();
+    for row in &rows {
+        links.push(ShareLink::from_row_ref(row)?);
+    }
+    Ok(links)
+}
+
+pub async fn delete_share_link(
+    client: &deadpool_postgres::Client,
+    album_id: i32,
+    link_id: i32,
+) -> Result<(), DBError> {
+    let deleted = client
+        .execute("DELETE FROM album_share_links WHERE id = $1 AND album_id = $2", &[&link_id, &album_id])
+        .await?;
+    if deleted == 0 {
+        return Err(DBError::NotFound { err: format!("Share link {} not found", link_id) });
+    }
+    Ok(())
+}
+
+// Album of a share link that has not expired, NotFound otherwise
+pub async fn get_shared_album(
+    client: &deadpool_postgres::Client,
+    token_hash: &str,
+) -> Result<Album, DBError> {
+    let result = client
+        .query_opt(
+            "SELECT albums.* FROM albums JOIN album_share_links ON album_share_links.album_id = albums.id
+             WHERE album_share_links.token_hash = $1 AND album_share_links.expires_at > now()
+             AND albums.deleted_at IS NULL",
+            &[&token_hash],
+        )
+        .await?;
+    match result {
+        Some(item) => Ok(Album::from_row_ref(&item)?),
+        None => Err(DBError::NotFound { err: "Share link not found or expired".to_string() }),
+    }
+}
+
+pub async fn delete_expired_share_links(
+    client: &deadpool_postgres::Client,
+) -> Result<u64, DBError> {
+    let deleted = client.execute("DELETE FROM album_share_links WHERE expires_at <= now()", &[]).await?;
+    Ok(deleted)
+}
+
+//api tokens
+pub async fn create_api_token(
+    client: &deadpool_postgres::Client,
+    user_id: i32,
+    name: &str,
+    token_hash: &str,
+    scopes: &[TokenScope],
+    expires_in_days: Option<i32>,
+) -> Result<ApiToken, DBError> {
+    let scopes: Vec<&str> = scopes.iter().map(|scope| scope.name()).collect();
+    let result = client
+        .query_one(
+            "INSERT INTO api_tokens (users_id, name, token_hash, scopes, expires_at)
+             VALUES ($1, $2, $3, $4, now() + make_interval(days => $5))
+             RETURNING id, name, scopes, created_at, expires_at, last_used_at",
+            &[&user_id, &name, &token_hash, &scopes, &expires_in_days],
+        )
+        .await?;
+    Ok(ApiToken::from_row_ref(&result)?)
+}
+
+pub async fn get_api_tokens_of_user(
+    client: &deadpool_postgres::Client,
+    user_id: i32,
+) -> Result<Vec<ApiToken>, DBError> {
+    let rows = client
+        .query(
+            "SELECT id, name, scopes, created_at, expires_at, last_used_at FROM api_tokens
+             WHERE users_id = $1 ORDER BY id",
+            &[&user_id],
+        )
+        .await?;
+    let mut tokens = Vec::new();
+    for row in rows.iter() {
+        tokens.push(ApiToken::from_row_ref(row)?);
+    }
+    Ok(tokens)
+}
+
+pub async fn delete_api_token(
+    client: &deadpool_postgres::Client,
+    user_id: i32,
+    token_id: i32,
+) -> Result<(), DBError> {
+    let deleted = client
+        .execute("DELETE FROM api_tokens WHERE id = $1 AND users_id = $2", &[&token_id, &user_id])
+        .await?;
+    if deleted == 0 {
+        return Err(DBError::NotFound { err: format!("Token {} not found", token_id) });
+    }
+    Ok(())
+}
+
+// User of an unexpired token and whether the token has the scope.
+// last_used_at is written at most once a minute per token.
+pub async fn get_api_token_user(
+    client: &deadpool_postgres::Client,
+    token_hash: &str,
+    scope: TokenScope,
+) -> Result<Option<(User, bool)>, DBError> {
+    let result = client
+        .query_opt(
+            "SELECT users.*, api_tokens.id AS token_id, $2 = ANY(api_tokens.scopes) AS permitted,
+                    COALESCE(api_tokens.last_used_at < now() - INTERVAL '1 minute', true) AS stale
+             FROM api_tokens JOIN users ON users.id = api_tokens.users_id
+             WHERE api_tokens.token_hash = $1 AND (api_tokens.expires_at IS NULL OR api_tokens.expires_at > now())",
+            &[&token_hash, &scope.name()],
+        )
+        .await?;
+    let row = match result {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+    let token_id: i32 = row.get("token_id");
+    let stale: bool = row.get("stale");
+    if stale {
+        client
+            .execute("UPDATE api_tokens SET last_used_at = now() WHERE id = $1", &[&token_id])
+            .await?;
+    }
+    Ok(Some((User::from_row_ref(&row)?, row.get("permitted"))))
+}
+
 //albums
 pub async fn create_album(
     client: &deadpool_postgres::Client,
@@ -126,43 +397,294 @@ pub async fn create_album(
     first_photo: String,
 ) -> Result<Album, DBError> {
     let result = client.query_one(
-        "INSERT INTO albums (title, description, tags, users_id, first_photo) VAlUES ($1, $2, $3, $4, $5) RETURNING *",
-        &[&album.title, &album.description, &album.tags, &id, &first_photo]).await?;
+        "INSERT INTO albums (title, description, tags, users_id, first_photo, strip_metadata, visibility) VAlUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *",
+        &[&album.title, &album.description, &album.tags, &id, &first_photo, &album.strip_metadata, &album.visibility]).await?;
     // println!("restlt: {:?}", result);
     Ok(Album::from_row_ref(&result)?)
 }
 
-pub async fn create_image_meta (
+// Advisory lock on the files of an album. Uploads hold it shared until their
+// transaction ends, the consistency check exclusively while it compares and
+// repairs the album, so it never sees a file whose row is not committed yet.
+const ALBUM_FILES_LOCK: i32 = 1;
+
+pub async fn lock_album_files_shared (
+    transaction: &deadpool_postgres::Transaction<'_>,
+    album_id: &i32,
+) -> Result<(), DBError> {
+    transaction.execute("SELECT pg_advisory_xact_lock_shared($1, $2)", &[&ALBUM_FILES_LOCK, &album_id]).await?;
+    Ok(())
+}
+
+// Held until unlock_album_files, also if a transaction ends
+pub async fn lock_album_files (
+    client: &deadpool_postgres::Client,
+    album_id: &i32,
+) -> Result<(), DBError> {
+    client.execute("SELECT pg_advisory_lock($1, $2)", &[&ALBUM_FILES_LOCK, &album_id]).await?;
+    Ok(())
+}
+
+pub async fn unlock_album_files (
     client: &deadpool_postgres::Client,
+    album_id: &i32,
+) -> Result<(), DBError> {
+    client.execute("SELECT pg_advisory_unlock($1, $2)", &[&ALBUM_FILES_LOCK, &album_id]).await?;
+    Ok(())
+}
+
+// Id for a new image, handed out only once even if the transaction is rolled
+// back. The file of the image is named after it.
+pub async fn reserve_image_id (
+    transaction: &deadpool_postgres::Transaction<'_>,
+) -> Result<i32, DBError> {
+    let result = transaction
+        .query_one("SELECT nextval(pg_get_serial_sequence('image_metas', 'id'))::INT", &[])
+        .await?;
+    Ok(result.get(0))
+}
+
+pub async fn create_image_meta (
+    transaction: &deadpool_postgres::Transaction<'_>,
+    image_id: &i32,
     image_meta: &CreateImageMeta,
-) -> Result<bool, DBError> {
-    let _result = client.query_one(
-        "insert into image_metas (album_id, file_path, coordinates, tag) values ($1, $2, $3, '') RETURNING *",
-        &[&image_meta.album_id, &image_meta.file_path, &image_meta.coordinates]).await?;
-    // println!("restlt: {:?}", result);
-    Ok(true)
+) -> Result<(), DBError> {
+    transaction.execute(
+        "insert into image_metas (id, album_id, file_path, original_filename, width, height, mime_type, byte_size, checksum,
+         perceptual_hash, orientation, captured_at, camera_make, camera_model)
+         values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)",
+        &[&image_id, &image_meta.album_id, &image_meta.file_path, &image_meta.original_filename, &image_meta.width,
+          &image_meta.height, &image_meta.mime_type, &image_meta.byte_size, &image_meta.checksum, &image_meta.perceptual_hash,
+          &image_meta.orientation, &image_meta.captured_at, &image_meta.camera_make, &image_meta.camera_model]).await?;
+    Ok(())
+}
+
+// A photo a tagger is working on is not replaced, returns None then. The row
+// stays locked until the transaction with the new image ends.
+pub async fn get_replaceable_file_path (
+    transaction: &deadpool_postgres::Transaction<'_>,
+    image_id: &i32,
+    lease: i64,
+) -> Result<Option<String>, DBError> {
+    let row = transaction.query_opt(
+        "SELECT file_path, locked_at, locked_by FROM image_metas WHERE id=$1 AND deleted_at IS NULL FOR UPDATE",
+        &[&image_id]).await?;
+    let row = match row {
+        Some(item) => item,
+        None => return Err(DBError::NotFound { err: format!("Image {} not found", image_id) }),
+    };
+    let locked_at: i64 = row.get(1);
+    let locked_by: Option<i32> = row.get(2);
+    if locked_by.is_some() && locked_at + lease > Utc::now().timestamp() {
+        return Ok(None);
+    }
+    Ok(Some(row.get(0)))
 }
 
+// The replaced file is queued for deletion in the same transaction, see
+// file_deletions
 pub async fn update_image_meta (
-    client: &deadpool_postgres::Client,
+    transaction: &deadpool_postgres::Transaction<'_>,
     image_meta: &CreateImageMeta,
     image_id: &i32,
+    old_file_path: &str,
+) -> Result<(), DBError> {
+    // A new image invalidates the old annotations
+    transaction.execute(
+        "UPDATE image_metas SET album_id=$1, file_path=$2, original_filename=$3, width=$4, height=$5,
+         mime_type=$6, byte_size=$7, checksum=$8, perceptual_hash=$9, orientation=$10, captured_at=$11, camera_make=$12,
+         camera_model=$13, tagged=false, verified=false, locked_at=0, locked_by=NULL WHERE id=$14",
+        &[&image_meta.album_id, &image_meta.file_path, &image_meta.original_filename, &image_meta.width, &image_meta.height,
+          &image_meta.mime_type, &image_meta.byte_size, &image_meta.checksum, &image_meta.perceptual_hash,
+          &image_meta.orientation, &image_meta.captured_at, &image_meta.camera_make, &image_meta.camera_model,
+          &image_id]).await?;
+    transaction.execute("DELETE FROM annotations WHERE image_id = $1", &[&image_id]).await?;
+    transaction.execute(
+        "INSERT INTO file_deletions (album_id, file_path) VALUES ($1, $2)",
+        &[&image_meta.album_id, &old_file_path]).await?;
+    Ok(())
+}
+
+pub async fn get_image_meta (
+    client: &deadpool_postgres::Client,
+    image_id: &i32,
+) -> Result<ImageMeta, DBError> {
+    let result = client.query_one(
+        "SELECT id, album_id, file_path, original_filename, width, height, mime_type, byte_size, checksum, perceptual_hash,
+         orientation, captured_at, camera_make, camera_model FROM image_metas WHERE id=$1 AND deleted_at IS NULL",
+        &[&image_id]).await?;
+    Ok(ImageMeta::from_row_ref(&result)?)
+}
+
+pub async fn get_image_metas_of_album (
+    client: &deadpool_postgres::Client,
+    album_id: &i32,
+) -> Result<Vec<ImageMeta>, DBError> {
+    let result = client.query(
+        "SELECT id, album_id, file_path, original_filename, width, height, mime_type, byte_size, checksum, perceptual_hash,
+         orientation, captured_at, camera_make, camera_model FROM image_metas WHERE album_id=$1 AND deleted_at IS NULL ORDER BY id",
+        &[&album_id]).await?;
+    let mut image_metas = Vec::new();
+    for row in result.iter() {
+        image_metas.push(ImageMeta::from_row_ref(row)?);
+    }
+    Ok(image_metas)
+}
+
+// all photos with a stored file, including those in the trash
+pub async fn get_stored_image_metas_of_album (
+    client: &deadpool_postgres::Client,
+    album_id: &i32,
+) -> Result<Vec<ImageMeta>, DBError> {
+    let result = client.query(
+        "SELECT id, album_id, file_path, original_filename, width, height, mime_type, byte_size, checksum, perceptual_hash,
+         orientation, captured_at, camera_make, camera_model FROM image_metas WHERE album_id=$1 ORDER BY id",
+        &[&album_id]).await?;
+    let mut image_metas = Vec::new();
+    for row in result.iter() {
+        image_metas.push(ImageMeta::from_row_ref(row)?);
+    }
+    Ok(image_metas)
+}
+
+// verified photos of an album, used for dataset export
+pub async fn get_verified_image_metas (
+    client: &deadpool_postgres::Client,
+    album_id: &i32,
+) -> Result<Vec<ImageMeta>, DBError> {
+    let result = client.query(
+        "SELECT id, album_id, file_path, original_filename, width, height, mime_type, byte_size, checksum, perceptual_hash,
+         orientation, captured_at, camera_make, camera_model FROM image_metas WHERE album_id=$1 AND verified=true AND deleted_at IS NULL ORDER BY id",
+        &[&album_id]).await?;
+    let mut image_metas = Vec::new();
+    for row in result.iter() {
+        image_metas.push(ImageMeta::from_row_ref(row)?);
+    }
+    Ok(image_metas)
+}
+
+// photos with unknown file properties, in batches ordered by id
+pub async fn get_image_metas_without_properties (
+    client: &deadpool_postgres::Client,
+    after_id: i32,
+    limit: i64,
+) -> Result<Vec<ImageMeta>, DBError> {
+    let result = client.query(
+        "SELECT id, album_id, file_path, original_filename, width, height, mime_type, byte_size, checksum, perceptual_hash,
+         orientation, captured_at, camera_make, camera_model FROM image_metas
+         WHERE (width IS NULL OR height IS NULL OR mime_type IS NULL OR byte_size IS NULL OR checksum IS NULL
+                OR perceptual_hash IS NULL) AND id > $1
+         ORDER BY id LIMIT $2",
+        &[&after_id, &limit]).await?;
+    let mut image_metas = Vec::new();
+    for row in result.iter() {
+        image_metas.push(ImageMeta::from_row_ref(row)?);
+    }
+    Ok(image_metas)
+}
+
+// known values are kept if the file could not be read as image
+pub async fn set_image_properties (
+    client: &deadpool_postgres::Client,
+    image_id: &i32,
+    dimensions: Option<(i32, i32)>,
+    mime_type: Option<&str>,
+    byte_size: i64,
+    checksum: &str,
+    perceptual_hash: Option<i64>,
+) -> Result<(), DBError> {
+    client.execute(
+        "UPDATE image_metas SET width=COALESCE($1, width), height=COALESCE($2, height), mime_type=COALESCE($3, mime_type),
+         byte_size=$4, checksum=$5, perceptual_hash=COALESCE($6, perceptual_hash) WHERE id=$7",
+        &[&dimensions.map(|d| d.0), &dimensions.map(|d| d.1), &mime_type, &byte_size, &checksum, &perceptual_hash,
+          &image_id]).await?;
+    Ok(())
+}
+
+// another photo of the album with exactly this content. Locks the content
+// in the album until the transaction ends, so concurrent uploads of the same
+// photo wait for each other and only the first is stored.
+pub async fn find_image_by_checksum (
+    transaction: &deadpool_postgres::Transaction<'_>,
+    album_id: &i32,
+    checksum: &str,
+    except_id: Option<i32>,
+) -> Result<Option<i32>, DBError> {
+    transaction.execute("SELECT pg_advisory_xact_lock(hashtextextended($1, $2))", &[&checksum, &(*album_id as i64)]).await?;
+    let result = transaction.query_opt(
+        "SELECT id FROM image_metas WHERE album_id=$1 AND checksum=$2 AND ($3::INT IS NULL OR id <> $3) AND deleted_at IS NULL
+         ORDER BY id LIMIT 1",
+        &[&album_id, &checksum, &except_id]).await?;
+    Ok(result.map(|row| row.get(0)))
+}
+
+pub async fn set_image_dimensions (
+    client: &deadpool_postgres::Client,
+    image_id: &i32,
+    width: i32,
+    height: i32,
 ) -> Result<bool, DBError> {
-    let _result = client.query_one(
-        "UPDATE image_metas SET album_id=$1, file_path=$2, coordinates=$3 WHERE id=$4 RETURNING *",
-        &[&image_meta.album_id, &image_meta.file_path, &image_meta.coordinates, &image_id]).await?;
-    // println!("restlt: {:?}", result);
+    client.execute(
+        "UPDATE image_metas SET width=$1, height=$2 WHERE id=$3",
+        &[&width, &height, &image_id]).await?;
     Ok(true)
 }
 
-pub async fn delete_image_meta (
+// photo pairs of an album the owner decided to keep, as (smaller id, larger id)
+pub async fn get_duplicate_dismissals (
+    client: &deadpool_postgres::Client,
+    album_id: &i32,
+) -> Result<Vec<(i32, i32)>, DBError> {
+    let result = client.query(
+        "SELECT duplicate_dismissals.image_id, duplicate_dismissals.other_image_id FROM duplicate_dismissals
+         INNER JOIN image_metas ON image_metas.id = duplicate_dismissals.image_id WHERE image_metas.album_id=$1",
+        &[&album_id]).await?;
+    Ok(result.iter().map(|row| (row.get(0), row.get(1))).collect())
+}
+
+pub async fn dismiss_duplicate (
     client: &deadpool_postgres::Client,
+    image_id: &i32,
+    other_image_id: &i32,
+) -> Result<(), DBError> {
+    let (first, second) = if image_id < other_image_id {
+        (image_id, other_image_id)
+    } else {
+        (other_image_id, image_id)
+    };
+    client.execute(
+        "INSERT INTO duplicate_dismissals (image_id, other_image_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        &[&first, &second]).await?;
+    Ok(())
+}
+
+// The file is queued for deletion in the same transaction, see file_deletions
+pub async fn delete_image_meta (
+    client: &mut deadpool_postgres::Client,
     image_meta_id: &i32,
-) -> Result<bool, DBError> {
-    let _result = client.query_one(
-        "DELETE FROM image_metas WHERE id=$1 RETURNING *",
+) -> Result<(), DBError> {
+    let transaction = client.transaction().await?;
+    let row = transaction.query_opt(
+        "DELETE FROM image_metas WHERE id=$1 RETURNING album_id, file_path",
         &[&image_meta_id]).await?;
-    Ok(true)
+    let row = match row {
+        Some(item) => item,
+        None => return Err(DBError::NotFound { err: format!("Image {} not found", image_meta_id) }),
+    };
+    let album_id: i32 = row.get(0);
+    let file_path: String = row.get(1);
+    transaction.execute(
+        "INSERT INTO file_deletions (album_id, file_path) VALUES ($1, $2)",
+        &[&album_id, &file_path]).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+pub async fn get_album_ids (
+    client: &deadpool_postgres::Client,
+) -> Result<Vec<i32>, DBError> {
+    let rows = client.query("SELECT id FROM albums ORDER BY id", &[]).await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
 }
 
 pub async fn check_album_exist_by_id (
@@ -170,7 +692,7 @@ pub async fn check_album_exist_by_id (
     album_id: &i32,
 ) -> bool {
     let result = client.query_one(
-        "SELECT * FROM albums WHERE id=$1", &[&album_id]).await;
+        "SELECT * FROM albums WHERE id=$1 AND deleted_at IS NULL", &[&album_id]).await;
     // println!("restlt: {:?}", result);
     match result {
         Ok(_row) => return true,
@@ -181,41 +703,25 @@ pub async fn check_album_exist_by_id (
     }
 }
 
-pub async fn get_image_filenames_of_album_with_id (
-    client: &deadpool_postgres::Client,
-    album_id: &i32,
-) -> Vec<String> {
-
-    let mut filenames_db: Vec<String> = Vec::new();
-    let result = client.query(
-        "SELECT * FROM image_metas WHERE album_id = $1 ORDER BY file_path DESC", &[&album_id]).await;
-    match result {
-        Ok(rows) => {
-            if rows.len() == 0{
-                info!("Album {} has no photo in db", album_id);
-            } else {
-                for row in rows {
-                    let filename: String = row.get(3);
-                    filenames_db.push(filename);
-                }
-            }
-        },
-        Err(e) => {
-            error!("Error get_image_filenames_of_album_with_id: {:?}", e);
-        },
-    }
-    return filenames_db;
-}
-
 // get albums data to preview from DB
+// Albums visible to the user (None for anonymous requests), see_all for
+// users that see private albums of others
 pub async fn get_all_albums(
-    client: deadpool_postgres::Client
+    client: deadpool_postgres::Client,
+    user_id: Option<i32>,
+    see_all: bool,
 ) -> Result<AlbumsPreview, DBError> {
     let mut albums = AlbumsPreview {
         albums: Vec::new()
     };
     
-    for row in client.query("SELECT id, title, description, first_photo  FROM albums ", &[]).await? {
+    for row in client.query(
+        "SELECT id, title, description, first_photo FROM albums WHERE deleted_at IS NULL AND (
+             $2 OR visibility = 'public'
+             OR ($1::INT IS NOT NULL AND (visibility = 'internal' OR users_id = $1
+                 OR EXISTS (SELECT 1 FROM album_members WHERE album_id = albums.id AND users_id = $1)))
+         )",
+        &[&user_id, &see_all]).await? {
         let album = AlbumPreview {
             id: row.get(0),
             title: row.get(1),
@@ -234,26 +740,18 @@ pub async fn get_photos_from_album(
     id: &i32,
     index: &i32
 ) -> Result<Vec<PhotoPreview>, DBError> {
-    let mut photos = Vec::new();
+    const PAGE_SIZE: i64 = 20;
+    if *index < 0 {
+        return Err(DBError::BadArgs { err: format!("Page {} does not exist", index) });
+    }
 
-    let start_position = index * 20;
-    let last_position = &start_position + 20;
-    let mut current_position = 0;
-    
-    for row in client.query("SELECT id, file_path  FROM image_metas WHERE album_id = $1 ", &[&id]).await? {
-        if &current_position >= &start_position {
-            let photo = PhotoPreview {
-                id: row.get(0),
-                file_path: row.get(1)
-            };
-            photos.push(photo);
-            current_position = current_position + 1;
-            if &current_position >= &last_position {
-                break;
-            }
-        }else {
-            current_position = current_position + 1;
-        }
+    let rows = client.query(
+        "SELECT id, file_path, width, height, mime_type, byte_size, checksum FROM image_metas
+         WHERE album_id = $1 AND deleted_at IS NULL ORDER BY id LIMIT $2 OFFSET $3",
+        &[&id, &PAGE_SIZE, &(i64::from(*index) * PAGE_SIZE)]).await?;
+    let mut photos = Vec::new();
+    for row in rows {
+        photos.push(PhotoPreview::from_row_ref(&row)?);
     }
     Ok(photos)
 }
@@ -266,14 +764,14 @@ pub async fn get_image_file_path_with_id_from_album (
 
     let mut file_path = "".to_string();
     let result = client.query(
-        "SELECT * FROM image_metas WHERE id = $1 AND album_id = $2", &[&image_id, &album_id]).await;
+        "SELECT file_path FROM image_metas WHERE id = $1 AND album_id = $2 AND deleted_at IS NULL", &[&image_id, &album_id]).await;
     match result {
         Ok(rows) => {
             if rows.len() == 0{
                 info!("Image with id {} not found in db", image_id);
             } else {
                 for row in rows {
-                    file_path = row.get(3);
+                    file_path = row.get(0);
                     break;
                 }
             }
@@ -291,7 +789,7 @@ pub async fn get_users_albums(
     id: i32,
 ) -> Result<Vec<Album>, DBError> {
     let result = client
-        .query("SELECT * FROM albums WHERE users_id = $1", &[&id])
+        .query("SELECT * FROM albums WHERE users_id = $1 AND deleted_at IS NULL", &[&id])
         .await
         .expect("ERROR GETTING ALBUMS")
         .iter()
@@ -307,145 +805,1058 @@ pub async fn get_album_by_id(
 ) -> Result<Album, DBError> {
     // Query data
     let result = client
-        .query_one("SELECT * FROM albums WHERE id = $1", &[&album_id])
+        .query_opt("SELECT * FROM albums WHERE id = $1 AND deleted_at IS NULL", &[&album_id])
         .await?;
-    println!("get_album_by_id {:?}", result);
-    Ok(Album::from_row_ref(&result)?)
+    match result {
+        Some(item) => Ok(Album::from_row_ref(&item)?),
+        None => Err(DBError::NotFound { err: format!("Album {} not found", album_id) }),
+    }
 }
 
-
-pub async fn delete_album(
+// The album with the role the user was invited with, None if not a member
+pub async fn get_album_with_role(
     client: &deadpool_postgres::Client,
     album_id: i32,
-) -> Result<Album, DBError> {
-    client.query("DELETE FROM image_metas WHERE album_id = $1", &[&album_id]).await?;   // need to delete all photos from the album firstly
+    user_id: i32,
+) -> Result<(Album, Option<AlbumRole>), DBError> {
     let result = client
-        .query_one("DELETE FROM albums WHERE id=$1 RETURNING *", &[&album_id])
+        .query_opt(
+            "SELECT albums.*, album_members.role AS member_role FROM albums
+             LEFT JOIN album_members ON album_members.album_id = albums.id AND album_members.users_id = $2
+             WHERE albums.id = $1 AND albums.deleted_at IS NULL",
+            &[&album_id, &user_id],
+        )
         .await?;
-    Ok(Album::from_row_ref(&result)?)
+    match result {
+        Some(item) => Ok((Album::from_row_ref(&item)?, item.try_get("member_role")?)),
+        None => Err(DBError::NotFound { err: format!("Album {} not found", album_id) }),
+    }
 }
 
-pub async fn update_album(
+// Same for the album of a photo
+pub async fn get_album_of_photo(
     client: &deadpool_postgres::Client,
-    album_id: i32,
-    album: &UpdateAlbum,
-) -> Result<Album, DBError> {
+    photo_id: &i32,
+    user_id: i32,
+) -> Result<(Album, Option<AlbumRole>), DBError> {
     let result = client
-        .query_one(
-            "UPDATE albums SET title=$1, description=$2 WHERE id=$3 RETURNING *",
-            &[&album.title, &album.description, &album_id],
+        .query_opt(
+            "SELECT albums.*, album_members.role AS member_role FROM albums
+             JOIN image_metas ON image_metas.album_id = albums.id
+             LEFT JOIN album_members ON album_members.album_id = albums.id AND album_members.users_id = $2
+             WHERE image_metas.id = $1 AND albums.deleted_at IS NULL AND image_metas.deleted_at IS NULL",
+            &[photo_id, &user_id],
         )
         .await?;
-    Ok(Album::from_row_ref(&result)?)
+    match result {
+        Some(item) => Ok((Album::from_row_ref(&item)?, item.try_get("member_role")?)),
+        None => Err(DBError::NotFound { err: format!("Photo {} not found", photo_id) }),
+    }
 }
 
-pub async fn get_all_users(
-    client: &deadpool_postgres::Client,
-) -> Result<Vec<SendUser>, DBError> {
-    let result = client
-        .query("SELECT id, username, nickname, role FROM users ", &[])
-        .await
-        .expect("ERROR GETTING USERS")
-        .iter()
-        .map(|row| SendUser::from_row_ref(row).unwrap())
-        .collect::<Vec<SendUser>>();
+const ALBUM_MEMBER_COLUMNS: &str = "album_members.users_id, users.username, users.nickname,
+    album_members.role, album_members.invited_by, album_members.created_at";
 
-    Ok(result)
+pub async fn get_album_members(
+    client: &deadpool_postgres::Client,
+    album_id: i32,
+) -> Result<Vec<AlbumMember>, DBError> {
+    let rows = client
+        .query(
+            format!(
+                "SELECT {} FROM album_members JOIN users ON users.id = album_members.users_id
+                 WHERE album_members.album_id = $1 ORDER BY album_members.created_at",
+                ALBUM_MEMBER_COLUMNS
+            )
+            .as_str(),
+            &[&album_id],
+        )
+        .await?;
+    let mut members = Vec::new();
+    for row in &rows {
+        members.push(AlbumMember::from_row_ref(row)?);
+    }
+    Ok(members)
 }
 
-// tag photo + set coordinats
-pub async fn tag_photo_by_id(
-    client: deadpool_postgres::Client,
+// BadArgs if the user already is a member
+pub async fn add_album_member(
+    client: &deadpool_postgres::Client,
+    album_id: i32,
+    user_id: i32,
+    role: AlbumRole,
+    invited_by: i32,
+) -> Result<AlbumMember, DBError> {
+    let result = client
+        .query_opt(
+            format!(
+                "WITH album_members AS (
+                     INSERT INTO album_members (album_id, users_id, role, invited_by) VALUES ($1, $2, $3, $4)
+                     ON CONFLICT (album_id, users_id) DO NOTHING RETURNING *
+                 )
+                 SELECT {} FROM album_members JOIN users ON users.id = album_members.users_id",
+                ALBUM_MEMBER_COLUMNS
+            )
+            .as_str(),
+            &[&album_id, &user_id, &role, &invited_by],
+        )
+        .await?;
+    match result {
+        Some(item) => Ok(AlbumMember::from_row_ref(&item)?),
+        None => Err(DBError::BadArgs { err: format!("User {} already is a member of album {}", user_id, album_id) }),
+    }
+}
+
+pub async fn update_album_member(
+    client: &deadpool_postgres::Client,
+    album_id: i32,
+    user_id: i32,
+    role: AlbumRole,
+) -> Result<AlbumMember, DBError> {
+    let result = client
+        .query_opt(
+            format!(
+                "WITH album_members AS (
+                     UPDATE album_members SET role = $3 WHERE album_id = $1 AND users_id = $2 RETURNING *
+                 )
+                 SELECT {} FROM album_members JOIN users ON users.id = album_members.users_id",
+                ALBUM_MEMBER_COLUMNS
+            )
+            .as_str(),
+            &[&album_id, &user_id, &role],
+        )
+        .await?;
+    match result {
+        Some(item) => Ok(AlbumMember::from_row_ref(&item)?),
+        None => Err(DBError::NotFound { err: format!("User {} is no member of album {}", user_id, album_id) }),
+    }
+}
+
+pub async fn delete_album_member(
+    client: &deadpool_postgres::Client,
+    album_id: i32,
+    user_id: i32,
+) -> Result<(), DBError> {
+    let deleted = client
+        .execute("DELETE FROM album_members WHERE album_id = $1 AND users_id = $2", &[&album_id, &user_id])
+        .await?;
+    if deleted == 0 {
+        return Err(DBError::NotFound { err: format!("User {} is no member of album {}", user_id, album_id) });
+    }
+    Ok(())
+}
+
+// Albums of other users the user was invited to
+pub async fn get_assigned_albums(
+    client: &deadpool_postgres::Client,
+    user_id: i32,
+) -> Result<Vec<AssignedAlbum>, DBError> {
+    let rows = client
+        .query(
+            "SELECT albums.*, album_members.role AS member_role FROM albums
+             JOIN album_members ON album_members.album_id = albums.id
+             WHERE album_members.users_id = $1 AND albums.deleted_at IS NULL
+             ORDER BY albums.id",
+            &[&user_id],
+        )
+        .await?;
+    let mut albums = Vec::new();
+    for row in &rows {
+        albums.push(AssignedAlbum {
+            album: Album::from_row_ref(row)?,
+            role: row.try_get("member_role")?,
+        });
+    }
+    Ok(albums)
+}
+
+
+// Permanently deletes the album with its photos and members, the album
+// folder is queued for deletion in the same transaction
+pub async fn delete_album(
+    client: &mut deadpool_postgres::Client,
+    album_id: i32,
+) -> Result<Album, DBError> {
+    let transaction = client.transaction().await?;
+    // Uploads to the album wait and fail afterwards, they can not add photos
+    // between deleting the photos and the album
+    transaction.execute("SELECT pg_advisory_xact_lock($1, $2)", &[&ALBUM_FILES_LOCK, &album_id]).await?;
+    transaction.execute("DELETE FROM album_members WHERE album_id = $1", &[&album_id]).await?;
+    transaction.execute("DELETE FROM image_metas WHERE album_id = $1", &[&album_id]).await?;
+    let result = transaction
+        .query_opt("DELETE FROM albums WHERE id=$1 RETURNING *", &[&album_id])
+        .await?;
+    let album = match result {
+        Some(item) => Album::from_row_ref(&item)?,
+        None => return Err(DBError::NotFound { err: format!("Album {} not found", album_id) }),
+    };
+    transaction.execute("INSERT INTO file_deletions (album_id) VALUES ($1)", &[&album_id]).await?;
+    transaction.commit().await?;
+    Ok(album)
+}
+
+// Moves the album with its photos to the trash
+pub async fn trash_album(
+    client: &deadpool_postgres::Client,
+    album_id: i32,
+) -> Result<Album, DBError> {
+    let result = client
+        .query_opt(
+            "UPDATE albums SET deleted_at = now() WHERE id = $1 AND deleted_at IS NULL RETURNING *",
+            &[&album_id],
+        )
+        .await?;
+    match result {
+        Some(item) => Ok(Album::from_row_ref(&item)?),
+        None => Err(DBError::NotFound { err: format!("Album {} not found", album_id) }),
+    }
+}
+
+// Album in the trash, to check the owner before restoring it
+pub async fn get_trashed_album(
+    client: &deadpool_postgres::Client,
+    album_id: i32,
+) -> Result<Album, DBError> {
+    let result = client
+        .query_opt("SELECT * FROM albums WHERE id = $1 AND deleted_at IS NOT NULL", &[&album_id])
+        .await?;
+    match result {
+        Some(item) => Ok(Album::from_row_ref(&item)?),
+        None => Err(DBError::NotFound { err: format!("Album {} not found in trash", album_id) }),
+    }
+}
+
+pub async fn restore_album(
+    client: &deadpool_postgres::Client,
+    album_id: i32,
+) -> Result<Album, DBError> {
+    let result = client
+        .query_opt(
+            "UPDATE albums SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL RETURNING *",
+            &[&album_id],
+        )
+        .await?;
+    match result {
+        Some(item) => Ok(Album::from_row_ref(&item)?),
+        None => Err(DBError::NotFound { err: format!("Album {} not found in trash", album_id) }),
+    }
+}
+
+// Moves the photo to the trash, a tagging lock on it is released
+pub async fn trash_image_meta(
+    client: &deadpool_postgres::Client,
+    album_id: &i32,
+    image_meta_id: &i32,
+) -> Result<(), DBError> {
+    let trashed = client
+        .execute(
+            "UPDATE image_metas SET deleted_at = now(), locked_at = 0, locked_by = NULL
+             WHERE id = $1 AND album_id = $2 AND deleted_at IS NULL",
+            &[&image_meta_id, &album_id],
+        )
+        .await?;
+    if trashed == 0 {
+        return Err(DBError::NotFound { err: format!("Image {} not found in album {}", image_meta_id, album_id) });
+    }
+    Ok(())
+}
+
+pub async fn restore_image_meta(
+    client: &deadpool_postgres::Client,
+    album_id: &i32,
+    image_meta_id: &i32,
+) -> Result<(), DBError> {
+    let restored = client
+        .execute(
+            "UPDATE image_metas SET deleted_at = NULL WHERE id = $1 AND album_id = $2 AND deleted_at IS NOT NULL",
+            &[&image_meta_id, &album_id],
+        )
+        .await?;
+    if restored == 0 {
+        return Err(DBError::NotFound {
+            err: format!("Image {} not found in trash of album {}", image_meta_id, album_id),
+        });
+    }
+    Ok(())
+}
+
+// Albums and photos in the trash of the user, of all users if None
+pub async fn get_trash(
+    client: &deadpool_postgres::Client,
+    user_id: Option<i32>,
+    retention_days: i32,
+) -> Result<TrashContent, DBError> {
+    let album_rows = client
+        .query(
+            "SELECT id, title, description, users_id, deleted_at, deleted_at + make_interval(days => $2) AS purge_at
+             FROM albums WHERE deleted_at IS NOT NULL AND ($1::INT IS NULL OR users_id = $1) ORDER BY deleted_at DESC",
+            &[&user_id, &retention_days],
+        )
+        .await?;
+    let photo_rows = client
+        .query(
+            "SELECT image_metas.id, image_metas.album_id, image_metas.file_path, image_metas.original_filename,
+                    image_metas.deleted_at, image_metas.deleted_at + make_interval(days => $2) AS purge_at
+             FROM image_metas JOIN albums ON albums.id = image_metas.album_id
+             WHERE image_metas.deleted_at IS NOT NULL AND albums.deleted_at IS NULL
+               AND ($1::INT IS NULL OR albums.users_id = $1)
+             ORDER BY image_metas.deleted_at DESC",
+            &[&user_id, &retention_days],
+        )
+        .await?;
+    let mut trash = TrashContent {
+        albums: Vec::new(),
+        photos: Vec::new(),
+    };
+    for row in album_rows.iter() {
+        trash.albums.push(TrashedAlbum::from_row_ref(row)?);
+    }
+    for row in photo_rows.iter() {
+        trash.photos.push(TrashedPhoto::from_row_ref(row)?);
+    }
+    Ok(trash)
+}
+
+// Ids of albums and photos that have been in the trash for longer than the retention period
+pub async fn get_expired_trash(
+    client: &deadpool_postgres::Client,
+    retention_days: i32,
+    limit: i64,
+) -> Result<(Vec<i32>, Vec<i32>), DBError> {
+    let albums = client
+        .query(
+            "SELECT id FROM albums WHERE deleted_at < now() - make_interval(days => $1) ORDER BY id LIMIT $2",
+            &[&retention_days, &limit],
+        )
+        .await?;
+    let photos = client
+        .query(
+            "SELECT id FROM image_metas WHERE deleted_at < now() - make_interval(days => $1) ORDER BY id LIMIT $2",
+            &[&retention_days, &limit],
+        )
+        .await?;
+    Ok((
+        albums.iter().map(|row| row.get(0)).collect(),
+        photos.iter().map(|row| row.get(0)).collect(),
+    ))
+}
+
+// Set the photo counters of all albums to the actual numbers, returns the
+// albums whose counters were wrong
+pub async fn recompute_album_counters(
+    client: &deadpool_postgres::Client,
+) -> Result<Vec<Album>, DBError> {
+    let rows = client
+        .query(
+            "UPDATE albums SET
+                 image_number = counts.image_number,
+                 tagged_number = counts.tagged_number,
+                 verified_number = counts.verified_number
+             FROM (
+                 SELECT albums.id,
+                        COUNT(image_metas.id)::INT AS image_number,
+                        COUNT(image_metas.id) FILTER (WHERE image_metas.tagged)::INT AS tagged_number,
+                        COUNT(image_metas.id) FILTER (WHERE image_metas.verified)::INT AS verified_number
+                 FROM albums LEFT JOIN image_metas
+                     ON image_metas.album_id = albums.id AND image_metas.deleted_at IS NULL
+                 GROUP BY albums.id
+             ) AS counts
+             WHERE albums.id = counts.id AND (albums.image_number <> counts.image_number
+                 OR albums.tagged_number <> counts.tagged_number OR albums.verified_number <> counts.verified_number)
+             RETURNING albums.*",
+            &[],
+        )
+        .await?;
+    let mut albums = Vec::new();
+    for row in rows.iter() {
+        albums.push(Album::from_row_ref(row)?);
+    }
+    Ok(albums)
+}
+
+// Oldest queued file deletions that have not failed too often
+pub async fn get_file_deletions(
+    client: &deadpool_postgres::Client,
+    max_attempts: i32,
+    limit: i64,
+) -> Result<Vec<FileDeletion>, DBError> {
+    let rows = client
+        .query(
+            "SELECT id, album_id, file_path, attempts, last_error FROM file_deletions
+             WHERE attempts < $1 ORDER BY id LIMIT $2",
+            &[&max_attempts, &limit],
+        )
+        .await?;
+    let mut deletions = Vec::new();
+    for row in rows {
+        deletions.push(FileDeletion::from_row_ref(&row)?);
+    }
+    Ok(deletions)
+}
+
+pub async fn get_file_deletions_of_album(
+    client: &deadpool_postgres::Client,
+    album_id: &i32,
+) -> Result<Vec<FileDeletion>, DBError> {
+    let rows = client
+        .query(
+            "SELECT id, album_id, file_path, attempts, last_error FROM file_deletions WHERE album_id = $1",
+            &[&album_id],
+        )
+        .await?;
+    let mut deletions = Vec::new();
+    for row in rows {
+        deletions.push(FileDeletion::from_row_ref(&row)?);
+    }
+    Ok(deletions)
+}
+
+pub async fn finish_file_deletion(
+    client: &deadpool_postgres::Client,
+    deletion_id: &i32,
+) -> Result<(), DBError> {
+    client.execute("DELETE FROM file_deletions WHERE id = $1", &[&deletion_id]).await?;
+    Ok(())
+}
+
+pub async fn fail_file_deletion(
+    client: &deadpool_postgres::Client,
+    deletion_id: &i32,
+    err: &str,
+) -> Result<(), DBError> {
+    client
+        .execute(
+            "UPDATE file_deletions SET attempts = attempts + 1, last_error = $1 WHERE id = $2",
+            &[&err, &deletion_id],
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn update_album(
+    client: &deadpool_postgres::Client,
+    album_id: i32,
+    album: &UpdateAlbum,
+) -> Result<Album, DBError> {
+    let result = client
+        .query_one(
+            "UPDATE albums SET title=$1, description=$2, strip_metadata=COALESCE($3, strip_metadata),
+             visibility=COALESCE($5, visibility) WHERE id=$4 RETURNING *",
+            &[&album.title, &album.description, &album.strip_metadata, &album_id, &album.visibility],
+        )
+        .await?;
+    Ok(Album::from_row_ref(&result)?)
+}
+
+fn validate_tag(tag: &str) -> Result<(), DBError> {
+    if tag.trim().is_empty() || tag.chars().count() > 100 {
+        return Err(DBError::BadArgs {
+            err: format!("Tag '{}' must be between 1 and 100 characters long", tag),
+        });
+    }
+    Ok(())
+}
+
+// Labels allowed for annotations of the album the photo belongs to
+pub async fn get_album_tags_of_photo(
+    client: &deadpool_postgres::Client,
+    photo_id: &i32,
+) -> Result<Vec<String>, DBError> {
+    let result = client
+        .query_one(
+            "SELECT albums.tags FROM albums JOIN image_metas ON image_metas.album_id = albums.id WHERE image_metas.id = $1",
+            &[&photo_id],
+        )
+        .await?;
+    let tags: Option<Vec<String>> = result.get(0);
+    Ok(tags.unwrap_or_default())
+}
+
+async fn lock_album_tags(
+    transaction: &deadpool_postgres::Transaction<'_>,
+    album_id: i32,
+) -> Result<Vec<String>, DBError> {
+    let result = transaction
+        .query_one("SELECT tags FROM albums WHERE id = $1 FOR UPDATE", &[&album_id])
+        .await?;
+    let tags: Option<Vec<String>> = result.get(0);
+    Ok(tags.unwrap_or_default())
+}
+
+async fn store_album_tags(
+    transaction: &deadpool_postgres::Transaction<'_>,
+    album_id: i32,
+    tags: &[String],
+) -> Result<Album, DBError> {
+    let result = transaction
+        .query_one(
+            "UPDATE albums SET tags = $1, date_modified = CURRENT_DATE WHERE id = $2 RETURNING *",
+            &[&tags, &album_id],
+        )
+        .await?;
+    Ok(Album::from_row_ref(&result)?)
+}
+
+pub async fn add_album_tag(
+    client: &mut deadpool_postgres::Client,
+    album_id: i32,
+    tag: &str,
+) -> Result<Album, DBError> {
+    validate_tag(tag)?;
+
+    let transaction = client.transaction().await?;
+    let mut tags = lock_album_tags(&transaction, album_id).await?;
+    if tags.iter().any(|t| t == tag) {
+        return Err(DBError::BadArgs {
+            err: format!("Tag '{}' already exists in album {}", tag, album_id),
+        });
+    }
+    tags.push(tag.to_string());
+    let album = store_album_tags(&transaction, album_id, &tags).await?;
+    transaction.commit().await?;
+    Ok(album)
+}
+
+pub async fn rename_album_tag(
+    client: &mut deadpool_postgres::Client,
+    album_id: i32,
+    from: &str,
+    to: &str,
+) -> Result<Album, DBError> {
+    validate_tag(to)?;
+
+    let transaction = client.transaction().await?;
+    let mut tags = lock_album_tags(&transaction, album_id).await?;
+    if !tags.iter().any(|t| t == from) {
+        return Err(DBError::BadArgs {
+            err: format!("Tag '{}' does not exist in album {}", from, album_id),
+        });
+    }
+    if tags.iter().any(|t| t == to) {
+        return Err(DBError::BadArgs {
+            err: format!("Tag '{}' already exists in album {}, merge the tags instead", to, album_id),
+        });
+    }
+    for tag in tags.iter_mut().filter(|t| *t == from) {
+        *tag = to.to_string();
+    }
+    transaction
+        .execute(
+            "UPDATE annotations SET tag = $1, date_modified = CURRENT_DATE FROM image_metas
+             WHERE annotations.image_id = image_metas.id AND image_metas.album_id = $2 AND annotations.tag = $3",
+            &[&to, &album_id, &from],
+        )
+        .await?;
+    let album = store_album_tags(&transaction, album_id, &tags).await?;
+    transaction.commit().await?;
+    Ok(album)
+}
+
+pub async fn merge_album_tags(
+    client: &mut deadpool_postgres::Client,
+    album_id: i32,
+    from: &[String],
+    into: &str,
+) -> Result<Album, DBError> {
+    validate_tag(into)?;
+
+    let transaction = client.transaction().await?;
+    let mut tags = lock_album_tags(&transaction, album_id).await?;
+    if let Some(unknown) = from.iter().find(|f| !tags.contains(f)) {
+        return Err(DBError::BadArgs {
+            err: format!("Tag '{}' does not exist in album {}", unknown, album_id),
+        });
+    }
+    tags.retain(|t| !from.contains(t) || t == into);
+    if !tags.iter().any(|t| t == into) {
+        tags.push(into.to_string());
+    }
+    transaction
+        .execute(
+            "UPDATE annotations SET tag = $1, date_modified = CURRENT_DATE FROM image_metas
+             WHERE annotations.image_id = image_metas.id AND image_metas.album_id = $2 AND annotations.tag = ANY($3)",
+            &[&into, &album_id, &from],
+        )
+        .await?;
+    let album = store_album_tags(&transaction, album_id, &tags).await?;
+    transaction.commit().await?;
+    Ok(album)
+}
+
+// Removes the tag and every annotation using it. Photos left without
+// annotations go back into the tagging queue.
+pub async fn retire_album_tag(
+    client: &mut deadpool_postgres::Client,
+    album_id: i32,
+    tag: &str,
+) -> Result<Album, DBError> {
+    let transaction = client.transaction().await?;
+    let mut tags = lock_album_tags(&transaction, album_id).await?;
+    if !tags.iter().any(|t| t == tag) {
+        return Err(DBError::BadArgs {
+            err: format!("Tag '{}' does not exist in album {}", tag, album_id),
+        });
+    }
+    tags.retain(|t| t != tag);
+
+    let affected: Vec<i32> = transaction
+        .query(
+            "DELETE FROM annotations USING image_metas
+             WHERE annotations.image_id = image_metas.id AND image_metas.album_id = $1 AND annotations.tag = $2
+             RETURNING annotations.image_id",
+            &[&album_id, &tag],
+        )
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+    transaction
+        .execute(
+            "UPDATE image_metas SET tagged = false, verified = false
+             WHERE id = ANY($1) AND NOT EXISTS (SELECT 1 FROM annotations WHERE annotations.image_id = image_metas.id)",
+            &[&affected],
+        )
+        .await?;
+    let album = store_album_tags(&transaction, album_id, &tags).await?;
+    transaction.commit().await?;
+    Ok(album)
+}
+
+pub async fn get_all_users(
+    client: &deadpool_postgres::Client,
+) -> Result<Vec<SendUser>, DBError> {
+    let result = client
+        .query("SELECT id, username, nickname, role FROM users ", &[])
+        .await
+        .expect("ERROR GETTING USERS")
+        .iter()
+        .map(|row| SendUser::from_row_ref(row).unwrap())
+        .collect::<Vec<SendUser>>();
+
+    Ok(result)
+}
+
+fn annotation_from_row(row: &tokio_postgres::Row) -> Annotation {
+    let coordinates: Json<Geometry> = row.get("coordinates");
+    Annotation {
+        id: row.get("id"),
+        image_id: row.get("image_id"),
+        tag: row.get("tag"),
+        coordinates: coordinates.0,
+    }
+}
+
+// Lock state of a photo for the given user, a lock is handed out by
+// get_photos_for_tagging and is valid for `lease` seconds after the last renewal
+pub async fn get_photo_lock(
+    client: &deadpool_postgres::Client,
     id: &i32,
-    photo_data: &TagPhoto
-) -> Result<bool, DBError> {
+    user_id: i32,
+    lease: i64,
+) -> Result<PhotoLock, DBError> {
     let current_time = Utc::now().timestamp();
-    let offset: i64 = 30; // 15 min in sec
-    
-    
-    let result = client.query_one("SELECT locked_at FROM image_metas WHERE id = $1",&[&id],).await?;
-    
 
-    if (&result.get(0) + &offset) > current_time {
-        client
+    let result = client.query_one("SELECT locked_at, locked_by FROM image_metas WHERE id = $1",&[&id],).await?;
+    let locked_at: i64 = result.get(0);
+    let locked_by: Option<i32> = result.get(1);
+    if locked_at + lease <= current_time {
+        Ok(PhotoLock::Expired)
+    } else if locked_by == Some(user_id) {
+        Ok(PhotoLock::Held)
+    } else {
+        Ok(PhotoLock::HeldByOther)
+    }
+}
+
+// Extend the lock of a photo the user still holds, None if the lock was lost
+pub async fn renew_photo_lock(
+    client: &deadpool_postgres::Client,
+    id: &i32,
+    user_id: i32,
+    lease: i64,
+) -> Result<Option<LockLease>, DBError> {
+    let current_time = Utc::now().timestamp();
+
+    let result = client
+        .query_opt(
+            "UPDATE image_metas SET locked_at = $1 WHERE id = $2 AND locked_by = $3 AND locked_at > $4 RETURNING locked_at",
+            &[&current_time, &id, &user_id, &(current_time - lease)],
+        )
+        .await?;
+    Ok(result.map(|_| LockLease {
+        photo_id: *id,
+        locked_until: Utc.timestamp(current_time + lease, 0),
+    }))
+}
+
+// Give a photo back to the tagging queue, false if the user did not hold the lock
+pub async fn release_photo_lock(
+    client: &deadpool_postgres::Client,
+    id: &i32,
+    user_id: i32,
+) -> Result<bool, DBError> {
+    let released = client
+        .execute(
+            "UPDATE image_metas SET locked_at = 0, locked_by = NULL WHERE id = $1 AND locked_by = $2",
+            &[&id, &user_id],
+        )
+        .await?;
+    Ok(released > 0)
+}
+
+// Row lock on the photo inside a transaction, false if the user lost the tagging lock
+async fn hold_photo_lock(
+    transaction: &deadpool_postgres::Transaction<'_>,
+    id: &i32,
+    user_id: i32,
+    lease: i64,
+) -> Result<bool, DBError> {
+    let time_after_offset = Utc::now().timestamp() - lease;
+    let result = transaction
+        .query_opt(
+            "SELECT id FROM image_metas WHERE id = $1 AND locked_by = $2 AND locked_at > $3 AND deleted_at IS NULL
+             AND album_id IN (SELECT id FROM albums WHERE deleted_at IS NULL) FOR UPDATE",
+            &[&id, &user_id, &time_after_offset],
+        )
+        .await?;
+    Ok(result.is_some())
+}
+
+pub async fn get_annotations_of_photos(
+    client: &deadpool_postgres::Client,
+    photo_ids: &[i32],
+) -> Result<Vec<Annotation>, DBError> {
+    let annotations = client
         .query(
-            "UPDATE image_metas SET tag = $1, coordinates = $2, tagged = true, locked_at = 0 WHERE id = $3 ", // reset timer if tagged
-            &[&photo_data.tag, &photo_data.coordinates, &id],
+            "SELECT id, image_id, tag, coordinates FROM annotations WHERE image_id = ANY($1) ORDER BY id",
+            &[&photo_ids],
         )
+        .await?
+        .iter()
+        .map(annotation_from_row)
+        .collect();
+    Ok(annotations)
+}
+
+// Error for annotation changes of a tagger whose lock ran out or was taken over
+fn lock_lost() -> DBError {
+    DBError::BadArgs { err: "timeout".to_string() }
+}
+
+pub async fn create_annotation(
+    client: &mut deadpool_postgres::Client,
+    photo_id: &i32,
+    user_id: i32,
+    lease: i64,
+    annotation: &CreateAnnotation,
+) -> Result<Annotation, DBError> {
+    let transaction = client.transaction().await?;
+    if !hold_photo_lock(&transaction, photo_id, user_id, lease).await? {
+        return Err(lock_lost());
+    }
+
+    let result = transaction
+        .query_one(
+            "INSERT INTO annotations (image_id, tag, coordinates, users_id) VALUES ($1, $2, $3, $4) RETURNING *",
+            &[&photo_id, &annotation.tag, &Json(&annotation.coordinates), &user_id],
+        )
+        .await?;
+    transaction
+        .execute("UPDATE image_metas SET tagged = true WHERE id = $1", &[&photo_id])
         .await?;
+    transaction.commit().await?;
+    Ok(annotation_from_row(&result))
+}
 
-        Ok(true)
-    }else {
-        Ok(false)
+// None if the annotation does not belong to the photo
+pub async fn update_annotation(
+    client: &mut deadpool_postgres::Client,
+    photo_id: &i32,
+    annotation_id: &i32,
+    user_id: i32,
+    lease: i64,
+    annotation: &CreateAnnotation,
+) -> Result<Option<Annotation>, DBError> {
+    let transaction = client.transaction().await?;
+    if !hold_photo_lock(&transaction, photo_id, user_id, lease).await? {
+        return Err(lock_lost());
     }
+
+    let result = transaction
+        .query_opt(
+            "UPDATE annotations SET tag = $1, coordinates = $2, users_id = $3, date_modified = CURRENT_DATE WHERE id = $4 AND image_id = $5 RETURNING *",
+            &[&annotation.tag, &Json(&annotation.coordinates), &user_id, &annotation_id, &photo_id],
+        )
+        .await?;
+    transaction.commit().await?;
+    Ok(result.as_ref().map(annotation_from_row))
 }
 
-// verify photo ( if true => set verify true, else delete tag and coordinates & set both verified and tagged as false)
-pub async fn verify_photo_by_id(
-    client: deadpool_postgres::Client,
-    id: &i32,
-    verified: bool
+// false if the annotation does not belong to the photo
+pub async fn delete_annotation(
+    client: &mut deadpool_postgres::Client,
+    photo_id: &i32,
+    annotation_id: &i32,
+    user_id: i32,
+    lease: i64,
 ) -> Result<bool, DBError> {
-    let current_time = Utc::now().timestamp();
-    let offset: i64 = 30;   //15 min in sec
-    
-    let result = client.query_one("SELECT locked_at FROM image_metas WHERE id = $1",&[&id],).await?;
-    if(&result.get(0) + &offset) > current_time {
-        if verified {
-            client
-            .query(
-                "UPDATE image_metas SET verified = true, locked_at = 0 WHERE id = $1 ",    // reset timer
-                &[ &id],
+    let transaction = client.transaction().await?;
+    if !hold_photo_lock(&transaction, photo_id, user_id, lease).await? {
+        return Err(lock_lost());
+    }
+
+    let deleted = transaction
+        .execute(
+            "DELETE FROM annotations WHERE id = $1 AND image_id = $2",
+            &[&annotation_id, &photo_id],
+        )
+        .await?;
+    transaction
+        .execute(
+            "UPDATE image_metas SET tagged = EXISTS (SELECT 1 FROM annotations WHERE image_id = $1) WHERE id = $1",
+            &[&photo_id],
+        )
+        .await?;
+    transaction.commit().await?;
+    Ok(deleted > 0)
+}
+
+// Imported annotations replace those of an unverified photo, which then waits
+// for review. Photos verified in the meantime or held by a tagger are left alone.
+pub async fn import_annotations(
+    client: &mut deadpool_postgres::Client,
+    photo_id: &i32,
+    user_id: i32,
+    lease: i64,
+    annotations: &[CreateAnnotation],
+) -> Result<PhotoImport, DBError> {
+    let transaction = client.transaction().await?;
+    // Taggers claiming photos skip the locked row until the import is committed
+    let row = transaction
+        .query_opt(
+            "SELECT verified, locked_at, locked_by FROM image_metas WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+            &[&photo_id],
+        )
+        .await?;
+    let row = match row {
+        Some(item) => item,
+        None => return Err(DBError::NotFound { err: format!("Image {} not found", photo_id) }),
+    };
+    let verified: bool = row.get(0);
+    let locked_at: i64 = row.get(1);
+    let locked_by: Option<i32> = row.get(2);
+    if verified {
+        return Ok(PhotoImport::Verified);
+    }
+    if locked_by.is_some() && locked_at + lease > Utc::now().timestamp() {
+        return Ok(PhotoImport::Locked);
+    }
+
+    transaction
+        .execute("UPDATE image_metas SET tagged = true WHERE id = $1", &[&photo_id])
+        .await?;
+    transaction
+        .execute("DELETE FROM annotations WHERE image_id = $1", &[&photo_id])
+        .await?;
+    for annotation in annotations {
+        transaction
+            .execute(
+                "INSERT INTO annotations (image_id, tag, coordinates, users_id) VALUES ($1, $2, $3, $4)",
+                &[&photo_id, &annotation.tag, &Json(&annotation.coordinates), &user_id],
             )
             .await?;
-        } else {
-            client
-            .query(
-                "UPDATE image_metas SET tag = '', coordinates = '', tagged = false, verified = false, locked_at = 0 WHERE id = $1 ", // reset timer
-                &[ &id],
+    }
+    transaction.commit().await?;
+    Ok(PhotoImport::Imported)
+}
+
+// replace all annotations of a photo and mark it as tagged
+pub async fn tag_photo_by_id(
+    mut client: deadpool_postgres::Client,
+    id: &i32,
+    user_id: i32,
+    lease: i64,
+    photo_data: &TagPhoto
+) -> Result<bool, DBError> {
+    let transaction = client.transaction().await?;
+    if !hold_photo_lock(&transaction, id, user_id, lease).await? {
+        return Ok(false);
+    }
+
+    transaction.execute("DELETE FROM annotations WHERE image_id = $1", &[&id]).await?;
+    for annotation in &photo_data.annotations {
+        transaction
+            .execute(
+                "INSERT INTO annotations (image_id, tag, coordinates, users_id) VALUES ($1, $2, $3, $4)",
+                &[&id, &annotation.tag, &Json(&annotation.coordinates), &user_id],
             )
             .await?;
-        }
-        Ok(true)
-    }else {
-        Ok(false)
     }
-    
-    
+    transaction
+        .execute(
+            "UPDATE image_metas SET tagged = true, locked_at = 0, locked_by = NULL WHERE id = $1 ", // release lock if tagged
+            &[&id],
+        )
+        .await?;
+    transaction.commit().await?;
+    Ok(true)
 }
 
-//get photos for tagging
+// verify photo ( if true => set verify true, else delete annotations & set both verified and tagged as false)
+pub async fn verify_photo_by_id(
+    mut client: deadpool_postgres::Client,
+    id: &i32,
+    user_id: i32,
+    lease: i64,
+    verified: bool
+) -> Result<bool, DBError> {
+    let transaction = client.transaction().await?;
+    if !hold_photo_lock(&transaction, id, user_id, lease).await? {
+        return Ok(false);
+    }
+
+    if verified {
+        transaction
+        .execute(
+            "UPDATE image_metas SET verified = true, locked_at = 0, locked_by = NULL WHERE id = $1 ",    // release lock
+            &[ &id],
+        )
+        .await?;
+    } else {
+        transaction.execute("DELETE FROM annotations WHERE image_id = $1", &[&id]).await?;
+        transaction
+        .execute(
+            "UPDATE image_metas SET tagged = false, verified = false, locked_at = 0, locked_by = NULL WHERE id = $1 ", // release lock
+            &[ &id],
+        )
+        .await?;
+    }
+    transaction.commit().await?;
+    Ok(true)
+}
+
+//get photos for tagging, photos the user already holds are handed out again.
+// Claiming is a single statement: rows another tagger is claiming at the same
+// moment are row locked and skipped, so a photo is never handed out twice.
 pub async fn get_photos_for_tagging(
-    client: deadpool_postgres::Client,
-    id: &i32
+    mut client: deadpool_postgres::Client,
+    id: &i32,
+    user_id: i32,
+    lease: i64,
+    batch_size: i64,
 ) -> Result<Vec<PhotoToTag>, DBError> {
-    let mut photos = Vec::new();
+    let photo_timestamp = Utc::now();
+    let current_time = photo_timestamp.timestamp();
+    let time_after_offset: i64 = current_time - lease;
 
-    let current_time = Utc::now().timestamp();
-    let offset: i64 = 30; // 15 min in sec
-    let time_after_offset: i64 = &current_time - &offset;
-    
-    
-    for row in client.query("SELECT id, file_path, tagged, tag  FROM image_metas WHERE album_id = $1 AND verified = false AND locked_at <= $2", &[&id, &time_after_offset]).await? {
-        
-        
-            let photo_timestamp = Utc::now();
-
-            let photo = PhotoToTag {
-                id: row.get(0),
-                file_path: row.get(1),
-                tagged: row.get(2),
-                tag: row.get(3),
-                timestamp: photo_timestamp
-            };
-            
-            client.query("UPDATE image_metas SET locked_at = $2 WHERE id = $1 ", &[&&photo.id, &photo.timestamp.timestamp()]).await?;
-
-            photos.push(photo);
-            if photos.len() >= 20 {
-                break;
-            }
-        
+    let transaction = client.transaction().await?;
+    let rows = transaction
+        .query(
+            "UPDATE image_metas SET locked_at = $1, locked_by = $2
+             FROM (
+                 SELECT id FROM image_metas
+                 WHERE album_id = $3 AND verified = false AND (locked_at <= $4 OR locked_by = $2) AND deleted_at IS NULL
+                   AND album_id IN (SELECT id FROM albums WHERE deleted_at IS NULL)
+                 ORDER BY id
+                 LIMIT $5
+                 FOR UPDATE SKIP LOCKED
+             ) AS claimable
+             WHERE image_metas.id = claimable.id
+             RETURNING image_metas.id, image_metas.file_path, image_metas.tagged, image_metas.width,
+                       image_metas.height, image_metas.mime_type, image_metas.byte_size, image_metas.checksum",
+            &[&current_time, &user_id, &id, &time_after_offset, &batch_size],
+        )
+        .await?;
+
+    let mut photos: Vec<PhotoToTag> = rows
+        .iter()
+        .map(|row| PhotoToTag {
+            id: row.get(0),
+            file_path: row.get(1),
+            tagged: row.get(2),
+            width: row.get(3),
+            height: row.get(4),
+            mime_type: row.get(5),
+            byte_size: row.get(6),
+            checksum: row.get(7),
+            annotations: Vec::new(),
+            timestamp: photo_timestamp,
+            locked_until: Utc.timestamp(current_time + lease, 0),
+        })
+        .collect();
+    photos.sort_by_key(|p| p.id);
+
+    let photo_ids: Vec<i32> = photos.iter().map(|p| p.id).collect();
+    let annotations = transaction
+        .query(
+            "SELECT id, image_id, tag, coordinates FROM annotations WHERE image_id = ANY($1) ORDER BY id",
+            &[&photo_ids],
+        )
+        .await?;
+    transaction.commit().await?;
+
+    for annotation in annotations.iter().map(annotation_from_row) {
+        if let Some(photo) = photos.iter_mut().find(|p| p.id == annotation.image_id) {
+            photo.annotations.push(annotation);
+        }
     }
     Ok(photos)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MyConfig;
+    use crate::album_models::Visibility;
+    use crate::test_fixtures;
+    use std::collections::HashSet;
+    use tokio_postgres::NoTls;
+
+    const TAGGERS: usize = 8;
+    const PHOTOS: usize = 100;
+
+    #[actix_rt::test]
+    #[ignore] // needs the PostgreSQL database configured in Settings.toml
+    async fn concurrent_taggers_never_share_photos() {
+        let conf = MyConfig::new("Settings").unwrap();
+        let pool = conf.postgres.create_pool(NoTls).unwrap();
+        let mut client = pool.get().await.unwrap();
+        crate::migrations::run_pending(&mut client).await.unwrap();
+
+        let mut users = Vec::new();
+        for i in 0..TAGGERS {
+            users.push(test_fixtures::create_user(&client, &format!("tagger{}", i)).await.id);
+        }
+        let album = create_album(&client, &CreateAlbum {
+            title: "concurrency".to_string(),
+            description: String::new(),
+            tags: vec!["car".to_string()],
+            strip_metadata: true,
+            visibility: Visibility::Private,
+        }, users[0], String::new()).await.unwrap();
+        let transaction = client.transaction().await.unwrap();
+        for _ in 0..PHOTOS {
+            test_fixtures::create_photo(&transaction, album.id).await;
+        }
+        transaction.commit().await.unwrap();
+
+        let claims = users.iter().map(|user_id| {
+            let pool = pool.clone();
+            let album_id = album.id;
+            let user_id = *user_id;
+            async move {
+                let client = pool.get().await.unwrap();
+                get_photos_for_tagging(client, &album_id, user_id, 900, 20).await.unwrap()
+            }
+        });
+        let results = futures::future::join_all(claims).await;
+
+        let mut seen = HashSet::new();
+        for photo in results.iter().flatten() {
+            assert!(seen.insert(photo.id), "photo {} was handed out twice", photo.id);
+        }
+        assert_eq!(seen.len(), PHOTOS.min(TAGGERS * 20));
+
+        test_fixtures::delete_users(&client, &users).await;
+    }
+}
@@ -0,0 +1,177 @@
+use crate::storage::{self, Storage};
+use crate::user_models::PhotoSize;
+
+use actix_web::web;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageOutputFormat, ImageResult};
+use log::error;
+use std::path::Path;
+
+/*
+ * Downscaled JPEG copies of album photos, used for previews.
+ *
+ * They are stored next to the album files as
+ *   <album id>_derivatives/<size>/<photo name>.jpg
+ * and generated on upload. Photos uploaded before are converted on first request.
+ */
+pub const DERIVATIVE_SIZES: [PhotoSize; 2] = [PhotoSize::Thumb, PhotoSize::Medium];
+
+pub fn derivative_key(album_id: i32, file_path: &str, size: PhotoSize) -> String {
+    let stem = Path::new(file_path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    format!("{}{}/{}.jpg", album_folder(album_id), size.name(), stem)
+}
+
+// Blocking
+fn render(image: &DynamicImage, max_edge: u32) -> ImageResult<Vec<u8>> {
+    // Small photos are only converted, never enlarged
+    let resized = if image.width() > max_edge || image.height() > max_edge {
+        image.resize(max_edge, max_edge, FilterType::Triangle)
+    } else {
+        image.clone()
+    };
+    let mut content = Vec::new();
+    DynamicImage::ImageRgb8(resized.to_rgb8()).write_to(&mut content, ImageOutputFormat::Jpeg(75))?;
+    Ok(content)
+}
+
+// Blocking
+fn render_all(content: &[u8]) -> ImageResult<Vec<(PhotoSize, Vec<u8>)>> {
+    let image = image::load_from_memory(content)?;
+    let mut derivatives = Vec::new();
+    for size in DERIVATIVE_SIZES.iter() {
+        if let Some(max_edge) = size.max_edge() {
+            derivatives.push((*size, render(&image, max_edge)?));
+        }
+    }
+    Ok(derivatives)
+}
+
+// Create all derivatives of a photo, replacing existing ones. Failures are
+// only logged, missing derivatives are created again on request.
+pub async fn generate(storage: &dyn Storage, album_id: i32, file_path: &str, content: Vec<u8>) {
+    let derivatives = match web::block(move || render_all(&content)).await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error creating derivatives of {}: {}", file_path, e);
+            return;
+        }
+    };
+    for (size, content) in derivatives {
+        let key = derivative_key(album_id, file_path, size);
+        if let Err(e) = storage.put(&key, content).await {
+            error!("Error storing derivative {}: {}", key, e);
+        }
+    }
+}
+
+// Key of the file to serve for the requested size, a missing derivative is
+// created first. Falls back to the original if that fails.
+pub async fn get_or_generate(storage: &dyn Storage, album_id: i32, file_path: &str, size: PhotoSize) -> String {
+    let original = storage::photo_key(album_id, file_path);
+    let max_edge = match size.max_edge() {
+        Some(item) => item,
+        None => return original,
+    };
+
+    let key = derivative_key(album_id, file_path, size);
+    match storage.exists(&key).await {
+        Ok(true) => return key,
+        Ok(false) => {}
+        Err(e) => {
+            error!("Error looking up derivative {}: {}", key, e);
+            return original;
+        }
+    }
+
+    let content = match storage.get(&original).await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error reading {} for derivative: {}", original, e);
+            return original;
+        }
+    };
+    let rendered = web::block(move || image::load_from_memory(&content).and_then(|image| render(&image, max_edge))).await;
+    let rendered = match rendered {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error creating derivative {}: {}", key, e);
+            return original;
+        }
+    };
+    if let Err(e) = storage.put(&key, rendered).await {
+        error!("Error storing derivative {}: {}", key, e);
+        return original;
+    }
+    key
+}
+
+pub fn album_folder(album_id: i32) -> String {
+    format!("{}_derivatives/", album_id)
+}
+
+pub async fn remove(storage: &dyn Storage, album_id: i32, file_path: &str) {
+    for size in DERIVATIVE_SIZES.iter() {
+        let key = derivative_key(album_id, file_path, *size);
+        if let Err(e) = storage.delete(&key).await {
+            error!("Error deleting derivative {}: {}", key, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::LocalStorage;
+    use image::RgbImage;
+
+    fn rendered_size(width: u32, height: u32, max_edge: u32) -> (u32, u32) {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(width, height));
+        let content = render(&image, max_edge).unwrap();
+        assert_eq!(image::guess_format(&content).unwrap(), image::ImageFormat::Jpeg);
+        image::load_from_memory(&content).unwrap().dimensions()
+    }
+
+    #[test]
+    fn renders_longest_edge_at_max_edge() {
+        assert_eq!(rendered_size(1000, 500, 256), (256, 128));
+        assert_eq!(rendered_size(300, 1200, 256), (64, 256));
+        // The shorter edge is rounded down
+        assert_eq!(rendered_size(1000, 333, 256), (256, 85));
+        assert_eq!(rendered_size(1000, 335, 256), (256, 85));
+    }
+
+    #[test]
+    fn never_enlarges() {
+        assert_eq!(rendered_size(100, 50, 256), (100, 50));
+        assert_eq!(rendered_size(256, 10, 256), (256, 10));
+    }
+
+    #[test]
+    fn keys_derivatives_by_album_size_and_photo() {
+        assert_eq!(derivative_key(3, "17.png", PhotoSize::Thumb), "3_derivatives/thumb/17.jpg");
+        assert_eq!(derivative_key(3, "17_00000000000000ff.jpeg", PhotoSize::Medium), "3_derivatives/medium/17_00000000000000ff.jpg");
+        assert_eq!(album_folder(3), "3_derivatives/");
+    }
+
+    #[actix_rt::test]
+    async fn creates_missing_derivatives_on_request() {
+        let root = std::env::temp_dir().join(format!("tagify_derivatives_{}", rand::random::<u64>()));
+        let storage = LocalStorage::new(root.to_str().unwrap());
+        let mut content = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(600, 300)).write_to(&mut content, ImageOutputFormat::Png).unwrap();
+        storage.put(&storage::photo_key(3, "17.png"), content).await.unwrap();
+
+        assert_eq!(get_or_generate(&storage, 3, "17.png", PhotoSize::Original).await, storage::photo_key(3, "17.png"));
+        let key = get_or_generate(&storage, 3, "17.png", PhotoSize::Thumb).await;
+        assert_eq!(key, derivative_key(3, "17.png", PhotoSize::Thumb));
+        let thumb = image::load_from_memory(&storage.get(&key).await.unwrap()).unwrap();
+        assert_eq!(thumb.dimensions(), (256, 128));
+
+        // Without the original the request falls back to it
+        assert_eq!(get_or_generate(&storage, 3, "18.png", PhotoSize::Medium).await, storage::photo_key(3, "18.png"));
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -17,6 +17,8 @@ pub enum HandlerError {
     AuthFail,
     #[fail(display = "Permission denied: {}", err_message)]
     PermissionDenied { err_message: String },
+    #[fail(display = "Not found: {}", err_message)]
+    NotFound { err_message: String },
 }
 
 impl ResponseError for HandlerError {
@@ -31,7 +33,8 @@ impl ResponseError for HandlerError {
             HandlerError::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
             HandlerError::BadClientData { .. } => StatusCode::BAD_REQUEST,
             HandlerError::AuthFail => StatusCode::UNAUTHORIZED,
-            HandlerError::PermissionDenied { .. } => StatusCode::UNAUTHORIZED,
+            HandlerError::PermissionDenied { .. } => StatusCode::FORBIDDEN,
+            HandlerError::NotFound { .. } => StatusCode::NOT_FOUND,
         }
     }
 }
@@ -52,6 +55,9 @@ pub enum DBError {
 
     #[fail(display = "Bad argument: {}", err)]
     BadArgs { err: String },
+
+    #[fail(display = "Not found: {}", err)]
+    NotFound { err: String },
 }
 impl From<argon2::Error> for DBError {
     fn from(err: argon2::Error) -> DBError {
@@ -68,3 +74,48 @@ impl From<tokio_pg_mapper::Error> for DBError {
         DBError::MapperError(err)
     }
 }
+
+/*
+ * Only to be used in maintenance.rs
+ */
+#[derive(Fail, Debug)]
+pub enum MaintenanceError {
+    #[fail(display = "Database error: {}", _0)]
+    Database(DBError),
+
+    #[fail(display = "{}", _0)]
+    Storage(StorageError),
+}
+impl From<DBError> for MaintenanceError {
+    fn from(err: DBError) -> MaintenanceError {
+        MaintenanceError::Database(err)
+    }
+}
+impl From<StorageError> for MaintenanceError {
+    fn from(err: StorageError) -> MaintenanceError {
+        MaintenanceError::Storage(err)
+    }
+}
+
+/*
+ * Only to be used in storage.rs
+ */
+#[derive(Fail, Debug)]
+pub enum StorageError {
+    #[fail(display = "File {} not found", key)]
+    NotFound { key: String },
+
+    #[fail(display = "Invalid file key {}", key)]
+    InvalidKey { key: String },
+
+    #[fail(display = "Io error: {}", _0)]
+    Io(std::io::Error),
+
+    #[fail(display = "Storage service error: {}", err)]
+    Service { err: String },
+}
+impl From<std::io::Error> for StorageError {
+    fn from(err: std::io::Error) -> StorageError {
+        StorageError::Io(err)
+    }
+}
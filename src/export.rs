@@ -0,0 +1,381 @@
+use crate::album_models::{Album, Annotation, CoordinateUnits, ExportFormat, Geometry, Shape};
+
+use chrono::offset::Utc;
+use serde_json::json;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use zip::write::{FileOptions, ZipWriter};
+use zip::CompressionMethod;
+
+/*
+ * Dataset export of verified annotations.
+ *
+ * Coordinates are always written in pixels of the stored image size. Pascal
+ * VOC and YOLO only know bounding boxes, so polygons are exported as their
+ * enclosing box there. Points have no representation in any of the formats
+ * and are left out, as are annotations with tags the album no longer has.
+ */
+pub struct ExportImage {
+    pub id: i32,
+    pub file_path: String,
+    pub width: i32,
+    pub height: i32,
+    pub annotations: Vec<Annotation>,
+}
+
+// (x, y, width, height) in pixels
+type PixelBox = (f64, f64, f64, f64);
+
+fn pixel_scale(geometry: &Geometry, width: i32, height: i32) -> (f64, f64) {
+    match geometry.units {
+        CoordinateUnits::Relative => (f64::from(width), f64::from(height)),
+        CoordinateUnits::Pixel => (1.0, 1.0),
+    }
+}
+
+fn pixel_polygon(geometry: &Geometry, width: i32, height: i32) -> Option<Vec<(f64, f64)>> {
+    let (scale_x, scale_y) = pixel_scale(geometry, width, height);
+    match &geometry.shape {
+        Shape::Polygon { points } => Some(
+            points
+                .iter()
+                .map(|p| (p.x * scale_x, p.y * scale_y))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn pixel_box(geometry: &Geometry, width: i32, height: i32) -> Option<PixelBox> {
+    let (scale_x, scale_y) = pixel_scale(geometry, width, height);
+    match &geometry.shape {
+        Shape::BoundingBox { x, y, width, height } => Some((
+            x * scale_x,
+            y * scale_y,
+            width * scale_x,
+            height * scale_y,
+        )),
+        Shape::Polygon { points } => {
+            let min_x = points.iter().map(|p| p.x).fold(f64::INFINITY, f64::min);
+            let min_y = points.iter().map(|p| p.y).fold(f64::INFINITY, f64::min);
+            let max_x = points.iter().map(|p| p.x).fold(f64::NEG_INFINITY, f64::max);
+            let max_y = points.iter().map(|p| p.y).fold(f64::NEG_INFINITY, f64::max);
+            Some((
+                min_x * scale_x,
+                min_y * scale_y,
+                (max_x - min_x) * scale_x,
+                (max_y - min_y) * scale_y,
+            ))
+        }
+        Shape::Point { .. } => None,
+    }
+}
+
+// Shoelace formula
+fn polygon_area(points: &[(f64, f64)]) -> f64 {
+    let mut area = 0.0;
+    for (i, (x1, y1)) in points.iter().enumerate() {
+        let (x2, y2) = points[(i + 1) % points.len()];
+        area += x1 * y2 - x2 * y1;
+    }
+    area.abs() / 2.0
+}
+
+fn file_stem(file_path: &str) -> String {
+    Path::new(file_path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| file_path.to_string())
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Single annotations.json, category ids follow the order of the album tags starting at 1
+fn coco(album: &Album, images: &[ExportImage]) -> Vec<u8> {
+    let categories: Vec<_> = album
+        .tags
+        .iter()
+        .enumerate()
+        .map(|(i, tag)| json!({"id": i + 1, "name": tag, "supercategory": ""}))
+        .collect();
+
+    let mut annotations = Vec::new();
+    for image in images {
+        for annotation in &image.annotations {
+            let category_id = match album.tags.iter().position(|tag| *tag == annotation.tag) {
+                Some(i) => i + 1,
+                None => continue,
+            };
+            let geometry = &annotation.coordinates;
+            let (x, y, width, height) = match pixel_box(geometry, image.width, image.height) {
+                Some(item) => item,
+                None => continue,
+            };
+            let (segmentation, area) = match pixel_polygon(geometry, image.width, image.height) {
+                Some(points) => {
+                    let flat: Vec<f64> = points.iter().flat_map(|(x, y)| vec![*x, *y]).collect();
+                    (vec![flat], polygon_area(&points))
+                }
+                None => (Vec::new(), width * height),
+            };
+            annotations.push(json!({
+                "id": annotation.id,
+                "image_id": image.id,
+                "category_id": category_id,
+                "bbox": [x, y, width, height],
+                "area": area,
+                "segmentation": segmentation,
+                "iscrowd": 0,
+            }));
+        }
+    }
+
+    let images: Vec<_> = images
+        .iter()
+        .map(|image| json!({
+            "id": image.id,
+            "file_name": image.file_path,
+            "width": image.width,
+            "height": image.height,
+        }))
+        .collect();
+
+    let dataset = json!({
+        "info": {
+            "description": album.title,
+            "date_created": Utc::now().to_rfc3339(),
+        },
+        "images": images,
+        "annotations": annotations,
+        "categories": categories,
+    });
+    serde_json::to_vec_pretty(&dataset).unwrap_or_default()
+}
+
+// One Annotations/<image>.xml per image
+fn voc(album: &Album, image: &ExportImage) -> Vec<u8> {
+    let mut xml = String::new();
+    xml.push_str("<annotation>\n");
+    xml.push_str(&format!("  <folder>{}</folder>\n", xml_escape(&album.title)));
+    xml.push_str(&format!("  <filename>{}</filename>\n", xml_escape(&image.file_path)));
+    xml.push_str(&format!(
+        "  <size>\n    <width>{}</width>\n    <height>{}</height>\n    <depth>3</depth>\n  </size>\n",
+        image.width, image.height
+    ));
+    xml.push_str("  <segmented>0</segmented>\n");
+    for annotation in &image.annotations {
+        if !album.tags.contains(&annotation.tag) {
+            continue;
+        }
+        let (x, y, width, height) = match pixel_box(&annotation.coordinates, image.width, image.height) {
+            Some(item) => item,
+            None => continue,
+        };
+        xml.push_str("  <object>\n");
+        xml.push_str(&format!("    <name>{}</name>\n", xml_escape(&annotation.tag)));
+        xml.push_str("    <pose>Unspecified</pose>\n    <truncated>0</truncated>\n    <difficult>0</difficult>\n");
+        xml.push_str(&format!(
+            "    <bndbox>\n      <xmin>{}</xmin>\n      <ymin>{}</ymin>\n      <xmax>{}</xmax>\n      <ymax>{}</ymax>\n    </bndbox>\n",
+            x.round(),
+            y.round(),
+            (x + width).round(),
+            (y + height).round()
+        ));
+        xml.push_str("  </object>\n");
+    }
+    xml.push_str("</annotation>\n");
+    xml.into_bytes()
+}
+
+// One labels/<image>.txt per image with "<class> <center x> <center y> <width> <height>"
+// relative to the image size, class ids are the line numbers of classes.txt
+fn yolo(album: &Album, image: &ExportImage) -> Vec<u8> {
+    let mut lines = String::new();
+    for annotation in &image.annotations {
+        let class = match album.tags.iter().position(|tag| *tag == annotation.tag) {
+            Some(i) => i,
+            None => continue,
+        };
+        let (x, y, width, height) = match pixel_box(&annotation.coordinates, image.width, image.height) {
+            Some(item) => item,
+            None => continue,
+        };
+        let image_width = f64::from(image.width);
+        let image_height = f64::from(image.height);
+        lines.push_str(&format!(
+            "{} {:.6} {:.6} {:.6} {:.6}\n",
+            class,
+            (x + width / 2.0) / image_width,
+            (y + height / 2.0) / image_height,
+            width / image_width,
+            height / image_height
+        ));
+    }
+    lines.into_bytes()
+}
+
+// Label files of the dataset as (path inside the archive, content)
+pub fn label_files(format: ExportFormat, album: &Album, images: &[ExportImage]) -> Vec<(String, Vec<u8>)> {
+    match format {
+        ExportFormat::Coco => vec![("annotations.json".to_string(), coco(album, images))],
+        ExportFormat::Voc => images
+            .iter()
+            .map(|image| (format!("Annotations/{}.xml", file_stem(&image.file_path)), voc(album, image)))
+            .collect(),
+        ExportFormat::Yolo => {
+            let mut files = vec![("classes.txt".to_string(), format!("{}\n", album.tags.join("\n")).into_bytes())];
+            files.extend(images.iter().map(|image| {
+                (format!("labels/{}.txt", file_stem(&image.file_path)), yolo(album, image))
+            }));
+            files
+        }
+    }
+}
+
+// Start the zip in a new file at path with the label files, images are added
+// one by one afterwards so that a large export is never held in memory
+pub fn create_zip(path: &Path, label_files: Vec<(String, Vec<u8>)>) -> zip::result::ZipResult<ZipWriter<File>> {
+    let file = OpenOptions::new().read(true).write(true).create_new(true).open(path)?;
+    let mut zip = ZipWriter::new(file);
+
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (name, content) in label_files {
+        zip.start_file(name, deflated)?;
+        zip.write_all(&content)?;
+    }
+    Ok(zip)
+}
+
+pub fn add_image(zip: &mut ZipWriter<File>, name: &str, content: &[u8]) -> zip::result::ZipResult<()> {
+    // Images are already compressed
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    zip.start_file(name, stored)?;
+    zip.write_all(content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::album_models::{Point, Visibility};
+
+    fn album() -> Album {
+        Album {
+            id: 1,
+            title: "street".to_string(),
+            description: String::new(),
+            tags: vec!["car".to_string(), "cat".to_string()],
+            image_number: 1,
+            tagged_number: 1,
+            verified_number: 1,
+            users_id: 1,
+            first_photo: "default_path".to_string(),
+            strip_metadata: true,
+            visibility: Visibility::Private,
+        }
+    }
+
+    fn annotation(id: i32, tag: &str, units: CoordinateUnits, shape: Shape) -> Annotation {
+        Annotation { id, image_id: 1, tag: tag.to_string(), coordinates: Geometry { units, shape } }
+    }
+
+    fn image(annotations: Vec<Annotation>) -> ExportImage {
+        ExportImage { id: 1, file_path: "1.png".to_string(), width: 200, height: 100, annotations }
+    }
+
+    fn triangle() -> Shape {
+        Shape::Polygon {
+            points: vec![Point { x: 10.0, y: 10.0 }, Point { x: 50.0, y: 10.0 }, Point { x: 10.0, y: 40.0 }],
+        }
+    }
+
+    #[test]
+    fn scales_relative_coordinates_to_pixels() {
+        let relative = Geometry {
+            units: CoordinateUnits::Relative,
+            shape: Shape::BoundingBox { x: 0.1, y: 0.2, width: 0.5, height: 0.25 },
+        };
+        assert_eq!(pixel_box(&relative, 200, 100), Some((20.0, 20.0, 100.0, 25.0)));
+
+        let pixel = Geometry { units: CoordinateUnits::Pixel, ..relative };
+        assert_eq!(pixel_box(&pixel, 200, 100), Some((0.1, 0.2, 0.5, 0.25)));
+
+        let point = Geometry { units: CoordinateUnits::Pixel, shape: Shape::Point { x: 1.0, y: 1.0 } };
+        assert_eq!(pixel_box(&point, 200, 100), None);
+    }
+
+    #[test]
+    fn boxes_polygons_and_measures_their_area() {
+        let geometry = Geometry { units: CoordinateUnits::Pixel, shape: triangle() };
+        assert_eq!(pixel_box(&geometry, 200, 100), Some((10.0, 10.0, 40.0, 30.0)));
+
+        let points = pixel_polygon(&geometry, 200, 100).unwrap();
+        assert_eq!(polygon_area(&points), 600.0);
+        // The winding order does not change the area
+        let reversed: Vec<_> = points.into_iter().rev().collect();
+        assert_eq!(polygon_area(&reversed), 600.0);
+    }
+
+    #[test]
+    fn writes_coco() {
+        let image = image(vec![
+            annotation(1, "cat", CoordinateUnits::Pixel, triangle()),
+            annotation(2, "dog", CoordinateUnits::Pixel, triangle()),
+        ]);
+        let dataset: serde_json::Value = serde_json::from_slice(&coco(&album(), &[image])).unwrap();
+
+        assert_eq!(dataset["categories"][0]["id"], 1);
+        assert_eq!(dataset["categories"][1]["name"], "cat");
+        // The tag the album no longer has is left out
+        let annotations = dataset["annotations"].as_array().unwrap();
+        assert_eq!(annotations.len(), 1);
+        assert_eq!(annotations[0]["category_id"], 2);
+        assert_eq!(annotations[0]["bbox"], json!([10.0, 10.0, 40.0, 30.0]));
+        assert_eq!(annotations[0]["area"], 600.0);
+        assert_eq!(annotations[0]["segmentation"], json!([[10.0, 10.0, 50.0, 10.0, 10.0, 40.0]]));
+    }
+
+    #[test]
+    fn writes_voc_corners() {
+        let image = image(vec![
+            annotation(1, "car", CoordinateUnits::Relative, Shape::BoundingBox { x: 0.1, y: 0.2, width: 0.5, height: 0.25 }),
+            annotation(2, "dog", CoordinateUnits::Pixel, triangle()),
+        ]);
+        let xml = String::from_utf8(voc(&album(), &image)).unwrap();
+
+        assert!(xml.contains("<name>car</name>"));
+        assert!(xml.contains("<xmin>20</xmin>"));
+        assert!(xml.contains("<ymin>20</ymin>"));
+        assert!(xml.contains("<xmax>120</xmax>"));
+        assert!(xml.contains("<ymax>45</ymax>"));
+        assert!(!xml.contains("dog"));
+        assert_eq!(xml.matches("<object>").count(), 1);
+    }
+
+    #[test]
+    fn writes_yolo_centers() {
+        let image = image(vec![
+            annotation(1, "cat", CoordinateUnits::Pixel, Shape::BoundingBox { x: 20.0, y: 20.0, width: 100.0, height: 25.0 }),
+            annotation(2, "dog", CoordinateUnits::Pixel, triangle()),
+            annotation(3, "car", CoordinateUnits::Relative, Shape::Point { x: 0.5, y: 0.5 }),
+        ]);
+        let labels = String::from_utf8(yolo(&album(), &image)).unwrap();
+
+        assert_eq!(labels, "1 0.350000 0.325000 0.500000 0.250000\n");
+    }
+
+    #[test]
+    fn names_label_files_after_the_images() {
+        let files = label_files(ExportFormat::Yolo, &album(), &[image(Vec::new())]);
+        let names: Vec<_> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["classes.txt", "labels/1.txt"]);
+        assert_eq!(files[0].1, b"car\ncat\n");
+    }
+}
@@ -1,7 +1,8 @@
-use crate::errors::HandlerError;
+use crate::errors::{DBError, HandlerError, StorageError};
 use crate::user_models::{
     Hash, LoginData, SendUser, Status, 
-    UpdateUserPassword, User, CreateImageMeta, UpdateUserNickname
+    UpdateUserPassword, User, CreateImageMeta, UpdateUserNickname, PhotoSize, PhotoSizeQuery, UploadResult,
+    CreateApiToken, CreatedApiToken, TokenScope, ArchiveUploadReport
 };
 use actix_web::http::StatusCode;
 use actix_web::{web, HttpRequest, HttpResponse, Result};
@@ -10,17 +11,25 @@ use deadpool_postgres::Pool;
 use crate::db;
 use crate::errors;
 use crate::my_cookie_policy::MyCookieIdentityPolicy;
-use crate::my_identity_service::{login_user, Identity};
-
+use crate::my_identity_service::{login_user, new_api_token, start_session, Identity};
+use crate::permissions;
+
+use crate::album_handlers;
+use crate::archive::{self, AlbumContent, ArchiveKind};
+use crate::config::Tagging;
+use crate::derivatives;
+use crate::image_utils;
+use crate::maintenance;
+use crate::photo_metadata;
+use crate::storage::{self, Storage};
 use crate::utils;
+use actix_web::error::BlockingError;
 use std::io::Write;
 use std::fs;
 
-use actix_multipart::Multipart;
+use actix_multipart::{Field, Multipart};
 use futures::{StreamExt, TryStreamExt};
 use log::{debug, error, info};
-use actix_files::NamedFile;
-use std::path::PathBuf;
 
 pub async fn status() -> Result<HttpResponse, HandlerError> {
     let status = String::from("server is working!");
@@ -42,7 +51,28 @@ pub async fn get_user(id: Identity) -> Result<HttpResponse, HandlerError> {
     Ok(HttpResponse::build(StatusCode::OK).json(send_user))
 }
 
-pub async fn logout(id: Identity) -> Result<HttpResponse, HandlerError> {
+pub async fn logout(pool: web::Data<Pool>, id: Identity) -> Result<HttpResponse, HandlerError> {
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    let user: User = id.identity();
+    // The cookie can not be used again even if it was copied
+    let session_id = match id.session_id() {
+        Some(item) => item,
+        None => return Ok(HttpResponse::new(StatusCode::OK)),
+    };
+    match db::delete_session(&client, user.id, session_id).await {
+        Ok(_) | Err(DBError::NotFound { .. }) => {}
+        Err(e) => {
+            error!("Error occured deleting session: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    }
     id.logout();
 
     Ok(HttpResponse::new(StatusCode::OK))
@@ -62,7 +92,7 @@ pub async fn login(
         }
     };
 
-    let user: User = match db::get_user_by_name(client, &data.username).await {
+    let user: User = match db::get_user_by_name(&client, &data.username).await {
         Ok(user) => user,
         Err(e) => match e {
             errors::DBError::PostgresError(e) => {
@@ -83,6 +113,12 @@ pub async fn login(
                     field: err.to_owned(),
                 });
             }
+            errors::DBError::NotFound { err } => {
+                error!("Error occured: {}", err);
+                return Err(HandlerError::NotFound {
+                    err_message: err.to_owned(),
+                });
+            }
         },
     };
 
@@ -98,8 +134,16 @@ pub async fn login(
         }
     }
 
+    let token = match start_session(&client, &req, &user).await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured creating session: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
     debug!("User {} logged in successfully", user.username);
-    Ok(login_user(req, cookie_factory.get_ref(), user).await)
+    Ok(login_user(req, cookie_factory.get_ref(), token).await)
 }
 
 pub async fn update_user_password(
@@ -126,7 +170,8 @@ pub async fn update_user_password(
         role: user.role,
     };
 
-    let result = db::update_user_password(&client, &new_user).await;
+    // Other sessions are logged out, e.g. on a device the password was stolen from
+    let result = db::update_user_password(&client, &new_user, id.session_id()).await;
 
     match result {
         Err(e) => match e {
@@ -148,6 +193,12 @@ pub async fn update_user_password(
                     field: err.to_owned(),
                 });
             }
+            errors::DBError::NotFound { err } => {
+                error!("Error occured: {}", err);
+                return Err(HandlerError::NotFound {
+                    err_message: err.to_owned(),
+                });
+            }
         },
         Ok(num_updated) => num_updated,
     };
@@ -155,6 +206,169 @@ pub async fn update_user_password(
     Ok(HttpResponse::new(StatusCode::OK))
 }
 
+// own sessions that have not expired, the session of the request is marked as current
+pub async fn get_sessions(pool: web::Data<Pool>, id: Identity) -> Result<HttpResponse, HandlerError> {
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    let user: User = id.identity();
+    match db::get_sessions_of_user(&client, user.id, id.session_id()).await {
+        Ok(sessions) => Ok(HttpResponse::build(StatusCode::OK).json(sessions)),
+        Err(e) => {
+            error!("Error occured get sessions: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+// log out one of the own sessions
+pub async fn delete_session(
+    pool: web::Data<Pool>,
+    id: Identity,
+    session_id: web::Path<(i32,)>,
+) -> Result<HttpResponse, HandlerError> {
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    let user: User = id.identity();
+    match db::delete_session(&client, user.id, session_id.0).await {
+        Ok(_) => {}
+        Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
+        Err(e) => {
+            error!("Error occured deleting session: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    }
+    if Some(session_id.0) == id.session_id() {
+        id.logout();
+    }
+
+    Ok(HttpResponse::build(StatusCode::OK).json(format!("Success delete session id={}", session_id.0)))
+}
+
+// log out all own sessions except the one of the request
+pub async fn delete_other_sessions(pool: web::Data<Pool>, id: Identity) -> Result<HttpResponse, HandlerError> {
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    let user: User = id.identity();
+    match db::delete_sessions_of_user(&client, user.id, id.session_id()).await {
+        Ok(deleted) => Ok(HttpResponse::build(StatusCode::OK).json(format!("Success delete {} session(s)", deleted))),
+        Err(e) => {
+            error!("Error occured deleting sessions: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+// own api tokens, without the tokens themselves
+pub async fn get_api_tokens(pool: web::Data<Pool>, id: Identity) -> Result<HttpResponse, HandlerError> {
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    let user: User = id.identity();
+    match db::get_api_tokens_of_user(&client, user.id).await {
+        Ok(tokens) => Ok(HttpResponse::build(StatusCode::OK).json(tokens)),
+        Err(e) => {
+            error!("Error occured get api tokens: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+pub async fn create_api_token(
+    pool: web::Data<Pool>,
+    id: Identity,
+    data: web::Json<CreateApiToken>,
+) -> Result<HttpResponse, HandlerError> {
+    let user: User = id.identity();
+
+    let name = data.name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Err(HandlerError::BadClientData {
+            field: "name must have 1 to 100 characters".to_string(),
+        });
+    }
+    if data.scopes.is_empty() {
+        return Err(HandlerError::BadClientData {
+            field: "at least one scope is needed".to_string(),
+        });
+    }
+    if data.scopes.contains(&TokenScope::Admin) && !permissions::can_use_admin_api(&user) {
+        return Err(HandlerError::BadClientData {
+            field: "only admins can create tokens with the admin scope".to_string(),
+        });
+    }
+    if let Some(days) = data.expires_in_days {
+        if days < 1 {
+            return Err(HandlerError::BadClientData {
+                field: "expires_in_days must be at least 1".to_string(),
+            });
+        }
+    }
+
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    let (token, token_hash) = new_api_token();
+    match db::create_api_token(&client, user.id, name, &token_hash, &data.scopes, data.expires_in_days).await {
+        Ok(api_token) => Ok(HttpResponse::build(StatusCode::CREATED).json(CreatedApiToken { token, api_token })),
+        Err(e) => {
+            error!("Error occured create api token: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
+pub async fn delete_api_token(
+    pool: web::Data<Pool>,
+    id: Identity,
+    token_id: web::Path<(i32,)>,
+) -> Result<HttpResponse, HandlerError> {
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    let user: User = id.identity();
+    match db::delete_api_token(&client, user.id, token_id.0).await {
+        Ok(_) => Ok(HttpResponse::build(StatusCode::OK).json(format!("Success delete api token id={}", token_id.0))),
+        Err(DBError::NotFound { err }) => Err(HandlerError::NotFound { err_message: err }),
+        Err(e) => {
+            error!("Error occured deleting api token: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
+}
+
 pub async fn update_user_nickname(
     pool: web::Data<Pool>,
     id: Identity,
@@ -201,6 +415,12 @@ pub async fn update_user_nickname(
                     field: err.to_owned(),
                 });
             }
+            errors::DBError::NotFound { err } => {
+                error!("Error occured: {}", err);
+                return Err(HandlerError::NotFound {
+                    err_message: err.to_owned(),
+                });
+            }
         },
         Ok(num_updated) => num_updated,
     };
@@ -236,14 +456,36 @@ pub async fn delete_user(
     Ok(HttpResponse::new(StatusCode::OK))
 }
 
+// Largest single photo upload, the same as for photos taken from archives
+const MAX_PHOTO_BYTES: usize = 64 << 20;
+
+// Uploads are checked before anything is written, so the whole part is read
+// first. None if the part is larger than max_bytes, the rest is not kept.
+pub async fn read_field(field: &mut Field, max_bytes: usize) -> Result<Option<Vec<u8>>, HandlerError> {
+    let mut content = Vec::new();
+    while let Some(chunk) = field.next().await {
+        match chunk {
+            Ok(data) if content.len() + data.len() > max_bytes => return Ok(None),
+            Ok(data) => content.extend_from_slice(&data),
+            Err(e) => {
+                error!("Error occured reading upload: {}", e);
+                return Err(HandlerError::BadClientData {
+                    field: "Upload interrupted".to_string()
+                });
+            }
+        }
+    }
+    Ok(Some(content))
+}
+
 pub async fn post_photo(
     pool: web::Data<Pool>,
-    tagify_albums_path: web::Data<String,>,
+    storage: web::Data<Box<dyn Storage>>,
     parameters: web::Path<(i32,)>,
     mut payload: Multipart,
     id: Identity,
 ) -> Result<HttpResponse, HandlerError> {
-    let client = match pool.get().await {
+    let mut client = match pool.get().await {
         Ok(item) => item,
         Err(e) => {
             error!("Error occured : {}", e);
@@ -252,10 +494,10 @@ pub async fn post_photo(
     };
     let user: User = id.identity();
     let album_id = parameters.0;
-    let album_path = format!("{}{}/", tagify_albums_path.to_string(), &album_id);
 
     // Check user has right to change file image:
-    let result = match db::get_album_by_id(&client, album_id).await {
+    let (result, member) = match db::get_album_with_role(&client, album_id, user.id).await {
+        Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
         Err(e) => {
             error!("Error occured get users albums: {}", e);
             return Err(HandlerError::InternalError);
@@ -263,89 +505,458 @@ pub async fn post_photo(
         Ok(item) => item,
     };
 
-    if user.id != result.users_id && user.role != "admin" {
+    if !permissions::can_edit_album(&user, &result, member) {
         return Err(HandlerError::PermissionDenied {
-            err_message: format!("Only owner can add image to album {}", album_id)
+            err_message: format!("Only owners can add image to album {}", album_id)
         });
     }
 
-    // Check album exist
-    if !std::path::Path::new(&album_path).exists() {
-        error!("Error occured : album with id={} not found on disk", &album_id);
-        return Err(HandlerError::BadClientData {
-            field: "Album not found".to_string()
+    let mut results = Vec::new();
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let filename_original = match field.content_disposition().and_then(|cd| cd.get_filename().map(String::from)) {
+            Some(item) => item,
+            None => {
+                info!("Field without filename in payload. Skip.");
+                continue;
+            }
+        };
+        let filename_clean = sanitize_filename::sanitize(&filename_original);
+        let content = match read_field(&mut field, MAX_PHOTO_BYTES).await? {
+            Some(item) => item,
+            None => {
+                info!("Rejected upload {}: larger than {} bytes", filename_original, MAX_PHOTO_BYTES);
+                results.push(UploadResult {
+                    file: filename_original,
+                    accepted: false,
+                    id: None,
+                    file_path: None,
+                    error: Some(format!("larger than {} MiB", MAX_PHOTO_BYTES >> 20)),
+                });
+                continue;
+            }
+        };
+
+        // Only real images are stored, the extension follows the content
+        let (image_type, (width, height)) = match image_utils::inspect_image(&content) {
+            Ok(item) => item,
+            Err(e) => {
+                info!("Rejected upload {}: {}", filename_original, e);
+                results.push(UploadResult {
+                    file: filename_original,
+                    accepted: false,
+                    id: None,
+                    file_path: None,
+                    error: Some(e),
+                });
+                continue;
+            }
+        };
+
+        // Stored upright, metadata is removed if the album strips it
+        let strip_metadata = result.strip_metadata;
+        let prepared = web::block(move || photo_metadata::prepare(content, image_type, strip_metadata)).await;
+        let prepared = match prepared {
+            Ok(item) => item,
+            Err(BlockingError::Error(e)) => {
+                info!("Rejected upload {}: {}", filename_original, e);
+                results.push(UploadResult {
+                    file: filename_original,
+                    accepted: false,
+                    id: None,
+                    file_path: None,
+                    error: Some(e),
+                });
+                continue;
+            }
+            Err(BlockingError::Canceled) => return Err(HandlerError::InternalError),
+        };
+        let (content, image_type, exif) = (prepared.content, prepared.image_type, prepared.exif);
+        let (width, height) = photo_metadata::upright_size(width, height, exif.orientation);
+
+        let byte_size = content.len() as i64;
+        let checksum = image_utils::checksum(&content);
+        let (perceptual_hash, content) = match web::block(move || Ok::<_, ()>((image_utils::perceptual_hash(&content), content))).await {
+            Ok(item) => item,
+            Err(e) => {
+                error!("Error occured : {}", e);
+                return Err(HandlerError::InternalError);
+            }
+        };
+        let mut image_meta = CreateImageMeta{
+            album_id, 
+            // Named after the id in store_new_photo
+            file_path: String::new(),
+            original_filename: Some(filename_clean),
+            width: Some(width),
+            height: Some(height),
+            mime_type: Some(image_type.mime_type().to_string()),
+            byte_size: Some(byte_size),
+            checksum: Some(checksum),
+            perceptual_hash,
+            orientation: exif.orientation,
+            captured_at: exif.captured_at,
+            camera_make: exif.camera_make,
+            camera_model: exif.camera_model,
+        };
+        let image_id = match store_new_photo(
+            &mut client, storage.get_ref().as_ref(), &mut image_meta, image_type.extension(), content
+        ).await? {
+            Stored::Written(image_id) => image_id,
+            Stored::Identical(existing_id) => {
+                info!("Rejected upload {}: identical to image {}", filename_original, existing_id);
+                results.push(UploadResult {
+                    file: filename_original,
+                    accepted: false,
+                    id: Some(existing_id),
+                    file_path: None,
+                    error: Some(format!("identical to photo {} of the album", existing_id)),
+                });
+                continue;
+            }
+        };
+        info!("Write meta data for {} to db success under {}", filename_original, &image_meta.file_path);
+        results.push(UploadResult {
+            file: filename_original,
+            accepted: true,
+            id: Some(image_id),
+            file_path: Some(image_meta.file_path),
+            error: None,
         });
     }
-    if  !db::check_album_exist_by_id(&client, &album_id).await {
-        error!("Error occured : album with id={} not found in db", &album_id);
-        return Err(HandlerError::BadClientData {
-            field: "Album not found".to_string()
-        });
+    Ok(HttpResponse::build(StatusCode::OK).json(results))
+}
+
+// Outcome of storing a photo, nothing is written if the album already has
+// the same content
+enum Stored {
+    Written(i32),
+    // id of the photo with the same content
+    Identical(i32),
+}
+
+// Photo of the album with the same content, checked under a lock held until
+// the transaction ends so concurrent uploads of one photo cannot both pass
+async fn find_identical(
+    transaction: &deadpool_postgres::Transaction<'_>,
+    image_meta: &CreateImageMeta,
+    except_id: Option<i32>,
+) -> Result<Option<i32>, HandlerError> {
+    let checksum = match &image_meta.checksum {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+    match db::find_image_by_checksum(transaction, &image_meta.album_id, checksum, except_id).await {
+        Ok(item) => Ok(item),
+        Err(e) => {
+            error!("Error occured looking up checksum: {}", e);
+            Err(HandlerError::InternalError)
+        }
     }
-    
-    while let Ok(Some(mut field)) = payload.try_next().await {
+}
 
-        let new_filename = utils::calculate_next_filename_image(
-            &utils::get_filenames_in_folder(&album_path), 
-            &db::get_image_filenames_of_album_with_id(&client, &album_id).await
-        );
+// Store a new photo under a name derived from its id and write it to db. The
+// row is only committed once the file is stored, so concurrent uploads never
+// collide and a failed upload leaves nothing behind.
+async fn store_new_photo(
+    client: &mut deadpool_postgres::Client,
+    storage: &dyn Storage,
+    image_meta: &mut CreateImageMeta,
+    extension: &str,
+    content: Vec<u8>,
+) -> Result<Stored, HandlerError> {
+    let transaction = match client.transaction().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured : {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+    if let Err(e) = db::lock_album_files_shared(&transaction, &image_meta.album_id).await {
+        error!("Error occured locking files of album {}: {}", image_meta.album_id, e);
+        return Err(HandlerError::InternalError);
+    }
+    if let Some(existing_id) = find_identical(&transaction, image_meta, None).await? {
+        return Ok(Stored::Identical(existing_id));
+    }
+    let image_id = match db::reserve_image_id(&transaction).await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured reserving image id: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+    image_meta.file_path = utils::image_file_name(image_id, extension);
+    if let Err(e) = db::create_image_meta(&transaction, &image_id, image_meta).await {
+        error!("Write file meta to db failed: {:?}", e);
+        return Err(HandlerError::InternalError);
+    }
 
-        let content_type = field.content_disposition().unwrap();
-        let filename_original = content_type.get_filename().unwrap();
-        let filename_clean = sanitize_filename::sanitize(&filename_original);
-        let vec: Vec<&str> = filename_clean.split(".").collect();
-        if vec.len() < 2 {
-            info!("Filename {} in payload has no extension. Skip.", filename_original);
+    let key = storage::photo_key(image_meta.album_id, &image_meta.file_path);
+    if let Err(e) = storage.put(&key, content.clone()).await {
+        error!("Error occured storing {}: {}", key, e);
+        return Err(HandlerError::InternalError);
+    }
+    if let Err(e) = transaction.commit().await {
+        error!("Write file meta to db failed: {:?}", e);
+        if let Err(e) = storage.delete(&key).await {
+            error!("Error deleting file {}: {}", key, e);
+        }
+        return Err(HandlerError::InternalError);
+    }
+    derivatives::generate(storage, image_meta.album_id, &image_meta.file_path, content).await;
+    Ok(Stored::Written(image_id))
+}
+
+// Store the extracted photos like single uploads. Entries meanwhile stored by
+// another upload move to the duplicates of the report, the ones from the first
+// failure on to not_stored. Returns false after a failure.
+async fn store_extracted(
+    client: &mut deadpool_postgres::Client,
+    storage: &dyn Storage,
+    staging_path: &std::path::Path,
+    image_metas: Vec<CreateImageMeta>,
+    report: &mut ArchiveUploadReport,
+) -> bool {
+    // The extraction lists the added entries in the order of their photos
+    let entries = std::mem::take(&mut report.added);
+    for (mut image_meta, entry) in image_metas.into_iter().zip(entries) {
+        if !report.not_stored.is_empty() {
+            report.not_stored.push(entry);
             continue;
         }
-        let file_extension = vec[vec.len()-1];
-        let new_filename_with_ext = format!("{}.{}", new_filename, file_extension);
-        let filepath = format!("{}{}", album_path, new_filename_with_ext);
-        // File::create is blocking operation, use threadpool
-        // Write file
-        let mut f = web::block(|| std::fs::File::create(filepath))
-            .await
-            .unwrap();
-        // Field in turn is stream of *Bytes* object
+        let staged = staging_path.join(&image_meta.file_path);
+        let extension = staged.extension().map(|e| e.to_string_lossy().into_owned()).unwrap_or_default();
+        let content = match web::block(move || fs::read(staged)).await {
+            Ok(item) => item,
+            Err(e) => {
+                error!("Error occured reading extracted {}: {}", image_meta.file_path, e);
+                report.not_stored.push(entry);
+                continue;
+            }
+        };
+        match store_new_photo(client, storage, &mut image_meta, &extension, content).await {
+            Ok(Stored::Written(_)) => report.added.push(entry),
+            Ok(Stored::Identical(_)) => report.duplicates.push(entry),
+            Err(_) => report.not_stored.push(entry),
+        }
+    }
+    report.not_stored.is_empty()
+}
+
+// Report of an archive that is skipped, the other archives of the upload go on
+fn rejected_archive(archive: String, error: String) -> ArchiveUploadReport {
+    ArchiveUploadReport {
+        archive,
+        added: Vec::new(),
+        not_images: Vec::new(),
+        duplicates: Vec::new(),
+        too_large: Vec::new(),
+        not_stored: Vec::new(),
+        error: Some(error),
+    }
+}
+
+// upload photos packed in zip or tar.gz archives, one archive per field
+pub async fn post_photo_archive(
+    pool: web::Data<Pool>,
+    storage: web::Data<Box<dyn Storage>>,
+    parameters: web::Path<(i32,)>,
+    mut payload: Multipart,
+    id: Identity,
+) -> Result<HttpResponse, HandlerError> {
+    let mut client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured : {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+    let user: User = id.identity();
+    let album_id = parameters.0;
+
+    // Check user has right to add images:
+    let (result, member) = match db::get_album_with_role(&client, album_id, user.id).await {
+        Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
+        Err(e) => {
+            error!("Error occured get users albums: {}", e);
+            return Err(HandlerError::InternalError);
+        }
+        Ok(item) => item,
+    };
+
+    if !permissions::can_edit_album(&user, &result, member) {
+        return Err(HandlerError::PermissionDenied {
+            err_message: format!("Only owners can add image to album {}", album_id)
+        });
+    }
+
+    let mut reports = Vec::new();
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let archive_name = match field.content_disposition().and_then(|cd| cd.get_filename().map(String::from)) {
+            Some(item) => item,
+            None => {
+                info!("Field without filename in payload. Skip.");
+                continue;
+            }
+        };
+        // The data of a skipped field is drained when the next one is read
+        let kind = match ArchiveKind::of_file(&archive_name) {
+            Some(item) => item,
+            None => {
+                info!("Rejected archive {}: not a .zip or .tar.gz archive", archive_name);
+                let error = format!("{} is not a .zip or .tar.gz archive", archive_name);
+                reports.push(rejected_archive(archive_name, error));
+                continue;
+            }
+        };
+
+        // Archives can be large, keep them on disk instead of in memory
+        let archive_path = std::env::temp_dir().join(format!("tagify_upload_{}", rand::random::<u64>()));
+        let create_path = archive_path.clone();
+        let mut f = match web::block(|| std::fs::File::create(create_path)).await {
+            Ok(item) => item,
+            Err(e) => {
+                error!("Error occured : {}", e);
+                return Err(HandlerError::InternalError);
+            }
+        };
         while let Some(chunk) = field.next().await {
-            let data = chunk.unwrap();
-            // filesystem operations are blocking, we have to use threadpool
-            f = match web::block(move || f.write_all(&data).map(|_| f)).await {
+            let written = match chunk {
+                Ok(data) => web::block(move || f.write_all(&data).map(|_| f)).await.map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            f = match written {
                 Ok(item) => item,
                 Err(e) => {
-                    error!("Error occured : {}", e);
+                    error!("Error occured receiving {}: {}", archive_name, e);
+                    let _ = fs::remove_file(&archive_path);
                     return Err(HandlerError::InternalError);
                 }
             }
         }
-        // Write to db
-        match db::create_image_meta(
-            &client, 
-            &CreateImageMeta{
-                album_id: album_id.clone(), 
-                coordinates: "".to_string(),
-                file_path: new_filename_with_ext.clone(),
-            }
-        ).await {
-            Ok(_) => info!("Write meta data for {} to db success under {}", filename_original, &new_filename_with_ext),
+
+        let existing = match db::get_image_metas_of_album(&client, &album_id).await {
+            Ok(item) => AlbumContent {
+                original_filenames: item.iter().filter_map(|meta| meta.original_filename.clone()).collect(),
+                checksums: item.into_iter().filter_map(|meta| meta.checksum).collect(),
+            },
             Err(e) => {
-                error!("Write file meta to db failed: {:?}", e);
+                error!("Error occured get images of album: {}", e);
+                let _ = fs::remove_file(&archive_path);
                 return Err(HandlerError::InternalError);
             }
         };
 
+        // Photos are extracted to a staging folder and moved to the storage afterwards
+        let staging_path = std::env::temp_dir().join(format!("tagify_staging_{}", rand::random::<u64>()));
+        let strip_metadata = result.strip_metadata;
+        let name = archive_name.clone();
+        let staging = staging_path.clone();
+        let extraction = web::block(move || {
+            let result = archive::extract(kind, &name, &archive_path, album_id, &staging, existing, strip_metadata);
+            let _ = fs::remove_file(&archive_path);
+            result
+        })
+        .await;
+        let extraction = match extraction {
+            Ok(item) => item,
+            Err(BlockingError::Error(e)) => {
+                info!("Rejected archive {}: {}", archive_name, e);
+                let _ = fs::remove_dir_all(&staging_path);
+                let error = format!("Could not extract {}: {}", archive_name, e);
+                reports.push(rejected_archive(archive_name, error));
+                continue;
+            }
+            Err(BlockingError::Canceled) => {
+                let _ = fs::remove_dir_all(&staging_path);
+                return Err(HandlerError::InternalError);
+            }
+        };
+
+        let mut report = extraction.report;
+        let complete = store_extracted(
+            &mut client, storage.get_ref().as_ref(), &staging_path, extraction.image_metas, &mut report
+        ).await;
+        let _ = fs::remove_dir_all(&staging_path);
+        info!("Added {} images of archive {} to album {}", report.added.len(), archive_name, album_id);
+        if !complete {
+            // What is stored stays, the client sees where the upload stopped
+            reports.push(report);
+            return Ok(HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR).json(reports));
+        }
+        reports.push(report);
     }
-    Ok(HttpResponse::build(StatusCode::OK).json("Success write file(s)"))
+    Ok(HttpResponse::build(StatusCode::OK).json(reports))
+}
+
+// Store the replacement of a photo and point its row to it. The old file is
+// only queued for deletion once the row is committed, a failed replacement
+// keeps the old photo.
+async fn replace_photo(
+    client: &mut deadpool_postgres::Client,
+    storage: &dyn Storage,
+    image_id: &i32,
+    image_meta: &CreateImageMeta,
+    content: Vec<u8>,
+    lease: i64,
+) -> Result<Stored, HandlerError> {
+    let transaction = match client.transaction().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured : {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+    if let Err(e) = db::lock_album_files_shared(&transaction, &image_meta.album_id).await {
+        error!("Error occured locking files of album {}: {}", image_meta.album_id, e);
+        return Err(HandlerError::InternalError);
+    }
+    let old_file_path = match db::get_replaceable_file_path(&transaction, image_id, lease).await {
+        Ok(Some(item)) => item,
+        Ok(None) => {
+            return Err(HandlerError::PermissionDenied {
+                err_message: format!("Image {} is being tagged", image_id)
+            });
+        }
+        Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
+        Err(e) => {
+            error!("Error occured : {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+    if let Some(existing_id) = find_identical(&transaction, image_meta, Some(*image_id)).await? {
+        return Ok(Stored::Identical(existing_id));
+    }
+
+    let key = storage::photo_key(image_meta.album_id, &image_meta.file_path);
+    if let Err(e) = storage.put(&key, content.clone()).await {
+        error!("Error occured storing {}: {}", key, e);
+        return Err(HandlerError::InternalError);
+    }
+    let updated = match db::update_image_meta(&transaction, image_meta, image_id, &old_file_path).await {
+        Ok(_) => transaction.commit().await.map_err(DBError::from),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = updated {
+        error!("Update file meta to db failed: {:?}", e);
+        if let Err(e) = storage.delete(&key).await {
+            error!("Error deleting file {}: {}", key, e);
+        }
+        return Err(HandlerError::InternalError);
+    }
+    derivatives::generate(storage, image_meta.album_id, &image_meta.file_path, content).await;
+    Ok(Stored::Written(*image_id))
 }
 
 pub async fn put_photo(
     pool: web::Data<Pool>,
-    tagify_albums_path: web::Data<String,>,
+    storage: web::Data<Box<dyn Storage>>,
     parameters: web::Path<(i32, i32)>,
     mut payload: Multipart,
+    tagging: web::Data<Tagging>,
     id: Identity,
 ) -> Result<HttpResponse, HandlerError> {
-    let client = match pool.get().await {
+    let mut client = match pool.get().await {
         Ok(item) => item,
         Err(e) => {
             error!("Error occured : {}", e);
@@ -355,10 +966,10 @@ pub async fn put_photo(
     let user: User = id.identity();
     let album_id = parameters.0;
     let image_id = parameters.1;
-    let album_path = format!("{}{}/", tagify_albums_path.to_string(), &album_id);
 
     // Check user has right to change file image:
-    let result = match db::get_album_by_id(&client, album_id).await {
+    let (result, member) = match db::get_album_with_role(&client, album_id, user.id).await {
+        Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
         Err(e) => {
             error!("Error occured get users albums: {}", e);
             return Err(HandlerError::InternalError);
@@ -366,23 +977,9 @@ pub async fn put_photo(
         Ok(item) => item,
     };
 
-    if user.id != result.users_id && user.role != "admin" {
+    if !permissions::can_edit_album(&user, &result, member) {
         return Err(HandlerError::PermissionDenied {
-            err_message: format!("Only owner can add image to album {}", album_id)
-        });
-    }
-
-    // Check album exist
-    if !std::path::Path::new(&album_path).exists() {
-        error!("Error occured : album with id={} not found on disk", &album_id);
-        return Err(HandlerError::BadClientData {
-            field: "Album not found".to_string()
-        });
-    }
-    if  !db::check_album_exist_by_id(&client, &album_id).await {
-        error!("Error occured : album with id={} not found in db", &album_id);
-        return Err(HandlerError::BadClientData {
-            field: "Album not found".to_string()
+            err_message: format!("Only owners can add image to album {}", album_id)
         });
     }
 
@@ -394,84 +991,128 @@ pub async fn put_photo(
         });
     }
 
+    let mut results = Vec::new();
     while let Ok(Some(mut field)) = payload.try_next().await {
-        // Extract num part of filename from db
-        let mut new_filename = "".to_string();
-        let vec: Vec<&str> = file_path_db.split(".").collect();
-        let fname: &str = vec[0];
-        if fname.parse::<u32>().is_ok() {
-            new_filename = fname.parse().unwrap();
-        }
-
-        let content_type = field.content_disposition().unwrap();
-        let filename_original = content_type.get_filename().unwrap();
+        let filename_original = match field.content_disposition().and_then(|cd| cd.get_filename().map(String::from)) {
+            Some(item) => item,
+            None => {
+                info!("Field without filename in payload. Skip.");
+                continue;
+            }
+        };
         let filename_clean = sanitize_filename::sanitize(&filename_original);
-        let vec: Vec<&str> = filename_clean.split(".").collect();
-        if vec.len() < 2 {
-            info!("Filename {} in payload has no extension. Skip.", filename_original);
-            continue;
-        }
-        let file_extension = vec[vec.len()-1];
-        let new_filename_with_ext = format!("{}.{}", new_filename, file_extension);
-        let filepath = format!("{}{}", album_path, new_filename_with_ext);
-
-        // Delete old file:
-        if !std::path::Path::new(&filepath).exists() {
-            let old_file_path = format!("{}{}", album_path, file_path_db);
-            match fs::remove_file(&old_file_path) {
-                Ok(_) => info!("Deleted old file {}", filepath),
-                Err(e) => {
-                    error!("Error deleting old file {}: {:?}", &old_file_path, e);
-                    return Err(HandlerError::InternalError);
-                }
+        let content = match read_field(&mut field, MAX_PHOTO_BYTES).await? {
+            Some(item) => item,
+            None => {
+                info!("Rejected upload {}: larger than {} bytes", filename_original, MAX_PHOTO_BYTES);
+                results.push(UploadResult {
+                    file: filename_original,
+                    accepted: false,
+                    id: Some(image_id),
+                    file_path: None,
+                    error: Some(format!("larger than {} MiB", MAX_PHOTO_BYTES >> 20)),
+                });
+                continue;
             }
-        }
+        };
 
+        // Only real images replace the old one, the extension follows the content
+        let (image_type, (width, height)) = match image_utils::inspect_image(&content) {
+            Ok(item) => item,
+            Err(e) => {
+                info!("Rejected upload {}: {}", filename_original, e);
+                results.push(UploadResult {
+                    file: filename_original,
+                    accepted: false,
+                    id: Some(image_id),
+                    file_path: None,
+                    error: Some(e),
+                });
+                continue;
+            }
+        };
 
-        // File::create is blocking operation, use threadpool
-        // Write file
-        let mut f = web::block(|| std::fs::File::create(filepath))
-            .await
-            .unwrap();
-        // Field in turn is stream of *Bytes* object
-        while let Some(chunk) = field.next().await {
-            let data = chunk.unwrap();
-            // filesystem operations are blocking, we have to use threadpool
-            f = match web::block(move || f.write_all(&data).map(|_| f)).await {
-                Ok(item) => item,
-                Err(e) => {
-                    error!("Error occured : {}", e);
-                    return Err(HandlerError::InternalError);
-                }
+        // Stored upright, metadata is removed if the album strips it
+        let strip_metadata = result.strip_metadata;
+        let prepared = web::block(move || photo_metadata::prepare(content, image_type, strip_metadata)).await;
+        let prepared = match prepared {
+            Ok(item) => item,
+            Err(BlockingError::Error(e)) => {
+                info!("Rejected upload {}: {}", filename_original, e);
+                results.push(UploadResult {
+                    file: filename_original,
+                    accepted: false,
+                    id: Some(image_id),
+                    file_path: None,
+                    error: Some(e),
+                });
+                continue;
             }
-        }
-        // Update to db
-        match db::update_image_meta(
-            &client, 
-            &CreateImageMeta{
-                album_id: album_id.clone(), 
-                coordinates: "".to_string(),
-                file_path: new_filename_with_ext.clone(),
-            },
-            &image_id
-        ).await {
-            Ok(_) => info!("Update meta data for {} to db success under {}", filename_original, &new_filename_with_ext),
+            Err(BlockingError::Canceled) => return Err(HandlerError::InternalError),
+        };
+        let (content, image_type, exif) = (prepared.content, prepared.image_type, prepared.exif);
+        let (width, height) = photo_metadata::upright_size(width, height, exif.orientation);
+
+        let byte_size = content.len() as i64;
+        let checksum = image_utils::checksum(&content);
+        let (perceptual_hash, content) = match web::block(move || Ok::<_, ()>((image_utils::perceptual_hash(&content), content))).await {
+            Ok(item) => item,
             Err(e) => {
-                error!("Update file meta to db failed: {:?}", e);
+                error!("Error occured : {}", e);
                 return Err(HandlerError::InternalError);
             }
         };
-
+        let image_meta = CreateImageMeta{
+            album_id,
+            file_path: utils::replaced_image_file_name(image_id, image_type.extension()),
+            original_filename: Some(filename_clean),
+            width: Some(width),
+            height: Some(height),
+            mime_type: Some(image_type.mime_type().to_string()),
+            byte_size: Some(byte_size),
+            checksum: Some(checksum),
+            perceptual_hash,
+            orientation: exif.orientation,
+            captured_at: exif.captured_at,
+            camera_make: exif.camera_make,
+            camera_model: exif.camera_model,
+        };
+        let replaced = replace_photo(
+            &mut client, storage.get_ref().as_ref(), &image_id, &image_meta, content, tagging.lock_lease_secs
+        ).await?;
+        if let Stored::Identical(existing_id) = replaced {
+            info!("Rejected upload {}: identical to image {}", filename_original, existing_id);
+            results.push(UploadResult {
+                file: filename_original,
+                accepted: false,
+                id: Some(image_id),
+                file_path: None,
+                error: Some(format!("identical to photo {} of the album", existing_id)),
+            });
+            continue;
+        }
+        info!("Update meta data for {} to db success under {}", filename_original, &image_meta.file_path);
+        // The old file and its previews are queued for deletion
+        maintenance::spawn_file_deletions(pool.clone(), storage.clone());
+        results.push(UploadResult {
+            file: filename_original,
+            accepted: true,
+            id: Some(image_id),
+            file_path: Some(image_meta.file_path),
+            error: None,
+        });
     }
-    Ok(HttpResponse::build(StatusCode::OK).json(format!("Success update image id={}",  &image_id)))
+    Ok(HttpResponse::build(StatusCode::OK).json(results))
 }
 
 pub async fn get_photo(
     pool: web::Data<Pool>,
-    tagify_albums_path: web::Data<String,>,
+    storage: web::Data<Box<dyn Storage>>,
     parameters: web::Path<(i32, i32)>,
-    //id: Identity,
-) -> Result<NamedFile, HandlerError> {
+    query: web::Query<PhotoSizeQuery>,
+    req: HttpRequest,
+    id: Identity,
+) -> Result<HttpResponse, HandlerError> {
     let client = match pool.get().await {
         Ok(item) => item,
         Err(e) => {
@@ -479,60 +1120,79 @@ pub async fn get_photo(
             return Err(HandlerError::InternalError);
         }
     };
-    //let user: User = id.identity();
     let album_id = parameters.0;
     let image_id = parameters.1;
-    let album_path = format!("{}{}/", tagify_albums_path.to_string(), &album_id);
 
-    // Check album exist
-    if !std::path::Path::new(&album_path).exists() {
-        error!("Error occured : album with id={} not found on disk", &album_id);
-        return Err(HandlerError::BadClientData {
-            field: "Album not found".to_string()
-        });
-    }
-    if  !db::check_album_exist_by_id(&client, &album_id).await {
-        error!("Error occured : album with id={} not found in db", &album_id);
-        return Err(HandlerError::BadClientData {
-            field: "Album not found".to_string()
-        });
-    }
+    // Check album exists and the user can see it, /api/albums also serves anonymous requests
+    album_handlers::get_visible_album(&client, album_id, id.user().as_ref()).await?;
+
+    serve_photo(&client, storage.get_ref().as_ref(), &req, album_id, image_id, query.size).await
+}
+
+// photo of the album a share link points to, no login needed
+pub async fn get_shared_photo(
+    pool: web::Data<Pool>,
+    storage: web::Data<Box<dyn Storage>>,
+    parameters: web::Path<(String, i32)>,
+    query: web::Query<PhotoSizeQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, HandlerError> {
+    let client = match pool.get().await {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error occured : {}", e);
+            return Err(HandlerError::InternalError);
+        }
+    };
+
+    let album = album_handlers::get_album_of_share_link(&client, &parameters.0).await?;
 
+    serve_photo(&client, storage.get_ref().as_ref(), &req, album.id, parameters.1, query.size).await
+}
+
+// Serves a photo of an album the request may see
+async fn serve_photo(
+    client: &deadpool_postgres::Client,
+    storage: &dyn Storage,
+    req: &HttpRequest,
+    album_id: i32,
+    image_id: i32,
+    size: PhotoSize,
+) -> Result<HttpResponse, HandlerError> {
     // Check if image exists in db:
-    let file_path_db = db::get_image_file_path_with_id_from_album(&client, &album_id, &image_id).await;
+    let file_path_db = db::get_image_file_path_with_id_from_album(client, &album_id, &image_id).await;
     if file_path_db == "".to_string() {
         return Err(HandlerError::BadClientData {
             field: format!("Image with id={} of album id={} not found in db.\nImage not exists or false album id?", &image_id, &album_id).to_string()
         });
     }
 
+    // Downscaled copies of photos uploaded before are created on first request
+    let key = derivatives::get_or_generate(storage, album_id, &file_path_db, size).await;
+    serve_file(storage, req, &key).await
+}
 
-    let filepath = format!("{}{}", album_path, file_path_db);
-    // Check file exist
-    if !std::path::Path::new(&filepath).exists() {
-        error!("Error occured : Image file with id={} not found on disk", &filepath);
-        return Err(HandlerError::BadClientData {
-            field: format!("File {} not found on disk", filepath).to_string()
-        });
-    }
-
-    let path: PathBuf = filepath.parse().unwrap();
-
-    let r = NamedFile::open(&path);
-    match &r {
-        Ok(_) => info!("success open file {:?}", &path),
+pub async fn serve_file(storage: &dyn Storage, req: &HttpRequest, key: &str) -> Result<HttpResponse, HandlerError> {
+    match storage.serve(req, key).await {
+        Ok(item) => {
+            info!("success open file {}", key);
+            Ok(item)
+        }
+        Err(StorageError::NotFound { .. }) => {
+            error!("Error occured : image file {} not found in storage", key);
+            Err(HandlerError::NotFound {
+                err_message: format!("File {} not found", key)
+            })
+        }
         Err(e) => {
-            error!("unsuccess open: {:?}", e);
-            return Err(HandlerError::InternalError);
-        },
-    };
-
-    Ok(r.unwrap())
+            error!("unsuccess open: {}", e);
+            Err(HandlerError::InternalError)
+        }
+    }
 }
 
 pub async fn delete_photo(
     pool: web::Data<Pool>,
-    tagify_albums_path: web::Data<String,>,
     parameters: web::Path<(i32, i32)>,
     id: Identity,
 ) -> Result<HttpResponse, HandlerError> {
@@ -546,10 +1206,10 @@ pub async fn delete_photo(
     let user: User = id.identity();
     let album_id = parameters.0;
     let image_id = parameters.1;
-    let album_path = format!("{}{}/", tagify_albums_path.to_string(), &album_id);
 
     // Check user has right to change file image:
-    let result = match db::get_album_by_id(&client, album_id).await {
+    let (result, member) = match db::get_album_with_role(&client, album_id, user.id).await {
+        Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
         Err(e) => {
             error!("Error occured get users albums: {}", e);
             return Err(HandlerError::InternalError);
@@ -557,63 +1217,108 @@ pub async fn delete_photo(
         Ok(item) => item,
     };
 
-    if user.id != result.users_id && user.role != "admin" {
+    if !permissions::can_edit_album(&user, &result, member) {
         return Err(HandlerError::PermissionDenied {
-            err_message: format!("Only owner can delete image from album {}", album_id)
-        });
-    }
-
-    // Check if image exists in db:
-    let file_path_db = db::get_image_file_path_with_id_from_album(&client, &album_id, &image_id).await;
-    if file_path_db == "".to_string() {
-        return Err(HandlerError::BadClientData {
-            field: "Id of image not found in db".to_string()
+            err_message: format!("Only owners can delete image from album {}", album_id)
         });
     }
 
-    // Check album exist
-    if !std::path::Path::new(&album_path).exists() {
-        error!("Error occured : album with id={} not found on disk", &album_id);
-        return Err(HandlerError::BadClientData {
-            field: "Album not found".to_string()
-        });
-    }
-    if  !db::check_album_exist_by_id(&client, &album_id).await {
-        error!("Error occured : album with id={} not found in db", &album_id);
-        return Err(HandlerError::BadClientData {
-            field: "Album not found".to_string()
-        });
-    }
-
-    // Check file exist
-    let filepath = format!("{}{}", album_path, file_path_db);
-    if !std::path::Path::new(&filepath).exists() {
-        error!("Error occured : image file with id={} not found on disk", &filepath);
-        return Err(HandlerError::BadClientData {
-            field: "File not found".to_string()
-        });
-    }
-
-    // Delete file 
-    match fs::remove_file(&filepath) {
-        Ok(_) => info!("Deleted file "),
-        Err(e) => {
-            error!("Error deleting file {}: {:?}", &filepath, e);
-            return Err(HandlerError::InternalError);
-        }
-    }
-    
-    // Delete from db
-    match db::delete_image_meta(
-        &client, 
+    // Move to the trash, the photo is purged after the retention period
+    match db::trash_image_meta(
+        &client,
+        &album_id,
         &image_id,
     ).await {
-        Ok(_) => info!("Delete meta id={} from album {} success", &image_id, &album_id),
+        Ok(_) => info!("Moved image id={} of album {} to the trash", &image_id, &album_id),
+        Err(DBError::NotFound { err }) => return Err(HandlerError::NotFound { err_message: err }),
         Err(e) => {
-            error!("Delete meta id={} from album {} success: {:?}", &image_id, &album_id, e);
+            error!("Delete meta id={} from album {} failed: {:?}", &image_id, &album_id, e);
             return Err(HandlerError::InternalError);
         }
     };
 
     Ok(HttpResponse::build(StatusCode::OK).json(format!("Success delete image id={}",  &image_id)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::album_models::{AlbumRole, CreateAlbum, Visibility};
+    use crate::config::MyConfig;
+    use crate::my_identity_service::IdentityService;
+    use crate::storage::LocalStorage;
+    use crate::test_fixtures;
+    use actix_web::{test, App};
+    use tokio_postgres::NoTls;
+
+    async fn create_user(client: &deadpool_postgres::Client, name: &str) -> (User, String) {
+        let user = test_fixtures::create_user(client, name).await;
+        let (token, hash) = new_api_token();
+        db::create_api_token(client, user.id, "test", &hash, &[TokenScope::ReadAlbums], None).await.unwrap();
+        (user, token)
+    }
+
+    #[actix_rt::test]
+    #[ignore] // needs the PostgreSQL database configured in Settings.toml
+    async fn serves_photo_files_of_visible_albums() {
+        let conf = MyConfig::new("Settings").unwrap();
+        let pool = conf.postgres.create_pool(NoTls).unwrap();
+        let mut client = pool.get().await.unwrap();
+        crate::migrations::run_pending(&mut client).await.unwrap();
+        let root = std::env::temp_dir().join(format!("tagify_files_{}", rand::random::<u64>()));
+        let storage: Box<dyn Storage> = Box::new(LocalStorage::new(root.to_str().unwrap()));
+
+        let (owner, _) = create_user(&client, "owner").await;
+        let (internal, internal_token) = create_user(&client, "internal").await;
+        let (member, member_token) = create_user(&client, "member").await;
+        let mut photos = Vec::new();
+        for visibility in &[Visibility::Public, Visibility::Internal, Visibility::Private] {
+            let album = db::create_album(&client, &CreateAlbum {
+                title: format!("{:?}", visibility),
+                description: String::new(),
+                tags: Vec::new(),
+                strip_metadata: true,
+                visibility: *visibility,
+            }, owner.id, String::new()).await.unwrap();
+            let transaction = client.transaction().await.unwrap();
+            let (image_id, file_path) = test_fixtures::create_photo(&transaction, album.id).await;
+            transaction.commit().await.unwrap();
+            storage.put(&storage::photo_key(album.id, &file_path), b"photo".to_vec()).await.unwrap();
+            photos.push((album.id, image_id));
+        }
+        let (private_album, _) = photos[2];
+        db::add_album_member(&client, private_album, member.id, AlbumRole::Tagger, owner.id).await.unwrap();
+
+        let mut app = test::init_service(
+            App::new().data(pool.clone()).data(storage).service(
+                web::scope("/api/albums")
+                    .wrap(IdentityService::new(MyCookieIdentityPolicy::new(&[0; 32]), pool.clone()).optional())
+                    .route("/{album_id}/photos/{photo_id}/file", web::get().to(get_photo)),
+            ),
+        )
+        .await;
+
+        // Anonymous, logged in user and member of the private album
+        let expected = vec![
+            (None, [StatusCode::OK, StatusCode::NOT_FOUND, StatusCode::NOT_FOUND]),
+            (Some(&internal_token), [StatusCode::OK, StatusCode::OK, StatusCode::NOT_FOUND]),
+            (Some(&member_token), [StatusCode::OK, StatusCode::OK, StatusCode::OK]),
+        ];
+        for (token, statuses) in expected {
+            for ((album_id, image_id), status) in photos.iter().zip(statuses.iter()) {
+                let mut req = test::TestRequest::get().uri(&format!("/api/albums/{}/photos/{}/file", album_id, image_id));
+                if let Some(token) = token {
+                    req = req.header("Authorization", format!("Bearer {}", token));
+                }
+                let response = test::call_service(&mut app, req.to_request()).await;
+                assert_eq!(response.status(), *status, "album {} with token {:?}", album_id, token);
+                if *status == StatusCode::OK {
+                    assert_eq!(test::read_body(response).await, web::Bytes::from_static(b"photo"));
+                }
+            }
+        }
+
+        test_fixtures::delete_users(&client, &[owner.id, internal.id, member.id]).await;
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,364 @@
+use image::imageops::FilterType;
+use image::ImageFormat;
+use log::error;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+
+// Image types accepted for upload
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageType {
+    Jpeg,
+    Png,
+    Webp,
+    Tiff,
+}
+
+impl ImageType {
+    // Extension the file is stored with, regardless of the uploaded file name
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageType::Jpeg => "jpg",
+            ImageType::Png => "png",
+            ImageType::Webp => "webp",
+            ImageType::Tiff => "tiff",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ImageType::Jpeg => "image/jpeg",
+            ImageType::Png => "image/png",
+            ImageType::Webp => "image/webp",
+            ImageType::Tiff => "image/tiff",
+        }
+    }
+
+    pub fn format(self) -> ImageFormat {
+        match self {
+            ImageType::Jpeg => ImageFormat::Jpeg,
+            ImageType::Png => ImageFormat::Png,
+            ImageType::Webp => ImageFormat::WebP,
+            ImageType::Tiff => ImageFormat::Tiff,
+        }
+    }
+}
+
+// Detects the image type from the magic bytes at the start of the content
+pub fn sniff_image_type(content: &[u8]) -> Option<ImageType> {
+    if content.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(ImageType::Jpeg)
+    } else if content.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(ImageType::Png)
+    } else if content.len() >= 12 && &content[0..4] == b"RIFF" && &content[8..12] == b"WEBP" {
+        Some(ImageType::Webp)
+    } else if content.starts_with(b"II*\0") || content.starts_with(b"MM\0*") {
+        Some(ImageType::Tiff)
+    } else {
+        None
+    }
+}
+
+// The image crate can not read lossless and extended WebP headers, so the
+// size is taken from the first chunk after the RIFF header directly
+fn webp_dimensions(content: &[u8]) -> Option<(u32, u32)> {
+    let data = content.get(20..)?;
+    let (width, height) = match content.get(12..16)? {
+        b"VP8 " => {
+            // 3 byte frame tag, start code, then 14 bit width and height
+            if data.get(3..6)? != [0x9D, 0x01, 0x2A] {
+                return None;
+            }
+            let size = data.get(6..10)?;
+            (
+                u32::from(u16::from_le_bytes([size[0], size[1]]) & 0x3FFF),
+                u32::from(u16::from_le_bytes([size[2], size[3]]) & 0x3FFF),
+            )
+        }
+        b"VP8L" => {
+            // Signature, then width - 1 and height - 1 in 14 bits each
+            if *data.first()? != 0x2F {
+                return None;
+            }
+            let size = data.get(1..5)?;
+            let bits = u32::from_le_bytes([size[0], size[1], size[2], size[3]]);
+            ((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1)
+        }
+        b"VP8X" => {
+            // 4 bytes of flags, then canvas width - 1 and height - 1 in 24 bits each
+            let size = data.get(4..10)?;
+            (
+                u32::from_le_bytes([size[0], size[1], size[2], 0]) + 1,
+                u32::from_le_bytes([size[3], size[4], size[5], 0]) + 1,
+            )
+        }
+        _ => return None,
+    };
+    if width == 0 || height == 0 {
+        return None;
+    }
+    Some((width, height))
+}
+
+// Checks that the content is a JPEG, PNG, WebP or TIFF image with a readable
+// header, returns its type and size or the reason for rejecting it
+pub fn inspect_image(content: &[u8]) -> Result<(ImageType, (i32, i32)), String> {
+    let image_type = match sniff_image_type(content) {
+        Some(item) => item,
+        None => return Err("not a JPEG, PNG, WebP or TIFF image".to_string()),
+    };
+    let dimensions = match image_type {
+        ImageType::Webp => webp_dimensions(content),
+        _ => image::io::Reader::with_format(Cursor::new(content), image_type.format())
+            .into_dimensions()
+            .ok(),
+    };
+    match dimensions {
+        Some((width, height)) => Ok((image_type, (width as i32, height as i32))),
+        None => Err(format!("corrupt {} image, header can not be read", image_type.extension())),
+    }
+}
+
+// Hex encoded SHA-256 of the file content
+pub fn checksum(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+// Difference hash over a 9x8 grey scale thumbnail, similar images differ in few
+// bits. None if the image can not be decoded.
+pub fn perceptual_hash(content: &[u8]) -> Option<i64> {
+    let image = match image::load_from_memory(content) {
+        Ok(item) => item,
+        Err(e) => {
+            error!("Error decoding image for perceptual hash: {}", e);
+            return None;
+        }
+    };
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash: u64 = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    Some(hash as i64)
+}
+
+// Number of differing bits of two perceptual hashes
+pub fn hash_distance(a: i64, b: i64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+// Pairs (i, j, distance) with i < j of hashes that differ in at most
+// max_distance bits. Hashes are split into max_distance + 1 blocks, two such
+// hashes agree in at least one of them, so only hashes sharing a block value
+// are compared.
+pub fn similar_hashes(hashes: &[i64], max_distance: u32) -> Vec<(usize, usize, u32)> {
+    if max_distance >= 64 {
+        let n = hashes.len();
+        return (0..n)
+            .flat_map(|i| (i + 1..n).map(move |j| (i, j, hash_distance(hashes[i], hashes[j]))))
+            .collect();
+    }
+
+    let blocks = max_distance as usize + 1;
+    let mut buckets: HashMap<(usize, u64), Vec<usize>> = HashMap::new();
+    for (i, hash) in hashes.iter().enumerate() {
+        for block in 0..blocks {
+            let (start, end) = (block * 64 / blocks, (block + 1) * 64 / blocks);
+            let bits = (*hash as u64 >> start) & (u64::MAX >> (64 - (end - start)));
+            buckets.entry((block, bits)).or_default().push(i);
+        }
+    }
+
+    let mut pairs = BTreeMap::new();
+    for members in buckets.values() {
+        for (k, i) in members.iter().enumerate() {
+            for j in &members[k + 1..] {
+                let distance = hash_distance(hashes[*i], hashes[*j]);
+                if distance <= max_distance {
+                    pairs.insert((*i, *j), distance);
+                }
+            }
+        }
+    }
+    pairs.into_iter().map(|((i, j), distance)| (i, j, distance)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageOutputFormat};
+
+    fn encode(width: u32, height: u32, format: ImageOutputFormat) -> Vec<u8> {
+        let mut content = Vec::new();
+        DynamicImage::new_rgb8(width, height).write_to(&mut content, format).unwrap();
+        content
+    }
+
+    // RIFF container with a single chunk, `data` starts at byte 20
+    fn webp(chunk: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut content = b"RIFF".to_vec();
+        content.extend_from_slice(&((12 + data.len()) as u32).to_le_bytes());
+        content.extend_from_slice(b"WEBP");
+        content.extend_from_slice(chunk);
+        content.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        content.extend_from_slice(data);
+        content
+    }
+
+    #[test]
+    fn sniffs_magic_bytes() {
+        assert_eq!(sniff_image_type(&[0xFF, 0xD8, 0xFF, 0xE0]), Some(ImageType::Jpeg));
+        assert_eq!(sniff_image_type(b"\x89PNG\r\n\x1a\n\0\0"), Some(ImageType::Png));
+        assert_eq!(sniff_image_type(b"RIFF\0\0\0\0WEBPVP8 "), Some(ImageType::Webp));
+        assert_eq!(sniff_image_type(b"II*\0\x08\0\0\0"), Some(ImageType::Tiff));
+        assert_eq!(sniff_image_type(b"MM\0*\0\0\0\x08"), Some(ImageType::Tiff));
+    }
+
+    #[test]
+    fn ignores_file_names_and_other_formats() {
+        assert_eq!(sniff_image_type(b""), None);
+        assert_eq!(sniff_image_type(&[0xFF, 0xD8]), None);
+        assert_eq!(sniff_image_type(b"\x89PNG"), None);
+        assert_eq!(sniff_image_type(b"GIF89a\x01\0\x01\0"), None);
+        assert_eq!(sniff_image_type(b"RIFF\0\0\0\0WAVEfmt "), None);
+        assert_eq!(sniff_image_type(b"RIFF\0\0\0\0WEB"), None);
+        assert_eq!(sniff_image_type(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>"), None);
+        assert_eq!(sniff_image_type(b"photo.png"), None);
+    }
+
+    #[test]
+    fn reads_type_and_size() {
+        let cases = vec![
+            (ImageOutputFormat::Png, ImageType::Png),
+            (ImageOutputFormat::Jpeg(90), ImageType::Jpeg),
+        ];
+        for (format, image_type) in cases {
+            let content = encode(7, 5, format);
+            assert_eq!(inspect_image(&content), Ok((image_type, (7, 5))));
+        }
+
+        let mut tiff = Cursor::new(Vec::new());
+        image::codecs::tiff::TiffEncoder::new(&mut tiff)
+            .encode(&[0; 7 * 5 * 3], 7, 5, image::ColorType::Rgb8)
+            .unwrap();
+        assert_eq!(inspect_image(tiff.get_ref()), Ok((ImageType::Tiff, (7, 5))));
+    }
+
+    #[test]
+    fn reads_size_of_webp_variants() {
+        // Lossy: frame tag, start code, 14 bit width and height
+        let lossy = webp(b"VP8 ", &[0, 0, 0, 0x9D, 0x01, 0x2A, 30, 0, 20, 0]);
+        assert_eq!(inspect_image(&lossy), Ok((ImageType::Webp, (30, 20))));
+
+        // Lossless: signature, width - 1 and height - 1 in 14 bits each
+        let bits: u32 = 9 | (4 << 14);
+        let mut data = vec![0x2F];
+        data.extend_from_slice(&bits.to_le_bytes());
+        let lossless = webp(b"VP8L", &data);
+        assert_eq!(inspect_image(&lossless), Ok((ImageType::Webp, (10, 5))));
+
+        // Extended: flags, canvas width - 1 and height - 1 in 24 bits each
+        let extended = webp(b"VP8X", &[0, 0, 0, 0, 99, 0, 0, 49, 0, 0]);
+        assert_eq!(inspect_image(&extended), Ok((ImageType::Webp, (100, 50))));
+    }
+
+    #[test]
+    fn rejects_corrupt_headers() {
+        let mut truncated = encode(7, 5, ImageOutputFormat::Png);
+        truncated.truncate(12);
+        assert!(inspect_image(&truncated).unwrap_err().starts_with("corrupt png"));
+
+        assert!(inspect_image(&[0xFF, 0xD8, 0xFF, 0, 0, 0]).unwrap_err().starts_with("corrupt jpg"));
+        // Wrong start code, unknown chunk, zero size
+        assert!(inspect_image(&webp(b"VP8 ", &[0, 0, 0, 0, 0, 0, 30, 0, 20, 0])).is_err());
+        assert!(inspect_image(&webp(b"ALPH", &[0; 10])).is_err());
+        assert!(inspect_image(&webp(b"VP8 ", &[0, 0, 0, 0x9D, 0x01, 0x2A, 0, 0, 20, 0])).is_err());
+        assert!(inspect_image(&webp(b"VP8L", &[0x2F])).is_err());
+
+        assert_eq!(inspect_image(b"hello"), Err("not a JPEG, PNG, WebP or TIFF image".to_string()));
+    }
+
+    // Horizontal gradient, brighter to the right when rising
+    fn gradient(rising: bool, offset: u8) -> Vec<u8> {
+        let image = image::GrayImage::from_fn(90, 80, |x, _| {
+            let value = if rising { x as u8 * 2 } else { 180 - x as u8 * 2 };
+            image::Luma([value.saturating_add(offset)])
+        });
+        let mut content = Vec::new();
+        DynamicImage::ImageLuma8(image).write_to(&mut content, ImageOutputFormat::Png).unwrap();
+        content
+    }
+
+    #[test]
+    fn counts_differing_bits() {
+        assert_eq!(hash_distance(0, 0), 0);
+        assert_eq!(hash_distance(0b1011, 0b0001), 2);
+        assert_eq!(hash_distance(0, -1), 64);
+        // The sign bit counts like any other
+        assert_eq!(hash_distance(i64::MIN, 0), 1);
+        assert_eq!(hash_distance(0x0F0F, 0x00FF), hash_distance(0x00FF, 0x0F0F));
+    }
+
+    #[test]
+    fn similar_images_have_close_hashes() {
+        let rising = perceptual_hash(&gradient(true, 0)).unwrap();
+        // Every pixel is brighter to the right of its neighbour
+        assert_eq!(rising, -1);
+        assert_eq!(perceptual_hash(&gradient(true, 0)), Some(rising));
+        assert_eq!(perceptual_hash(&gradient(true, 20)), Some(rising));
+
+        let falling = perceptual_hash(&gradient(false, 0)).unwrap();
+        assert_eq!(hash_distance(rising, falling), 64);
+
+        assert_eq!(perceptual_hash(b"not an image"), None);
+    }
+
+    #[test]
+    fn finds_the_same_pairs_as_comparing_all() {
+        // xorshift, the test needs many hashes with some close ones
+        let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let mut hashes = Vec::new();
+        for _ in 0..200 {
+            let hash = next() as i64;
+            hashes.push(hash);
+            // A near copy with up to 8 flipped bits
+            let flips = next() % 9;
+            let mut copy = hash;
+            for _ in 0..flips {
+                copy ^= 1 << (next() % 64);
+            }
+            hashes.push(copy);
+        }
+
+        for max_distance in &[0, 1, 4, 6, 12, 63, 64] {
+            let mut expected = Vec::new();
+            for i in 0..hashes.len() {
+                for j in i + 1..hashes.len() {
+                    let distance = hash_distance(hashes[i], hashes[j]);
+                    if distance <= *max_distance {
+                        expected.push((i, j, distance));
+                    }
+                }
+            }
+            assert_eq!(similar_hashes(&hashes, *max_distance), expected, "max_distance {}", max_distance);
+        }
+    }
+
+    #[test]
+    fn identical_hashes_are_similar() {
+        assert_eq!(similar_hashes(&[5, 7, 5], 0), vec![(0, 2, 0)]);
+        assert!(similar_hashes(&[], 6).is_empty());
+        assert!(similar_hashes(&[1], 6).is_empty());
+    }
+}
@@ -0,0 +1,546 @@
+use crate::album_models::{CoordinateUnits, Geometry, Point, Shape};
+use crate::user_models::ImageMeta;
+
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::Deserialize;
+use std::path::Path;
+
+/*
+ * Parsers for annotation files created outside of tagify.
+ *
+ * The format is chosen by file extension:
+ *   .json  COCO dataset, bounding boxes or polygon segmentations in pixels
+ *   .xml   Pascal VOC, one file per image, bounding boxes in pixels
+ *   .txt   YOLO, one file named after the image, "<class> <cx> <cy> <w> <h>"
+ *          or "<class> <x1> <y1> <x2> <y2> ..." polygons relative to the image
+ *   .csv   header with filename, label (or class/tag), xmin, ymin, xmax, ymax in pixels
+ * Entries that can not be read are reported as invalid, the rest is matched
+ * to photos and validated by the caller.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    Coco,
+    Voc,
+    Yolo,
+    Csv,
+}
+
+impl ImportFormat {
+    pub fn of_file(file_name: &str) -> Option<ImportFormat> {
+        let extension = Path::new(file_name).extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "json" => Some(ImportFormat::Coco),
+            "xml" => Some(ImportFormat::Voc),
+            "txt" => Some(ImportFormat::Yolo),
+            "csv" => Some(ImportFormat::Csv),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ImportFormat::Coco => "coco",
+            ImportFormat::Voc => "voc",
+            ImportFormat::Yolo => "yolo",
+            ImportFormat::Csv => "csv",
+        }
+    }
+}
+
+// One annotation read from a file, `image` is the image name used in the file
+pub struct ImportEntry {
+    pub image: String,
+    pub tag: String,
+    pub coordinates: Geometry,
+}
+
+#[derive(Default)]
+pub struct ParsedFile {
+    pub entries: Vec<ImportEntry>,
+    pub invalid: Vec<String>,
+}
+
+fn pixel_box(xmin: f64, ymin: f64, xmax: f64, ymax: f64) -> Geometry {
+    Geometry {
+        units: CoordinateUnits::Pixel,
+        shape: Shape::BoundingBox {
+            x: xmin,
+            y: ymin,
+            width: xmax - xmin,
+            height: ymax - ymin,
+        },
+    }
+}
+
+fn file_stem(file_name: &str) -> String {
+    Path::new(file_name)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn base_name(file_name: &str) -> String {
+    Path::new(file_name)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// Match an image name of an annotation file to a photo by stored or uploaded
+// file name, YOLO files only know the name without extension
+pub fn find_photo<'a>(photos: &'a [ImageMeta], image: &str) -> Option<&'a ImageMeta> {
+    let name = base_name(image);
+    let stem = file_stem(image);
+    photos
+        .iter()
+        .find(|photo| photo.file_path == name || photo.original_filename.as_deref() == Some(name.as_str()))
+        .or_else(|| {
+            photos.iter().find(|photo| {
+                file_stem(&photo.file_path) == stem
+                    || photo.original_filename.as_deref().map(file_stem).as_deref() == Some(stem.as_str())
+            })
+        })
+}
+
+#[derive(Deserialize)]
+struct CocoDataset {
+    images: Vec<CocoImage>,
+    annotations: Vec<CocoAnnotation>,
+    categories: Vec<CocoCategory>,
+}
+
+#[derive(Deserialize)]
+struct CocoImage {
+    id: i64,
+    file_name: String,
+}
+
+#[derive(Deserialize)]
+struct CocoAnnotation {
+    image_id: i64,
+    category_id: i64,
+    #[serde(default)]
+    bbox: Vec<f64>,
+    #[serde(default)]
+    segmentation: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct CocoCategory {
+    id: i64,
+    name: String,
+}
+
+fn coco(content: &[u8]) -> Result<ParsedFile, String> {
+    let dataset: CocoDataset = serde_json::from_slice(content).map_err(|e| e.to_string())?;
+    let mut parsed = ParsedFile::default();
+
+    for (i, annotation) in dataset.annotations.iter().enumerate() {
+        let image = match dataset.images.iter().find(|image| image.id == annotation.image_id) {
+            Some(image) => image,
+            None => {
+                parsed.invalid.push(format!("annotation {}: unknown image_id {}", i, annotation.image_id));
+                continue;
+            }
+        };
+        let category = match dataset.categories.iter().find(|c| c.id == annotation.category_id) {
+            Some(category) => category,
+            None => {
+                parsed.invalid.push(format!("annotation {}: unknown category_id {}", i, annotation.category_id));
+                continue;
+            }
+        };
+
+        // Polygon segmentation if present, RLE masks are not supported
+        let polygon: Option<Vec<f64>> = annotation
+            .segmentation
+            .as_array()
+            .and_then(|polygons| polygons.first())
+            .and_then(|polygon| serde_json::from_value(polygon.clone()).ok());
+        let coordinates = match (polygon, annotation.bbox.as_slice()) {
+            (Some(values), _) if values.len() >= 6 && values.len() % 2 == 0 => Geometry {
+                units: CoordinateUnits::Pixel,
+                shape: Shape::Polygon {
+                    points: values.chunks(2).map(|p| Point { x: p[0], y: p[1] }).collect(),
+                },
+            },
+            (_, [x, y, width, height]) => pixel_box(*x, *y, x + width, y + height),
+            _ => {
+                parsed.invalid.push(format!("annotation {}: neither bbox nor polygon segmentation", i));
+                continue;
+            }
+        };
+
+        parsed.entries.push(ImportEntry {
+            image: image.file_name.clone(),
+            tag: category.name.clone(),
+            coordinates,
+        });
+    }
+    Ok(parsed)
+}
+
+#[derive(Default)]
+struct VocObject {
+    name: Option<String>,
+    bndbox: [Option<f64>; 4],
+}
+
+fn voc(content: &[u8]) -> Result<ParsedFile, String> {
+    let mut reader = Reader::from_reader(content);
+    reader.trim_text(true);
+
+    let mut path: Vec<String> = Vec::new();
+    let mut buf = Vec::new();
+    let mut filename = None;
+    let mut objects = Vec::new();
+    let mut object = VocObject::default();
+
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(e)) => {
+                let name = String::from_utf8_lossy(e.name()).into_owned();
+                if name == "object" {
+                    object = VocObject::default();
+                }
+                path.push(name);
+            }
+            Ok(Event::End(_)) if path.last().map(|s| s.as_str()) == Some("object") => {
+                path.pop();
+                objects.push(std::mem::take(&mut object));
+            }
+            Ok(Event::End(_)) => {
+                path.pop();
+            }
+            Ok(Event::Text(e)) => {
+                let text = e.unescape_and_decode(&reader).map_err(|e| e.to_string())?;
+                let parent = path.len().checked_sub(2).map(|i| path[i].as_str());
+                let element = path.last().map(|s| s.as_str());
+                let corner = ["xmin", "ymin", "xmax", "ymax"].iter().position(|c| Some(*c) == element);
+                match (parent, element, corner) {
+                    (Some("annotation"), Some("filename"), _) => filename = Some(text),
+                    (Some("object"), Some("name"), _) => object.name = Some(text),
+                    (Some("bndbox"), _, Some(index)) => object.bndbox[index] = text.trim().parse().ok(),
+                    _ => {}
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(format!("position {}: {}", reader.buffer_position(), e)),
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    let filename = filename.ok_or_else(|| "missing <filename>".to_string())?;
+    let mut parsed = ParsedFile::default();
+    for (i, object) in objects.into_iter().enumerate() {
+        match (object.name, object.bndbox) {
+            (Some(name), [Some(xmin), Some(ymin), Some(xmax), Some(ymax)]) => parsed.entries.push(ImportEntry {
+                image: filename.clone(),
+                tag: name,
+                coordinates: pixel_box(xmin, ymin, xmax, ymax),
+            }),
+            _ => parsed.invalid.push(format!("object {}: missing name or bndbox", i)),
+        }
+    }
+    Ok(parsed)
+}
+
+fn yolo(file_name: &str, content: &[u8], classes: &[String]) -> Result<ParsedFile, String> {
+    let content = std::str::from_utf8(content).map_err(|e| e.to_string())?;
+    let image = file_stem(file_name);
+    let mut parsed = ParsedFile::default();
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let values: Option<Vec<f64>> = fields[1..].iter().map(|v| v.parse().ok()).collect();
+        let tag = fields[0].parse::<usize>().ok().and_then(|class| classes.get(class));
+        let (tag, values) = match (tag, values) {
+            (Some(tag), Some(values)) => (tag, values),
+            (None, _) => {
+                parsed.invalid.push(format!("line {}: unknown class '{}'", i + 1, fields[0]));
+                continue;
+            }
+            (_, None) => {
+                parsed.invalid.push(format!("line {}: coordinates are not numbers", i + 1));
+                continue;
+            }
+        };
+
+        let shape = if values.len() == 4 {
+            // Rounding of exported centers may push the box slightly outside of the image
+            let (cx, cy, width, height) = (values[0], values[1], values[2], values[3]);
+            let (xmin, ymin) = ((cx - width / 2.0).max(0.0), (cy - height / 2.0).max(0.0));
+            let (xmax, ymax) = ((cx + width / 2.0).min(1.0), (cy + height / 2.0).min(1.0));
+            Shape::BoundingBox { x: xmin, y: ymin, width: xmax - xmin, height: ymax - ymin }
+        } else if values.len() >= 6 && values.len() % 2 == 0 {
+            Shape::Polygon {
+                points: values.chunks(2).map(|p| Point { x: p[0], y: p[1] }).collect(),
+            }
+        } else {
+            parsed.invalid.push(format!("line {}: expected a box or polygon", i + 1));
+            continue;
+        };
+
+        parsed.entries.push(ImportEntry {
+            image: image.clone(),
+            tag: tag.clone(),
+            coordinates: Geometry { units: CoordinateUnits::Relative, shape },
+        });
+    }
+    Ok(parsed)
+}
+
+fn csv(content: &[u8]) -> Result<ParsedFile, String> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(content);
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|e| e.to_string())?
+        .iter()
+        .map(|h| h.to_lowercase())
+        .collect();
+    let column = |names: &[&str]| {
+        headers
+            .iter()
+            .position(|h| names.contains(&h.as_str()))
+            .ok_or_else(|| format!("missing column {}", names.join("/")))
+    };
+    let filename = column(&["filename", "file_name", "image"])?;
+    let label = column(&["label", "class", "tag"])?;
+    let corners = [column(&["xmin"])?, column(&["ymin"])?, column(&["xmax"])?, column(&["ymax"])?];
+
+    let mut parsed = ParsedFile::default();
+    for (i, record) in reader.records().enumerate() {
+        // Line 1 is the header
+        let line = i + 2;
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                parsed.invalid.push(format!("line {}: {}", line, e));
+                continue;
+            }
+        };
+        let values: Option<Vec<f64>> = corners
+            .iter()
+            .map(|c| record.get(*c).and_then(|v| v.parse().ok()))
+            .collect();
+        match (record.get(filename), record.get(label), values) {
+            (Some(image), Some(tag), Some(values)) => parsed.entries.push(ImportEntry {
+                image: image.to_string(),
+                tag: tag.to_string(),
+                coordinates: pixel_box(values[0], values[1], values[2], values[3]),
+            }),
+            _ => parsed.invalid.push(format!("line {}: missing filename, label or coordinates", line)),
+        }
+    }
+    Ok(parsed)
+}
+
+// `classes` maps YOLO class ids to labels
+pub fn parse(
+    format: ImportFormat,
+    file_name: &str,
+    content: &[u8],
+    classes: &[String],
+) -> Result<ParsedFile, String> {
+    match format {
+        ImportFormat::Coco => coco(content),
+        ImportFormat::Voc => voc(content),
+        ImportFormat::Yolo => yolo(file_name, content, classes),
+        ImportFormat::Csv => csv(content),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classes() -> Vec<String> {
+        vec!["car".to_string(), "cat".to_string()]
+    }
+
+    fn bounding_box(geometry: &Geometry) -> (f64, f64, f64, f64) {
+        match geometry.shape {
+            Shape::BoundingBox { x, y, width, height } => (x, y, width, height),
+            _ => panic!("expected a bounding box, got {:?}", geometry),
+        }
+    }
+
+    fn photo(id: i32, file_path: &str, original_filename: Option<&str>) -> ImageMeta {
+        ImageMeta {
+            id,
+            album_id: 1,
+            file_path: file_path.to_string(),
+            original_filename: original_filename.map(String::from),
+            width: Some(640),
+            height: Some(480),
+            mime_type: None,
+            byte_size: None,
+            checksum: None,
+            perceptual_hash: None,
+            orientation: None,
+            captured_at: None,
+            camera_make: None,
+            camera_model: None,
+        }
+    }
+
+    #[test]
+    fn format_follows_extension() {
+        assert_eq!(ImportFormat::of_file("annotations.json"), Some(ImportFormat::Coco));
+        assert_eq!(ImportFormat::of_file("street.XML"), Some(ImportFormat::Voc));
+        assert_eq!(ImportFormat::of_file("labels/street.txt"), Some(ImportFormat::Yolo));
+        assert_eq!(ImportFormat::of_file("boxes.csv"), Some(ImportFormat::Csv));
+        assert_eq!(ImportFormat::of_file("boxes.xlsx"), None);
+        assert_eq!(ImportFormat::of_file("csv"), None);
+    }
+
+    #[test]
+    fn reads_coco() {
+        let content = include_bytes!("../tests/fixtures/import/coco.json");
+        let parsed = parse(ImportFormat::Coco, "coco.json", content, &[]).unwrap();
+
+        assert_eq!(parsed.entries.len(), 2);
+        assert_eq!(parsed.entries[0].image, "1.png");
+        assert_eq!(parsed.entries[0].tag, "car");
+        assert_eq!(parsed.entries[0].coordinates.units, CoordinateUnits::Pixel);
+        assert_eq!(bounding_box(&parsed.entries[0].coordinates), (10.0, 20.0, 100.0, 50.0));
+
+        // The segmentation wins over the bbox
+        assert_eq!(parsed.entries[1].image, "street.jpg");
+        assert_eq!(parsed.entries[1].tag, "cat");
+        match &parsed.entries[1].coordinates.shape {
+            Shape::Polygon { points } => assert_eq!(points.len(), 3),
+            shape => panic!("expected a polygon, got {:?}", shape),
+        }
+
+        assert_eq!(parsed.invalid.len(), 3);
+        assert!(parsed.invalid[0].contains("unknown image_id 9"));
+        assert!(parsed.invalid[1].contains("unknown category_id 7"));
+        assert!(parsed.invalid[2].contains("neither bbox nor polygon"));
+    }
+
+    #[test]
+    fn rejects_malformed_coco() {
+        let content = include_bytes!("../tests/fixtures/import/coco_malformed.json");
+        assert!(parse(ImportFormat::Coco, "coco.json", content, &[]).is_err());
+        // Valid JSON, but no dataset
+        assert!(parse(ImportFormat::Coco, "coco.json", b"[1, 2, 3]", &[]).is_err());
+    }
+
+    #[test]
+    fn reads_voc() {
+        let content = include_bytes!("../tests/fixtures/import/voc.xml");
+        let parsed = parse(ImportFormat::Voc, "voc.xml", content, &[]).unwrap();
+
+        assert_eq!(parsed.entries.len(), 1);
+        assert_eq!(parsed.entries[0].image, "street.jpg");
+        assert_eq!(parsed.entries[0].tag, "car");
+        assert_eq!(bounding_box(&parsed.entries[0].coordinates), (10.0, 20.0, 100.0, 50.0));
+        // Unreadable corner and missing bndbox
+        assert_eq!(parsed.invalid, vec!["object 1: missing name or bndbox", "object 2: missing name or bndbox"]);
+    }
+
+    #[test]
+    fn rejects_malformed_voc() {
+        let content = include_bytes!("../tests/fixtures/import/voc_malformed.xml");
+        assert!(parse(ImportFormat::Voc, "voc.xml", content, &[]).is_err());
+
+        let content = include_bytes!("../tests/fixtures/import/voc_without_filename.xml");
+        assert_eq!(parse(ImportFormat::Voc, "voc.xml", content, &[]).err(), Some("missing <filename>".to_string()));
+    }
+
+    #[test]
+    fn reads_yolo() {
+        let content = include_bytes!("../tests/fixtures/import/street.txt");
+        let parsed = parse(ImportFormat::Yolo, "labels/street.txt", content, &classes()).unwrap();
+
+        assert_eq!(parsed.entries.len(), 3);
+        assert!(parsed.entries.iter().all(|entry| entry.image == "street"));
+        assert!(parsed.entries.iter().all(|entry| entry.coordinates.units == CoordinateUnits::Relative));
+
+        assert_eq!(parsed.entries[0].tag, "car");
+        let (x, y, width, height) = bounding_box(&parsed.entries[0].coordinates);
+        assert!((x - 0.4).abs() < 1e-9 && (y - 0.3).abs() < 1e-9);
+        assert!((width - 0.2).abs() < 1e-9 && (height - 0.4).abs() < 1e-9);
+
+        assert_eq!(parsed.entries[1].tag, "cat");
+        match &parsed.entries[1].coordinates.shape {
+            Shape::Polygon { points } => assert_eq!(points.len(), 3),
+            shape => panic!("expected a polygon, got {:?}", shape),
+        }
+
+        // Rounded centers close to the border are clamped to the image
+        let (x, _, width, _) = bounding_box(&parsed.entries[2].coordinates);
+        assert!((x + width - 1.0).abs() < 1e-9);
+        assert!(parsed.entries[2].coordinates.validate(640, 480).is_ok());
+
+        assert_eq!(parsed.invalid, vec![
+            "line 5: unknown class '7'",
+            "line 6: unknown class 'cat'",
+            "line 7: coordinates are not numbers",
+            "line 8: expected a box or polygon",
+        ]);
+    }
+
+    #[test]
+    fn rejects_malformed_yolo() {
+        assert!(parse(ImportFormat::Yolo, "street.txt", &[0x30, 0x20, 0xff, 0xfe], &classes()).is_err());
+
+        // Without classes every class id is unknown
+        let content = include_bytes!("../tests/fixtures/import/street.txt");
+        let parsed = parse(ImportFormat::Yolo, "street.txt", content, &[]).unwrap();
+        assert!(parsed.entries.is_empty());
+        assert_eq!(parsed.invalid.len(), 7);
+    }
+
+    #[test]
+    fn reads_csv() {
+        let content = include_bytes!("../tests/fixtures/import/annotations.csv");
+        let parsed = parse(ImportFormat::Csv, "annotations.csv", content, &[]).unwrap();
+
+        assert_eq!(parsed.entries.len(), 2);
+        assert_eq!(parsed.entries[0].image, "1.png");
+        assert_eq!(parsed.entries[0].tag, "car");
+        assert_eq!(bounding_box(&parsed.entries[0].coordinates), (10.0, 20.0, 100.0, 50.0));
+        // Fields are trimmed
+        assert_eq!(parsed.entries[1].tag, "cat");
+
+        assert_eq!(parsed.invalid.len(), 2);
+        assert!(parsed.invalid[0].starts_with("line 4: missing"));
+        assert!(parsed.invalid[1].starts_with("line 5: "));
+    }
+
+    #[test]
+    fn rejects_malformed_csv() {
+        let content = include_bytes!("../tests/fixtures/import/annotations_missing_column.csv");
+        assert_eq!(
+            parse(ImportFormat::Csv, "annotations.csv", content, &[]).err(),
+            Some("missing column ymax".to_string())
+        );
+    }
+
+    #[test]
+    fn matches_photos_by_file_name() {
+        let photos = vec![
+            photo(1, "1.png", Some("street.jpg")),
+            photo(2, "2.png", Some("house.png")),
+            photo(3, "3.jpg", None),
+        ];
+        let found = |image: &str| find_photo(&photos, image).map(|photo| photo.id);
+
+        assert_eq!(found("1.png"), Some(1));
+        assert_eq!(found("house.png"), Some(2));
+        assert_eq!(found("images/street.jpg"), Some(1));
+        assert_eq!(found("3.jpg"), Some(3));
+        // YOLO names have no extension
+        assert_eq!(found("street"), Some(1));
+        assert_eq!(found("3"), Some(3));
+        assert_eq!(found("4.png"), None);
+    }
+}
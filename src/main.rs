@@ -7,26 +7,37 @@ use std::path::PathBuf;
 
 use listenfd::ListenFd;
 use log::{error, info};
-use std::fs::File;
-use std::io::Read;
 use tokio_postgres::NoTls;
 
+mod archive;
+mod cli;
 mod config;
 mod db;
+mod derivatives;
 mod errors;
+mod export;
 mod handlers;
+mod import;
+mod maintenance;
+mod migrations;
+mod photo_metadata;
+mod storage;
 
 mod admin_handlers;
 mod album_handlers;
 mod my_cookie_policy;
 mod my_identity_service;
+mod permissions;
+mod image_utils;
 mod utils;
+#[cfg(test)]
+mod test_fixtures;
 
 mod album_models;
 mod user_models;
 
+use crate::config::StorageBackend;
 use crate::handlers::{login, logout, status};
-use user_models::ROLES;
 
 struct DistPath {
     path: PathBuf,
@@ -85,8 +96,19 @@ async fn main() -> std::io::Result<()> {
     // Create db connection pool
     let pool = conf.postgres.create_pool(NoTls).unwrap();
 
+    let tagify_data_path = conf.tagify_data.path;
+    let tagify_albums_path = format!("{}/albums/", &tagify_data_path);
+    let storage_config = conf.storage.clone();
+
+    // Maintenance commands run instead of the server
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        let storage = storage::from_config(&storage_config, &tagify_albums_path);
+        std::process::exit(cli::run(&args, &pool, storage.as_ref()).await);
+    }
+
     // Create connection to database
-    let client = match pool.get().await {
+    let mut client = match pool.get().await {
         Ok(i) => i,
         Err(e) => {
             error!("Could not connect to database err: {}", e);
@@ -94,26 +116,12 @@ async fn main() -> std::io::Result<()> {
         }
     };
 
-    // Read schema.sql and create db table
-    let mut schema = String::new();
-    let schema_path = conf_path.join("schema.sql");
-    match File::open(schema_path.clone()) {
-        Ok(mut i) => i.read_to_string(&mut schema).unwrap(),
+    // Bring the db schema up to date
+    match migrations::run_pending(&mut client).await {
+        Ok(applied) => info!("Applied {} pending db migration(s)", applied.len()),
         Err(e) => {
-            error!(
-                "Could not open schema.sql file at {} err: {}",
-                schema_path.to_str().unwrap(),
-                e
-            );
-            panic!("Could not open schema.sql file");
-        }
-    };
-
-    match client.batch_execute(&schema).await {
-        Ok(i) => i,
-        Err(e) => {
-            error!("Failed to apply db schema err: {}", e);
-            panic!("Failed to apply db schema");
+            error!("Failed to apply db migrations err: {:?}", e);
+            panic!("Failed to apply db migrations");
         }
     }
 
@@ -134,24 +142,32 @@ async fn main() -> std::io::Result<()> {
      }
 
     // Create data folder tagify_data. Default: in code base folder
-    let tagify_data_path = conf.tagify_data.path;
-    let tagify_albums_path = format!("{}/albums/", &tagify_data_path);
-
-    match std::fs::create_dir_all(&tagify_albums_path) {
-        Ok(_) => info!("Created data folder under{}", &tagify_albums_path),
-        Err(e) => {
-            error!("Error creating folder for album with id={}: {:?}", &tagify_albums_path, e);
+    if storage_config.backend == StorageBackend::Local {
+        match std::fs::create_dir_all(&tagify_albums_path) {
+            Ok(_) => info!("Created data folder under{}", &tagify_albums_path),
+            Err(e) => {
+                error!("Error creating folder for album with id={}: {:?}", &tagify_albums_path, e);
+            }
         }
     }
 
+    // Delete expired sessions and share links, purge the trash and remove files of purged photos and albums
+    actix_rt::spawn(maintenance::run_cleanup(
+        pool.clone(),
+        storage::from_config(&storage_config, &tagify_albums_path),
+        conf.trash.clone(),
+    ));
+
     let temp = conf.server.key.clone();
+    let tagging = conf.tagging.clone();
+    let trash = conf.trash.clone();
 
     // Register http routes
     let mut server = HttpServer::new(move || {
         let serve_file_service: fs::Files;
         let path_arg: DistPath;
         let secure_cookie: bool;
-        let max_age = 30 * 24 * 60 * 60; // days
+        let max_age = i64::from(my_identity_service::SESSION_LIFETIME_DAYS) * 24 * 60 * 60;
 
         // Check if in release mode if so use DIST env variable as path for serving frontend
         if cfg!(debug_assertions) {
@@ -180,15 +196,9 @@ async fn main() -> std::io::Result<()> {
         }
         let cookie_key = temp.as_bytes();
 
-        let cookie_factory_user = my_cookie_policy::MyCookieIdentityPolicy::new(cookie_key)
-            .name(ROLES[1])
-            .path("/")
-            .secure(secure_cookie)
-            .max_age(max_age)
-            .same_site(actix_http::cookie::SameSite::Strict);
-
-        let cookie_factory_admin = my_cookie_policy::MyCookieIdentityPolicy::new(cookie_key)
-            .name(ROLES[0])
+        // One session cookie for all roles
+        let cookie_factory = my_cookie_policy::MyCookieIdentityPolicy::new(cookie_key)
+            .name("session")
             .path("/")
             .secure(secure_cookie)
             .max_age(max_age)
@@ -198,7 +208,7 @@ async fn main() -> std::io::Result<()> {
             .wrap(middleware::Compress::default())
             .data(path_arg)
             // Give login handler access to cookie factory
-            .data(cookie_factory_user.clone())
+            .data(cookie_factory.clone())
             // Serve every file in directory from ../dist
             .service(serve_file_service)
             // Serve index.html
@@ -206,13 +216,18 @@ async fn main() -> std::io::Result<()> {
             .data(pool.clone())
             // Data path
             // .data(tagify_data_path.clone())
-            // Albums path
-            .data(tagify_albums_path.clone())
+            // Storage of album files
+            .data(storage::from_config(&storage_config, &tagify_albums_path))
+            // Tagging lock settings
+            .data(tagging.clone())
+            // Trash retention settings
+            .data(trash.clone())
             // Enable logger
             .wrap(Logger::default())
             //limit the maximum amount of data that server will accept
+            //(polygon annotations need more than a few kB)
             .app_data(web::JsonConfig::default()
-                .limit(4096)
+                .limit(65536)
                 .error_handler(|err, _req| {
                     actix_web::error::ErrorBadRequest(err)
                 }))
@@ -223,10 +238,13 @@ async fn main() -> std::io::Result<()> {
                     .service(web::resource("/login").route(web::post().to(login)))
                     .service(
                         web::scope("/admin")
-                            .wrap(my_identity_service::IdentityService::new(
-                                cookie_factory_admin,
-                                pool.clone(),
-                            ))
+                            .wrap(
+                                my_identity_service::IdentityService::new(
+                                    cookie_factory.clone(),
+                                    pool.clone(),
+                                )
+                                .require(permissions::can_use_admin_api),
+                            )
                             .route("/logout", web::post().to(logout))
                             //get all users
                             .route("/users", web::get().to(admin_handlers::get_all_users))
@@ -244,6 +262,41 @@ async fn main() -> std::io::Result<()> {
                                 "/user/{user_id}",
                                 web::delete().to(admin_handlers::delete_user),
                             )
+                            //log out all sessions of a user
+                            .route(
+                                "/user/{user_id}/sessions",
+                                web::delete().to(admin_handlers::delete_user_sessions),
+                            )
+                            //own login sessions
+                            .route("/sessions", web::get().to(handlers::get_sessions))
+                            .route("/sessions", web::delete().to(handlers::delete_other_sessions))
+                            .route("/sessions/{session_id}", web::delete().to(handlers::delete_session))
+                            //own api tokens
+                            .route("/me/tokens", web::get().to(handlers::get_api_tokens))
+                            .route("/me/tokens", web::post().to(handlers::create_api_token))
+                            .route("/me/tokens/{token_id}", web::delete().to(handlers::delete_api_token))
+                            //fill in file properties of photos uploaded before they were recorded
+                            .route(
+                                "/maintenance/backfill",
+                                web::post().to(admin_handlers::backfill_image_properties),
+                            )
+                            //report album files without image meta and image metas without file
+                            .route(
+                                "/maintenance/consistency",
+                                web::get().to(admin_handlers::check_consistency),
+                            )
+                            //same, repairing the differences as requested
+                            .route(
+                                "/maintenance/consistency",
+                                web::post().to(admin_handlers::repair_consistency),
+                            )
+                            //recompute photo counters of all albums
+                            .route(
+                                "/maintenance/counters",
+                                web::post().to(admin_handlers::recompute_album_counters),
+                            )
+                            //albums and photos of all users in the trash
+                            .route("/trash", web::get().to(admin_handlers::get_trash))
                             .service(
                                 web::scope("/albums")
                                     //get all albums
@@ -255,6 +308,18 @@ async fn main() -> std::io::Result<()> {
                                     /////////////////////////////////////
                                     .route("/{album_id}/photos/{photo_id}", web::get().to(admin_handlers::get_photo))
                                     .route("/{album_id}/photos/{photo_id}", web::delete().to(admin_handlers::delete_photo))
+                                    //take albums and photos out of the trash
+                                    .route("/{album_id}/restore", web::post().to(album_handlers::restore_album))
+                                    .route("/{album_id}/photos/{photo_id}/restore", web::post().to(album_handlers::restore_photo))
+                                    //members of any album
+                                    .route("/{album_id}/members", web::get().to(album_handlers::get_album_members))
+                                    .route("/{album_id}/members", web::post().to(album_handlers::add_album_member))
+                                    .route("/{album_id}/members/{user_id}", web::put().to(album_handlers::update_album_member))
+                                    .route("/{album_id}/members/{user_id}", web::delete().to(album_handlers::delete_album_member))
+                                    //share links of any album
+                                    .route("/{album_id}/share-links", web::get().to(album_handlers::get_share_links))
+                                    .route("/{album_id}/share-links", web::post().to(album_handlers::create_share_link))
+                                    .route("/{album_id}/share-links/{link_id}", web::delete().to(album_handlers::delete_share_link))
                                     ////////////////////////////////////////
                                     .route(
                                         "/{album_id}",
@@ -276,7 +341,7 @@ async fn main() -> std::io::Result<()> {
                     .service(
                         web::scope("/user")
                             .wrap(my_identity_service::IdentityService::new(
-                                cookie_factory_user,
+                                cookie_factory.clone(),
                                 pool.clone(),
                             ))
                             .route("/logout", web::post().to(logout))
@@ -286,6 +351,18 @@ async fn main() -> std::io::Result<()> {
                             .route("/me", web::put().to(handlers::update_user_nickname))
                             //update password
                             .route("/me/password", web::put().to(handlers::update_user_password))
+                            //own login sessions, e.g. to log out a lost device
+                            .route("/sessions", web::get().to(handlers::get_sessions))
+                            .route("/sessions", web::delete().to(handlers::delete_other_sessions))
+                            .route("/sessions/{session_id}", web::delete().to(handlers::delete_session))
+                            //personal api tokens for scripts, sent as "Authorization: Bearer <token>"
+                            .route("/me/tokens", web::get().to(handlers::get_api_tokens))
+                            .route("/me/tokens", web::post().to(handlers::create_api_token))
+                            .route("/me/tokens/{token_id}", web::delete().to(handlers::delete_api_token))
+                            //own albums and photos in the trash
+                            .route("/trash", web::get().to(album_handlers::get_own_trash))
+                            //albums of other users the user was invited to
+                            .route("/assignments", web::get().to(album_handlers::get_assigned_albums))
                             .service(
                                 web::scope("/albums")
                                     //get all own albums
@@ -311,10 +388,36 @@ async fn main() -> std::io::Result<()> {
                                     // ),
                                     /////////////////////////////////////
                                     .route("/{album_id}/photos", web::post().to(handlers::post_photo))
+                                    //add photos packed in zip or tar.gz archives
+                                    .route("/{album_id}/photos/archive", web::post().to(handlers::post_photo_archive))
                                     .route("/{album_id}/photos/{photo_id}", web::get().to(handlers::get_photo))
                                     .route("/{album_id}/photos/{photo_id}", web::put().to(handlers::put_photo))
                                     .route("/{album_id}/photos/{photo_id}", web::delete().to(handlers::delete_photo))
+                                    //take own albums and photos out of the trash
+                                    .route("/{album_id}/restore", web::post().to(album_handlers::restore_album))
+                                    .route("/{album_id}/photos/{photo_id}/restore", web::post().to(album_handlers::restore_photo))
+                                    //invite taggers, reviewers and co-owners
+                                    .route("/{album_id}/members", web::get().to(album_handlers::get_album_members))
+                                    .route("/{album_id}/members", web::post().to(album_handlers::add_album_member))
+                                    .route("/{album_id}/members/{user_id}", web::put().to(album_handlers::update_album_member))
+                                    .route("/{album_id}/members/{user_id}", web::delete().to(album_handlers::delete_album_member))
+                                    //share the album with people without login
+                                    .route("/{album_id}/share-links", web::get().to(album_handlers::get_share_links))
+                                    .route("/{album_id}/share-links", web::post().to(album_handlers::create_share_link))
+                                    .route("/{album_id}/share-links/{link_id}", web::delete().to(album_handlers::delete_share_link))
                                     ////////////////////////////////////////
+                                    //manage the labels allowed in the album
+                                    .route("/{album_id}/tags", web::post().to(album_handlers::add_album_tag))
+                                    .route("/{album_id}/tags", web::put().to(album_handlers::rename_album_tag))
+                                    .route("/{album_id}/tags/merge", web::post().to(album_handlers::merge_album_tags))
+                                    .route("/{album_id}/tags/retire", web::post().to(album_handlers::retire_album_tag))
+                                    //export verified annotations as dataset
+                                    .route("/{album_id}/export", web::get().to(album_handlers::export_album))
+                                    //import existing annotations for review
+                                    .route("/{album_id}/import", web::post().to(album_handlers::import_annotations))
+                                    //review identical and similar photos
+                                    .route("/{album_id}/duplicates", web::get().to(album_handlers::get_duplicates))
+                                    .route("/{album_id}/duplicates/resolve", web::post().to(album_handlers::resolve_duplicate))
                             )
                             .service(
                                 web::scope("/tag")
@@ -323,12 +426,27 @@ async fn main() -> std::io::Result<()> {
                                     //tag album
                                     .route("/action/{photo_id}", web::put().to(album_handlers::tag_photo_by_id))
                                     //verify tag
-                                    .route("/verify/{photo_id}", web::put().to(album_handlers::verify_photo_by_id)),
+                                    .route("/verify/{photo_id}", web::put().to(album_handlers::verify_photo_by_id))
+                                    //renew or release the tagging lock of a photo
+                                    .route("/lock/{photo_id}", web::put().to(album_handlers::renew_photo_lock))
+                                    .route("/lock/{photo_id}", web::delete().to(album_handlers::release_photo_lock))
+                                    //add, edit and delete single annotations
+                                    .route("/annotations/{photo_id}", web::post().to(album_handlers::add_annotation))
+                                    .route("/annotations/{photo_id}/{annotation_id}", web::put().to(album_handlers::update_annotation))
+                                    .route("/annotations/{photo_id}/{annotation_id}", web::delete().to(album_handlers::delete_annotation)),
                             ),
                     )
                     .service(
                         web::scope("/albums")
-                            //get albums for preview (all)
+                            //logged in users also see internal albums and private albums they work on
+                            .wrap(
+                                my_identity_service::IdentityService::new(
+                                    cookie_factory.clone(),
+                                    pool.clone(),
+                                )
+                                .optional(),
+                            )
+                            //get albums for preview (all visible)
                             .route("", web::get().to(album_handlers::get_all_albums))
                             //get album by id
                             .route(
@@ -336,7 +454,16 @@ async fn main() -> std::io::Result<()> {
                                 web::get().to(album_handlers::get_album_by_id),
                             )
                             //get photos from album (preview)
-                            .route("/{album_id}/photos/{index}", web::get().to(album_handlers::get_photos_from_album)),
+                            .route("/{album_id}/photos/{index}", web::get().to(album_handlers::get_photos_from_album))
+                            //get photo file (original, thumb or medium)
+                            .route("/{album_id}/photos/{photo_id}/file", web::get().to(handlers::get_photo)),
+                    )
+                    .service(
+                        web::scope("/shared/{token}")
+                            //album of a share link, no login needed
+                            .route("", web::get().to(album_handlers::get_shared_album))
+                            .route("/photos/{index}", web::get().to(album_handlers::get_shared_photos))
+                            .route("/files/{photo_id}", web::get().to(handlers::get_shared_photo)),
                     ),
 
             )
@@ -0,0 +1,378 @@
+use crate::config::Trash;
+use crate::db;
+use crate::derivatives;
+use crate::errors::{DBError, MaintenanceError, StorageError};
+use crate::image_utils;
+use crate::photo_metadata;
+use crate::storage::{self, Storage};
+use crate::user_models::{
+    BackfillReport, ConsistencyRepair, ConsistencyReport, CreateImageMeta, FileDeletion, MissingFile, OrphanFileAction
+};
+
+use actix_web::error::BlockingError;
+use actix_web::web;
+use deadpool_postgres::Pool;
+use log::{error, info};
+use std::collections::{BTreeSet, HashSet};
+use std::time::Duration;
+
+/*
+ * Jobs that complete or repair stored data. They are started by an admin via
+ * the api or on the command line, e.g.
+ *   backend backfill
+ *   backend consistency --orphan-files quarantine --delete-missing
+ *
+ * Albums and photos are purged from the trash after the retention period by
+ * the cleanup job. Files of purged photos and albums are removed by the file
+ * deletion job, which also runs after deletions by the consistency check.
+ */
+const BATCH_SIZE: i64 = 100;
+const MAX_DELETION_ATTEMPTS: i32 = 10;
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+// Fill in size, type, checksum and perceptual hash of photos stored before these were recorded on upload
+pub async fn backfill_image_properties(
+    client: &deadpool_postgres::Client,
+    storage: &dyn Storage,
+) -> Result<BackfillReport, DBError> {
+    let mut report = BackfillReport {
+        updated: 0,
+        missing_files: Vec::new(),
+        not_images: Vec::new(),
+    };
+
+    // Rows that can not be completed stay incomplete, so page by id
+    let mut last_id = 0;
+    loop {
+        let image_metas = db::get_image_metas_without_properties(client, last_id, BATCH_SIZE).await?;
+        if image_metas.is_empty() {
+            break;
+        }
+
+        for image_meta in image_metas {
+            last_id = image_meta.id;
+            let filepath = storage::photo_key(image_meta.album_id, &image_meta.file_path);
+
+            let content = match storage.get(&filepath).await {
+                Ok(item) => item,
+                Err(e) => {
+                    error!("Could not read image file {}: {}", filepath, e);
+                    report.missing_files.push(filepath);
+                    continue;
+                }
+            };
+            let properties = web::block(move || {
+                Ok::<_, ()>((
+                    content.len() as i64,
+                    image_utils::checksum(&content),
+                    image_utils::inspect_image(&content),
+                    image_utils::perceptual_hash(&content),
+                ))
+            })
+            .await;
+            let (byte_size, checksum, inspected, perceptual_hash) = match properties {
+                Ok(item) => item,
+                Err(e) => {
+                    error!("Could not inspect image file {}: {}", filepath, e);
+                    continue;
+                }
+            };
+
+            let (dimensions, mime_type) = match inspected {
+                Ok((image_type, dimensions)) => (Some(dimensions), Some(image_type.mime_type())),
+                Err(e) => {
+                    info!("Image file {} is not a supported image: {}", filepath, e);
+                    report.not_images.push(filepath);
+                    (None, None)
+                }
+            };
+            db::set_image_properties(client, &image_meta.id, dimensions, mime_type, byte_size, &checksum, perceptual_hash)
+                .await?;
+            report.updated += 1;
+        }
+    }
+
+    info!(
+        "Backfilled properties of {} images, {} files missing",
+        report.updated,
+        report.missing_files.len()
+    );
+    Ok(report)
+}
+
+// Add an image meta for a stored file, the file keeps its name
+async fn import_file(
+    client: &mut deadpool_postgres::Client,
+    storage: &dyn Storage,
+    album_id: i32,
+    file_path: &str,
+) -> Result<(), String> {
+    let key = storage::photo_key(album_id, file_path);
+    let content = storage.get(&key).await.map_err(|e| e.to_string())?;
+    let inspected = web::block(move || {
+        image_utils::inspect_image(&content).map(|(image_type, dimensions)| {
+            let exif = photo_metadata::read_exif(&content);
+            let image_meta = CreateImageMeta {
+                album_id,
+                file_path: String::new(),
+                original_filename: None,
+                width: Some(dimensions.0),
+                height: Some(dimensions.1),
+                mime_type: Some(image_type.mime_type().to_string()),
+                byte_size: Some(content.len() as i64),
+                checksum: Some(image_utils::checksum(&content)),
+                perceptual_hash: image_utils::perceptual_hash(&content),
+                orientation: exif.orientation,
+                captured_at: exif.captured_at,
+                camera_make: exif.camera_make,
+                camera_model: exif.camera_model,
+            };
+            (image_meta, content)
+        })
+    })
+    .await;
+    let (mut image_meta, content) = match inspected {
+        Ok(item) => item,
+        Err(BlockingError::Error(e)) => return Err(e),
+        Err(BlockingError::Canceled) => return Err("reading the image was canceled".to_string()),
+    };
+    image_meta.file_path = file_path.to_string();
+
+    let transaction = client.transaction().await.map_err(|e| e.to_string())?;
+    let image_id = db::reserve_image_id(&transaction).await.map_err(|e| e.to_string())?;
+    db::create_image_meta(&transaction, &image_id, &image_meta).await.map_err(|e| e.to_string())?;
+    transaction.commit().await.map_err(|e| e.to_string())?;
+    derivatives::generate(storage, album_id, file_path, content).await;
+    Ok(())
+}
+
+async fn quarantine_file(storage: &dyn Storage, key: &str) -> Result<(), String> {
+    let content = storage.get(key).await.map_err(|e| e.to_string())?;
+    storage.put(&storage::quarantine_key(key), content).await.map_err(|e| e.to_string())?;
+    storage.delete(key).await.map_err(|e| e.to_string())
+}
+
+async fn delete_file(storage: &dyn Storage, album_id: i32, file_path: &str) -> Result<(), String> {
+    derivatives::remove(storage, album_id, file_path).await;
+    storage.delete(&storage::photo_key(album_id, file_path)).await.map_err(|e| e.to_string())
+}
+
+async fn check_album(
+    client: &mut deadpool_postgres::Client,
+    storage: &dyn Storage,
+    repair: &ConsistencyRepair,
+    album_id: i32,
+    album_exists: bool,
+    report: &mut ConsistencyReport,
+) -> Result<(), MaintenanceError> {
+    let files: HashSet<String> = storage.list(&storage::album_folder(album_id)).await?.into_iter().collect();
+    let image_metas = if album_exists {
+        db::get_stored_image_metas_of_album(client, &album_id).await?
+    } else {
+        Vec::new()
+    };
+
+    for image_meta in &image_metas {
+        if files.contains(&image_meta.file_path) {
+            continue;
+        }
+        if repair.delete_missing {
+            // Derivatives are removed by the file deletion job
+            db::delete_image_meta(client, &image_meta.id).await?;
+            report.deleted_image_metas.push(image_meta.id);
+        }
+        report.missing_files.push(MissingFile {
+            image_id: image_meta.id,
+            album_id,
+            file_path: image_meta.file_path.clone(),
+        });
+    }
+
+    // Files of deleted photos are no orphans, they only wait for the file deletion job
+    let queued = db::get_file_deletions_of_album(client, &album_id).await?;
+    if queued.iter().any(|deletion| deletion.file_path.is_none()) {
+        return Ok(());
+    }
+    let mut referenced: HashSet<&str> = image_metas.iter().map(|image_meta| image_meta.file_path.as_str()).collect();
+    referenced.extend(queued.iter().filter_map(|deletion| deletion.file_path.as_deref()));
+    let mut orphans: Vec<&String> = files.iter().filter(|file| !referenced.contains(file.as_str())).collect();
+    orphans.sort();
+    for file_path in orphans {
+        let key = storage::photo_key(album_id, file_path);
+        report.orphan_files.push(key.clone());
+        let action = match repair.orphan_files {
+            Some(item) => item,
+            None => continue,
+        };
+        let result = match action {
+            OrphanFileAction::Import if !album_exists => Err(format!("album {} does not exist", album_id)),
+            OrphanFileAction::Import => import_file(client, storage, album_id, file_path).await,
+            OrphanFileAction::Quarantine => quarantine_file(storage, &key).await,
+            OrphanFileAction::Delete => delete_file(storage, album_id, file_path).await,
+        };
+        match (result, action) {
+            (Ok(()), OrphanFileAction::Import) => report.imported.push(key),
+            (Ok(()), OrphanFileAction::Quarantine) => report.quarantined.push(key),
+            (Ok(()), OrphanFileAction::Delete) => report.deleted_files.push(key),
+            (Err(e), _) => {
+                error!("Could not repair orphan file {}: {}", key, e);
+                report.failed.push(format!("{}: {}", key, e));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Compare the stored album files with the image metas and repair the
+// differences if asked to. Folders named after an album id hold the album
+// files, derivatives and the quarantine are not checked. Uploads to an album
+// wait while it is checked.
+pub async fn check_consistency(
+    client: &mut deadpool_postgres::Client,
+    storage: &dyn Storage,
+    repair: &ConsistencyRepair,
+) -> Result<ConsistencyReport, MaintenanceError> {
+    let mut report = ConsistencyReport::default();
+
+    let existing_albums: HashSet<i32> = db::get_album_ids(client).await?.into_iter().collect();
+    let mut album_ids: BTreeSet<i32> = existing_albums.iter().copied().collect();
+    for folder in storage.folders("").await? {
+        // "007" is no album folder, the folder of album 7 is "7"
+        match folder.parse::<i32>() {
+            Ok(album_id) if album_id.to_string() == folder => {
+                album_ids.insert(album_id);
+            }
+            _ => {}
+        }
+    }
+
+    for album_id in album_ids {
+        db::lock_album_files(client, &album_id).await?;
+        let checked = check_album(client, storage, repair, album_id, existing_albums.contains(&album_id), &mut report).await;
+        db::unlock_album_files(client, &album_id).await?;
+        checked?;
+    }
+
+    info!(
+        "Consistency check found {} orphan files and {} missing files",
+        report.orphan_files.len(),
+        report.missing_files.len()
+    );
+    Ok(report)
+}
+
+async fn delete_files(storage: &dyn Storage, deletion: &FileDeletion) -> Result<(), StorageError> {
+    match &deletion.file_path {
+        Some(file_path) => {
+            storage.delete(&storage::photo_key(deletion.album_id, file_path)).await?;
+            for size in derivatives::DERIVATIVE_SIZES.iter() {
+                storage.delete(&derivatives::derivative_key(deletion.album_id, file_path, *size)).await?;
+            }
+        }
+        None => {
+            storage.delete_folder(&storage::album_folder(deletion.album_id)).await?;
+            storage.delete_folder(&derivatives::album_folder(deletion.album_id)).await?;
+        }
+    }
+    Ok(())
+}
+
+// Remove the files of deleted photos and albums. A failed deletion is tried
+// again on the next run until MAX_DELETION_ATTEMPTS, afterwards it stays in
+// file_deletions with the last error.
+pub async fn process_file_deletions(
+    client: &deadpool_postgres::Client,
+    storage: &dyn Storage,
+) -> Result<usize, DBError> {
+    let mut deleted = 0;
+    // Failed deletions stay queued, so each run looks at every deletion once
+    let deletions = db::get_file_deletions(client, MAX_DELETION_ATTEMPTS, BATCH_SIZE).await?;
+    for deletion in deletions {
+        match delete_files(storage, &deletion).await {
+            Ok(()) => {
+                db::finish_file_deletion(client, &deletion.id).await?;
+                deleted += 1;
+            }
+            Err(e) => {
+                error!("Could not delete files of album {} ({:?}): {}", deletion.album_id, deletion.file_path, e);
+                db::fail_file_deletion(client, &deletion.id, &e.to_string()).await?;
+            }
+        }
+    }
+    Ok(deleted)
+}
+
+// Run the file deletion job in the background, e.g. right after a deletion
+pub fn spawn_file_deletions(pool: web::Data<Pool>, storage: web::Data<Box<dyn Storage>>) {
+    actix_rt::spawn(async move {
+        let client = match pool.get().await {
+            Ok(item) => item,
+            Err(e) => {
+                error!("Error occured: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = process_file_deletions(&client, storage.get_ref().as_ref()).await {
+            error!("File deletion job failed: {}", e);
+        }
+    });
+}
+
+// Permanently delete albums and photos that have been in the trash for longer
+// than the retention period, their files are queued in file_deletions
+pub async fn purge_trash(
+    client: &mut deadpool_postgres::Client,
+    retention_days: i32,
+) -> Result<usize, DBError> {
+    let (album_ids, photo_ids) = db::get_expired_trash(client, retention_days, BATCH_SIZE).await?;
+    for album_id in &album_ids {
+        match db::delete_album(client, *album_id).await {
+            Ok(_) | Err(DBError::NotFound { .. }) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    // Photos of purged albums are already gone
+    for photo_id in &photo_ids {
+        match db::delete_image_meta(client, photo_id).await {
+            Ok(()) | Err(DBError::NotFound { .. }) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(album_ids.len() + photo_ids.len())
+}
+
+// Delete expired sessions and share links, purge the trash and run the file deletion job
+// every CLEANUP_INTERVAL while the server runs
+pub async fn run_cleanup(pool: Pool, storage: Box<dyn Storage>, trash: Trash) {
+    let mut interval = actix_rt::time::interval(CLEANUP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let mut client = match pool.get().await {
+            Ok(item) => item,
+            Err(e) => {
+                error!("Error occured: {}", e);
+                continue;
+            }
+        };
+        match db::delete_expired_sessions(&client).await {
+            Ok(0) => {}
+            Ok(deleted) => info!("Deleted {} expired sessions", deleted),
+            Err(e) => error!("Deleting expired sessions failed: {}", e),
+        }
+        match db::delete_expired_share_links(&client).await {
+            Ok(0) => {}
+            Ok(deleted) => info!("Deleted {} expired share links", deleted),
+            Err(e) => error!("Deleting expired share links failed: {}", e),
+        }
+        match purge_trash(&mut client, trash.retention_days).await {
+            Ok(0) => {}
+            Ok(purged) => info!("Purged {} albums and photos from the trash", purged),
+            Err(e) => error!("Purging the trash failed: {}", e),
+        }
+        match process_file_deletions(&client, storage.as_ref()).await {
+            Ok(0) => {}
+            Ok(deleted) => info!("Removed files of {} deleted photos and albums", deleted),
+            Err(e) => error!("File deletion job failed: {}", e),
+        }
+    }
+}
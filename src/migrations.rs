@@ -0,0 +1,291 @@
+use crate::errors::DBError;
+
+use log::{error, info};
+use sha2::{Digest, Sha256};
+
+/*
+ * Versioned schema migrations.
+ *
+ * Every migration lives in migrations/<version>_<name>.{up,down}.sql and is
+ * compiled into the binary. Applied versions are recorded in the
+ * schema_migrations table together with a checksum of the up script. New
+ * migrations are appended to MIGRATIONS with the next free version number;
+ * already released migrations must never be edited, the backend refuses to
+ * start if an applied migration changed.
+ */
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+macro_rules! migration {
+    ($version:expr, $name:expr) => {
+        Migration {
+            version: $version,
+            name: $name,
+            up: include_str!(concat!("../migrations/", $name, ".up.sql")),
+            down: include_str!(concat!("../migrations/", $name, ".down.sql")),
+        }
+    };
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_initial"),
+    migration!(2, "0002_structured_coordinates"),
+    migration!(3, "0003_annotations"),
+    migration!(4, "0004_lock_holder"),
+    migration!(5, "0005_original_filename"),
+    migration!(6, "0006_image_properties"),
+    migration!(7, "0007_duplicates"),
+    migration!(8, "0008_photo_metadata"),
+    migration!(9, "0009_unique_file_path"),
+    migration!(10, "0010_file_deletions"),
+    migration!(11, "0011_trash"),
+    migration!(12, "0012_album_counters"),
+    migration!(13, "0013_sessions"),
+    migration!(14, "0014_api_tokens"),
+    migration!(15, "0015_user_roles"),
+    migration!(16, "0016_album_members"),
+    migration!(17, "0017_album_visibility"),
+];
+
+// Arbitrary but fixed key for pg_advisory_lock, so that several backend
+// instances starting at the same time never apply migrations concurrently
+const MIGRATION_LOCK_KEY: i64 = 0x7461_6769_6679;
+
+async fn ensure_migrations_table(client: &deadpool_postgres::Client) -> Result<(), DBError> {
+    client
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version BIGINT PRIMARY KEY,
+                name TEXT NOT NULL,
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            );
+            ALTER TABLE schema_migrations ADD COLUMN IF NOT EXISTS checksum TEXT",
+        )
+        .await?;
+    Ok(())
+}
+
+// SHA-256 of the up script, recorded when the migration is applied
+pub fn checksum(migration: &Migration) -> String {
+    hex::encode(Sha256::digest(migration.up.as_bytes()))
+}
+
+// Versions of applied migrations whose up script differs from the recorded
+// checksum. Migrations applied before checksums were recorded have none.
+fn edited_migrations(applied: &[(i64, Option<String>)]) -> Vec<i64> {
+    applied
+        .iter()
+        .filter_map(|(version, recorded)| {
+            let migration = MIGRATIONS.iter().find(|m| m.version == *version)?;
+            match recorded {
+                Some(recorded) if *recorded != checksum(migration) => Some(*version),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+async fn verify_checksums(client: &deadpool_postgres::Client) -> Result<(), DBError> {
+    let applied: Vec<(i64, Option<String>)> = client
+        .query("SELECT version, checksum FROM schema_migrations ORDER BY version", &[])
+        .await?
+        .iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect();
+
+    let edited = edited_migrations(&applied);
+    if !edited.is_empty() {
+        return Err(DBError::BadArgs {
+            err: format!("Migrations {:?} were edited after they had been applied", edited),
+        });
+    }
+
+    for (version, _) in applied.iter().filter(|(_, recorded)| recorded.is_none()) {
+        if let Some(migration) = MIGRATIONS.iter().find(|m| m.version == *version) {
+            client
+                .execute(
+                    "UPDATE schema_migrations SET checksum = $1 WHERE version = $2",
+                    &[&checksum(migration), version],
+                )
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn applied_versions(client: &deadpool_postgres::Client) -> Result<Vec<i64>, DBError> {
+    // The bookkeeping table does not exist before the very first run
+    let exists = client
+        .query_one("SELECT to_regclass('schema_migrations') IS NOT NULL", &[])
+        .await?;
+    if !exists.get::<_, bool>(0) {
+        return Ok(Vec::new());
+    }
+
+    let versions = client
+        .query("SELECT version FROM schema_migrations ORDER BY version", &[])
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+    Ok(versions)
+}
+
+// Migrations that are known to the binary but not yet applied, in order
+pub async fn pending(
+    client: &deadpool_postgres::Client,
+) -> Result<Vec<&'static Migration>, DBError> {
+    let applied = applied_versions(client).await?;
+    Ok(not_applied(&applied))
+}
+
+fn not_applied(applied: &[i64]) -> Vec<&'static Migration> {
+    MIGRATIONS
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+        .collect()
+}
+
+async fn lock(client: &deadpool_postgres::Client) -> Result<(), DBError> {
+    client
+        .execute("SELECT pg_advisory_lock($1)", &[&MIGRATION_LOCK_KEY])
+        .await?;
+    Ok(())
+}
+
+async fn unlock(client: &deadpool_postgres::Client) {
+    if let Err(e) = client
+        .execute("SELECT pg_advisory_unlock($1)", &[&MIGRATION_LOCK_KEY])
+        .await
+    {
+        error!("Failed to release migration lock: {}", e);
+    }
+}
+
+async fn apply_pending(client: &mut deadpool_postgres::Client) -> Result<Vec<i64>, DBError> {
+    ensure_migrations_table(client).await?;
+    verify_checksums(client).await?;
+
+    let mut applied = Vec::new();
+    for migration in pending(client).await? {
+        info!("Applying migration {}", migration.name);
+        let transaction = client.transaction().await?;
+        transaction.batch_execute(migration.up).await?;
+        transaction
+            .execute(
+                "INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)",
+                &[&migration.version, &migration.name, &checksum(migration)],
+            )
+            .await?;
+        transaction.commit().await?;
+        applied.push(migration.version);
+    }
+    Ok(applied)
+}
+
+async fn revert(client: &mut deadpool_postgres::Client) -> Result<Option<i64>, DBError> {
+    let version = match applied_versions(client).await?.last() {
+        Some(version) => *version,
+        None => return Ok(None),
+    };
+    let migration = match MIGRATIONS.iter().find(|m| m.version == version) {
+        Some(m) => m,
+        None => {
+            return Err(DBError::BadArgs {
+                err: format!("Migration {} is applied but unknown to this binary", version),
+            })
+        }
+    };
+
+    info!("Reverting migration {}", migration.name);
+    let transaction = client.transaction().await?;
+    transaction.batch_execute(migration.down).await?;
+    transaction
+        .execute(
+            "DELETE FROM schema_migrations WHERE version = $1",
+            &[&migration.version],
+        )
+        .await?;
+    transaction.commit().await?;
+    Ok(Some(version))
+}
+
+// Apply all pending migrations while holding the migration lock
+pub async fn run_pending(client: &mut deadpool_postgres::Client) -> Result<Vec<i64>, DBError> {
+    lock(client).await?;
+    let result = apply_pending(client).await;
+    unlock(client).await;
+    result
+}
+
+// Revert the most recently applied migration while holding the migration lock
+pub async fn revert_last(client: &mut deadpool_postgres::Client) -> Result<Option<i64>, DBError> {
+    lock(client).await?;
+    let result = revert(client).await;
+    unlock(client).await;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_consecutive_and_match_names() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as i64 + 1, "{}", migration.name);
+            assert!(
+                migration.name.starts_with(&format!("{:04}_", migration.version)),
+                "{} does not start with its version",
+                migration.name
+            );
+        }
+    }
+
+    #[test]
+    fn every_migration_can_be_reverted() {
+        for migration in MIGRATIONS {
+            assert!(!migration.up.trim().is_empty(), "{} has no up script", migration.name);
+            assert!(!migration.down.trim().is_empty(), "{} has no down script", migration.name);
+        }
+    }
+
+    #[test]
+    fn pending_migrations_keep_their_order() {
+        let pending: Vec<i64> = not_applied(&[1, 2, 4]).iter().map(|m| m.version).collect();
+        let mut expected = vec![3];
+        expected.extend(5..=MIGRATIONS.len() as i64);
+        assert_eq!(pending, expected);
+
+        let all: Vec<i64> = MIGRATIONS.iter().map(|m| m.version).collect();
+        assert!(not_applied(&all).is_empty());
+        assert_eq!(not_applied(&[]).len(), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn checksum_covers_the_up_script() {
+        let first = &MIGRATIONS[0];
+        assert_eq!(checksum(first), checksum(first));
+        assert_eq!(checksum(first).len(), 64);
+        let edited = Migration { up: "SELECT 1;", ..migration!(1, "0001_initial") };
+        assert_ne!(checksum(&edited), checksum(first));
+        // The down script is not part of the checksum
+        let down_edited = Migration { down: "SELECT 1;", ..migration!(1, "0001_initial") };
+        assert_eq!(checksum(&down_edited), checksum(first));
+    }
+
+    #[test]
+    fn finds_edited_migrations() {
+        let recorded = |version: usize| Some(checksum(&MIGRATIONS[version - 1]));
+        assert!(edited_migrations(&[(1, recorded(1)), (2, recorded(2))]).is_empty());
+        // Applied before checksums were recorded
+        assert!(edited_migrations(&[(1, None), (2, recorded(2))]).is_empty());
+        assert_eq!(edited_migrations(&[(1, recorded(1)), (2, recorded(1)), (3, Some("edited".to_string()))]), vec![2, 3]);
+        // Unknown versions are reported by revert_last, not here
+        assert!(edited_migrations(&[(10_000, Some("unknown".to_string()))]).is_empty());
+    }
+}
@@ -11,7 +11,6 @@ use std::rc::Rc;
 use std::time::SystemTime;
 use time::Duration;
 
-use crate::user_models::User;
 
 struct MyCookieIdentityInner {
     key: Key,
@@ -65,7 +64,6 @@ impl MyCookieIdentityInner {
         &self,
         resp: &mut ServiceResponse<B>,
         value: Option<CookieValue>,
-        cookie_name: &str,
     ) -> Result<()> {
         let add_cookie = value.is_some();
         let val = value.map(|val| {
@@ -76,7 +74,7 @@ impl MyCookieIdentityInner {
             }
         });
         let mut cookie = Cookie::new(
-            cookie_name.to_owned(),
+            self.name.clone(),
             val.unwrap_or_else(|| Ok(String::new()))?,
         );
         cookie.set_path(self.path.clone());
@@ -255,9 +253,8 @@ impl IdentityPolicy for MyCookieIdentityPolicy {
 
     fn to_response<B>(
         &self,
-        id: Option<User>,
+        id: Option<String>,
         changed: bool,
-        cookie_name: &str,
         res: &mut ServiceResponse<B>,
     ) -> Self::ResponseFuture {
         let _ = if changed {
@@ -265,11 +262,10 @@ impl IdentityPolicy for MyCookieIdentityPolicy {
             self.0.set_cookie(
                 res,
                 id.map(|identity| CookieValue {
-                    identity: identity.username,
+                    identity,
                     login_timestamp: self.0.login_deadline.map(|_| login_timestamp),
                     visit_timestamp: self.0.visit_deadline.map(|_| login_timestamp),
                 }),
-                cookie_name,
             )
         } else if self.0.always_update_cookie() && id.is_some() {
             let visit_timestamp = SystemTime::now();
@@ -284,11 +280,10 @@ impl IdentityPolicy for MyCookieIdentityPolicy {
             self.0.set_cookie(
                 res,
                 Some(CookieValue {
-                    identity: id.unwrap().username,
+                    identity: id.unwrap(),
                     login_timestamp,
                     visit_timestamp: self.0.visit_deadline.map(|_| visit_timestamp),
                 }),
-                cookie_name,
             )
         } else {
             Ok(())
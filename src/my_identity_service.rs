@@ -2,6 +2,7 @@
 
 use std::cell::RefCell;
 use std::future::Future;
+use std::net::SocketAddr;
 use std::rc::Rc;
 use std::task::{Context, Poll};
 
@@ -11,35 +12,142 @@ use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
 use actix_http::{Response, ResponseBuilder};
 use actix_web::dev::{Extensions, Payload, ServiceRequest, ServiceResponse};
 use actix_web::error::{Error, Result};
-use actix_web::http::StatusCode;
+use actix_web::http::{header, Method, StatusCode};
 use actix_web::{FromRequest, HttpMessage, HttpRequest, HttpResponse};
 use log::{debug, error};
 
 use deadpool_postgres::Pool;
+use rand::Rng;
+use sha2::{Digest, Sha256};
 
-use crate::db::get_user_by_name;
-use crate::errors::HandlerError;
+use crate::db;
+use crate::errors::{DBError, HandlerError};
 use crate::my_cookie_policy::MyCookieIdentityPolicy;
-use crate::user_models::User;
+use crate::user_models::{TokenScope, User};
+
+/*
+ * The cookie of a logged in user holds a random session token. Sessions are
+ * stored in the sessions table by the hash of the token, a request is only
+ * accepted while its session row exists and has not expired.
+ *
+ * Scripts authenticate with a personal access token instead, sent as
+ * "Authorization: Bearer <token>". Tokens are stored the same way and only
+ * reach the routes of their scopes, see required_scope.
+ *
+ * There is one cookie for all roles, a scope that needs more than a login is
+ * wrapped with IdentityService::require. Scopes wrapped with
+ * IdentityService::optional also take anonymous requests, see Identity::user.
+ */
+pub const SESSION_LIFETIME_DAYS: i32 = 30;
+const API_TOKEN_PREFIX: &str = "tagify_";
 
 #[derive(Clone)]
 pub struct Identity(HttpRequest);
 
+pub fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn new_token() -> String {
+    hex::encode(rand::thread_rng().gen::<[u8; 32]>())
+}
+
+// Returns the token to show to the user once and the hash to store
+pub fn new_api_token() -> (String, String) {
+    let token = format!("{}{}", API_TOKEN_PREFIX, new_token());
+    let hash = token_hash(&token);
+    (token, hash)
+}
+
+// Same for album share links
+pub fn new_share_token() -> (String, String) {
+    let token = new_token();
+    let hash = token_hash(&token);
+    (token, hash)
+}
+
+fn bearer_token(req: &ServiceRequest) -> Option<String> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let token = value.strip_prefix("Bearer ")?.trim();
+    Some(token.to_string())
+}
+
+// Permission a scope requires with IdentityService::require
+fn is_permitted(permission: Option<fn(&User) -> bool>, user: &User) -> bool {
+    match permission {
+        Some(permitted) => permitted(user),
+        None => true,
+    }
+}
+
+// Scope a token needs for the request, None for routes only reachable with a
+// login session (account, sessions, tokens, album members and share links)
+fn required_scope(method: &Method, path: &str) -> Option<TokenScope> {
+    // Checked first, an admin token must not manage sessions or tokens either
+    if path
+        .split('/')
+        .any(|part| part == "sessions" || part == "tokens" || part == "members" || part == "share-links")
+    {
+        return None;
+    }
+    if path.starts_with("/api/admin/") {
+        return Some(TokenScope::Admin);
+    }
+    if path.starts_with("/api/user/tag/") {
+        return Some(TokenScope::Tag);
+    }
+    if path == "/api/user/assignments" || path.starts_with("/api/albums") {
+        return Some(TokenScope::ReadAlbums);
+    }
+    if path == "/api/user/albums" || path.starts_with("/api/user/albums/") {
+        return Some(if method == Method::GET {
+            TokenScope::ReadAlbums
+        } else if path.ends_with("/import") {
+            TokenScope::Tag
+        } else {
+            TokenScope::Upload
+        });
+    }
+    None
+}
+
+// Create a session for the user, returns the token for the cookie
+pub async fn start_session(
+    client: &deadpool_postgres::Client,
+    req: &HttpRequest,
+    user: &User,
+) -> Result<String, DBError> {
+    let token = new_token();
+    let user_agent = req
+        .headers()
+        .get(actix_web::http::header::USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    // The peer address has a port, forwarded addresses usually not
+    let ip_address = req.connection_info().remote().map(|remote| match remote.parse::<SocketAddr>() {
+        Ok(address) => address.ip().to_string(),
+        Err(_) => remote.to_string(),
+    });
+    db::create_session(
+        client,
+        user.id,
+        &token_hash(&token),
+        user_agent,
+        ip_address.as_deref(),
+        SESSION_LIFETIME_DAYS,
+    )
+    .await?;
+    Ok(token)
+}
+
 pub async fn login_user(
     req: HttpRequest,
     cookie_factory: &MyCookieIdentityPolicy,
-    user: User,
+    token: String,
 ) -> Response {
     let mut resp = ServiceResponse::new(req, HttpResponse::new(StatusCode::OK));
-    if let Some(id) = resp.request().extensions_mut().get_mut::<IdentityItem>() {
-        id.user = Some(user.clone());
-        id.changed = true;
-    }
-
-    let cookie_name = user.role.clone();
 
     match cookie_factory
-        .to_response(Some(user), true, &cookie_name, &mut resp)
+        .to_response(Some(token), true, &mut resp)
         .await
     {
         Ok(_) => (),
@@ -62,12 +170,30 @@ impl Identity {
         Identity::get_identity(&self.0.extensions())
     }
 
+    /// The user of the request, None for anonymous requests to optional scopes
+    pub fn user(&self) -> Option<User> {
+        self.0
+            .extensions()
+            .get::<IdentityItem>()
+            .and_then(|id| id.user.clone())
+    }
+
+    /// Id of the session the request belongs to, None for api tokens
+    pub fn session_id(&self) -> Option<i32> {
+        if let Some(id) = self.0.extensions().get::<IdentityItem>() {
+            id.session_id
+        } else {
+            panic!("user is None, this should not happen");
+        }
+    }
+
     /// This method is used to 'forget' the current identity on subsequent
-    /// requests.
+    /// requests. The session itself has to be deleted by the caller.
     pub fn logout(&self) {
         if let Some(id) = self.0.extensions_mut().get_mut::<IdentityItem>() {
             id.changed = true;
             id.user = None;
+            id.token = None;
         }
     }
 
@@ -83,6 +209,8 @@ impl Identity {
 #[derive(Debug, Clone)]
 struct IdentityItem {
     user: Option<User>,
+    session_id: Option<i32>,
+    token: Option<String>,
     changed: bool,
 }
 
@@ -128,9 +256,8 @@ pub trait IdentityPolicy: Sized + 'static {
     /// Write changes to response
     fn to_response<B>(
         &self,
-        user: Option<User>,
+        token: Option<String>,
         changed: bool,
-        cookie_name: &str,
         response: &mut ServiceResponse<B>,
     ) -> Self::ResponseFuture;
 }
@@ -138,6 +265,8 @@ pub trait IdentityPolicy: Sized + 'static {
 pub struct IdentityService<T> {
     backend: Rc<T>,
     pool: Pool,
+    permission: Option<fn(&User) -> bool>,
+    optional: bool,
 }
 
 impl<T> IdentityService<T> {
@@ -146,8 +275,24 @@ impl<T> IdentityService<T> {
         IdentityService {
             backend: Rc::new(backend),
             pool: s_pool,
+            permission: None,
+            optional: false,
         }
     }
+
+    /// Let requests without a valid login through, handlers get the user
+    /// with `Identity::user`
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+
+    /// Only let users through that have the permission, e.g.
+    /// `permissions::can_use_admin_api`
+    pub fn require(mut self, permission: fn(&User) -> bool) -> Self {
+        self.permission = Some(permission);
+        self
+    }
 }
 
 impl<S, T, B> Transform<S> for IdentityService<T>
@@ -169,6 +314,8 @@ where
             backend: self.backend.clone(),
             service: Rc::new(RefCell::new(service)),
             pool: self.pool.clone(),
+            permission: self.permission,
+            optional: self.optional,
         })
     }
 }
@@ -178,6 +325,8 @@ pub struct IdentityServiceMiddleware<S, T> {
     backend: Rc<T>,
     service: Rc<RefCell<S>>,
     pool: Pool,
+    permission: Option<fn(&User) -> bool>,
+    optional: bool,
 }
 
 impl<S, T> Clone for IdentityServiceMiddleware<S, T> {
@@ -186,6 +335,8 @@ impl<S, T> Clone for IdentityServiceMiddleware<S, T> {
             backend: self.backend.clone(),
             service: self.service.clone(),
             pool: self.pool.clone(),
+            permission: self.permission,
+            optional: self.optional,
         }
     }
 }
@@ -211,6 +362,8 @@ where
         let backend = self.backend.clone();
         let fut = self.backend.from_request(&mut req);
         let pool = self.pool.clone();
+        let permission = self.permission;
+        let optional = self.optional;
 
         async move {
             let client = match pool.get().await {
@@ -221,30 +374,95 @@ where
                 }
             };
 
+            if let Some(token) = bearer_token(&req) {
+                let scope = match required_scope(req.method(), req.path()) {
+                    Some(item) => item,
+                    None => {
+                        return Ok(req.error_response(HandlerError::PermissionDenied {
+                            err_message: "Api tokens can not be used for this request".to_string(),
+                        }));
+                    }
+                };
+                let user = match db::get_api_token_user(&client, &token_hash(&token), scope).await {
+                    Ok(Some((user, true))) => user,
+                    Ok(Some((_, false))) => {
+                        return Ok(req.error_response(HandlerError::PermissionDenied {
+                            err_message: format!("Token lacks the {} scope", scope.name()),
+                        }));
+                    }
+                    Ok(None) => {
+                        debug!("Api token was revoked or expired");
+                        return Ok(req.error_response(HandlerError::AuthFail));
+                    }
+                    Err(e) => {
+                        error!("get_api_token_user failed {}", e);
+                        return Ok(req.error_response(HandlerError::InternalError));
+                    }
+                };
+                drop(client);
+                // The role could have changed since the token was created
+                if !is_permitted(permission, &user) {
+                    return Ok(req.error_response(HandlerError::PermissionDenied {
+                        err_message: "Your role does not permit this request".to_string(),
+                    }));
+                }
+
+                req.extensions_mut().insert(IdentityItem {
+                    user: Some(user),
+                    session_id: None,
+                    token: None,
+                    changed: false,
+                });
+                let fut = { srv.borrow_mut().call(req) };
+                return fut.await;
+            }
+
             match fut.await {
                 Ok(maybe_id) => {
                     let id = match maybe_id {
                         Some(id) => id,
+                        None if optional => {
+                            drop(client);
+                            let fut = { srv.borrow_mut().call(req) };
+                            return fut.await;
+                        }
                         None => {
                             error!("Could not extract id from request");
                             return Ok(req.error_response(HandlerError::AuthFail));
                         }
                     };
 
-                    debug!("Username in cookie is {}", id);
-                    let user: User = match get_user_by_name(client, &id).await {
-                        Ok(user) => user,
-                        Err(e) => {
-                            error!("get_user failed {}", e);
+                    let (session_id, user) = match db::get_session_user(&client, &token_hash(&id)).await {
+                        Ok(Some(item)) => item,
+                        Ok(None) if optional => {
+                            debug!("Session of cookie was logged out or expired, continue anonymous");
+                            drop(client);
+                            let fut = { srv.borrow_mut().call(req) };
+                            return fut.await;
+                        }
+                        Ok(None) => {
+                            debug!("Session of cookie was logged out or expired");
                             return Ok(req.error_response(HandlerError::AuthFail));
                         }
+                        Err(e) => {
+                            error!("get_session_user failed {}", e);
+                            return Ok(req.error_response(HandlerError::InternalError));
+                        }
                     };
+                    // Return the connection before the handler takes its own
+                    drop(client);
 
                     debug!("Extracted user is: {:?}", user);
-                    let cookie_name = user.role.clone();
+                    if !is_permitted(permission, &user) {
+                        return Ok(req.error_response(HandlerError::PermissionDenied {
+                            err_message: "Your role does not permit this request".to_string(),
+                        }));
+                    }
 
                     req.extensions_mut().insert(IdentityItem {
                         user: Some(user),
+                        session_id: Some(session_id),
+                        token: Some(id),
                         changed: false,
                     });
 
@@ -261,7 +479,7 @@ where
 
                     if let Some(id) = id {
                         match backend
-                            .to_response(id.user, id.changed, &cookie_name, &mut res)
+                            .to_response(id.token, id.changed, &mut res)
                             .await
                         {
                             Ok(_) => Ok(res),
@@ -283,3 +501,60 @@ where
         .boxed_local()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_routes_never_accept_tokens() {
+        for path in &[
+            "/api/user/me",
+            "/api/user/me/password",
+            "/api/user/sessions",
+            "/api/user/sessions/3",
+            "/api/user/me/tokens",
+            "/api/user/me/tokens/3",
+            "/api/user/albums/1/members",
+            "/api/user/albums/1/members/2",
+            "/api/user/albums/1/share-links",
+            "/api/admin/sessions",
+            "/api/admin/sessions/3",
+            "/api/admin/me/tokens",
+            "/api/admin/me/tokens/3",
+            "/api/admin/albums/1/members",
+            "/api/admin/albums/1/share-links/2",
+        ] {
+            assert_eq!(required_scope(&Method::GET, path), None, "{}", path);
+            assert_eq!(required_scope(&Method::POST, path), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn admin_routes_need_admin_scope() {
+        for path in &["/api/admin/users", "/api/admin/trash", "/api/admin/albums/1/photos/2"] {
+            assert_eq!(required_scope(&Method::GET, path), Some(TokenScope::Admin), "{}", path);
+        }
+    }
+
+    #[test]
+    fn album_routes_need_scope_by_method() {
+        assert_eq!(required_scope(&Method::GET, "/api/albums"), Some(TokenScope::ReadAlbums));
+        assert_eq!(required_scope(&Method::GET, "/api/albums/1/photos/0"), Some(TokenScope::ReadAlbums));
+        assert_eq!(required_scope(&Method::GET, "/api/user/assignments"), Some(TokenScope::ReadAlbums));
+        assert_eq!(required_scope(&Method::GET, "/api/user/albums"), Some(TokenScope::ReadAlbums));
+        assert_eq!(required_scope(&Method::GET, "/api/user/albums/1/export"), Some(TokenScope::ReadAlbums));
+        assert_eq!(required_scope(&Method::POST, "/api/user/albums"), Some(TokenScope::Upload));
+        assert_eq!(required_scope(&Method::POST, "/api/user/albums/1/photos"), Some(TokenScope::Upload));
+        assert_eq!(required_scope(&Method::DELETE, "/api/user/albums/1/photos/2"), Some(TokenScope::Upload));
+        assert_eq!(required_scope(&Method::POST, "/api/user/albums/1/import"), Some(TokenScope::Tag));
+        assert_eq!(required_scope(&Method::PUT, "/api/user/tag/action/2"), Some(TokenScope::Tag));
+    }
+
+    #[test]
+    fn unknown_routes_need_session() {
+        assert_eq!(required_scope(&Method::POST, "/api/login"), None);
+        assert_eq!(required_scope(&Method::POST, "/api/user/logout"), None);
+        assert_eq!(required_scope(&Method::GET, "/api/user/trash"), None);
+    }
+}
@@ -0,0 +1,178 @@
+/*
+ * What the roles may do. Handlers ask here instead of comparing roles.
+ *
+ * admin     everything, including the admin api and albums of other users
+ * reviewer  like user, and tags and verifies photos of all albums
+ * user      creates albums, works on own albums and albums invited to
+ * viewer    only looks at albums and photos, even when invited
+ *
+ * Owners invite users to an album as tagger, reviewer (tags and verifies)
+ * or co_owner (manages the album, but can not delete it).
+ *
+ * Public albums are seen by anyone, internal albums by every logged in user
+ * and private albums only by users that may work on them.
+ */
+use crate::album_models::{Album, AlbumRole, Visibility};
+use crate::user_models::{Role, User};
+
+pub fn can_use_admin_api(user: &User) -> bool {
+    user.role == Role::Admin
+}
+
+// See private albums of other users
+pub fn can_view_all_albums(user: &User) -> bool {
+    user.role == Role::Admin || user.role == Role::Reviewer
+}
+
+// user is None for anonymous requests
+pub fn can_view_album(user: Option<&User>, album: &Album, member: Option<AlbumRole>) -> bool {
+    match (album.visibility, user) {
+        (Visibility::Public, _) => true,
+        (_, None) => false,
+        (Visibility::Internal, Some(_)) => true,
+        (Visibility::Private, Some(user)) => {
+            can_view_all_albums(user) || user.id == album.users_id || member.is_some()
+        }
+    }
+}
+
+pub fn can_create_album(user: &User) -> bool {
+    user.role != Role::Viewer
+}
+
+fn is_owner(user: &User, album: &Album) -> bool {
+    user.role != Role::Viewer && user.id == album.users_id
+}
+
+// Trash, restore and delete the album
+pub fn can_delete_album(user: &User, album: &Album) -> bool {
+    user.role == Role::Admin || is_owner(user, album)
+}
+
+// Change, upload to, export and manage members of the album
+pub fn can_edit_album(user: &User, album: &Album, member: Option<AlbumRole>) -> bool {
+    can_delete_album(user, album) || (user.role != Role::Viewer && member == Some(AlbumRole::CoOwner))
+}
+
+pub fn can_tag(user: &User, album: &Album, member: Option<AlbumRole>) -> bool {
+    match user.role {
+        Role::Admin | Role::Reviewer => true,
+        Role::User => user.id == album.users_id || member.is_some(),
+        Role::Viewer => false,
+    }
+}
+
+pub fn can_verify(user: &User, album: &Album, member: Option<AlbumRole>) -> bool {
+    match user.role {
+        Role::Admin | Role::Reviewer => true,
+        Role::User => {
+            user.id == album.users_id || member == Some(AlbumRole::Reviewer) || member == Some(AlbumRole::CoOwner)
+        }
+        Role::Viewer => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: i32 = 1;
+    const OTHER: i32 = 2;
+
+    fn user(id: i32, role: Role) -> User {
+        User {
+            id,
+            username: format!("user{}", id),
+            nickname: format!("user{}", id),
+            password: String::new(),
+            role,
+        }
+    }
+
+    fn album(visibility: Visibility) -> Album {
+        Album {
+            id: 1,
+            title: "album".to_string(),
+            description: String::new(),
+            tags: Vec::new(),
+            image_number: 0,
+            tagged_number: 0,
+            verified_number: 0,
+            users_id: OWNER,
+            first_photo: String::new(),
+            strip_metadata: true,
+            visibility,
+        }
+    }
+
+    #[test]
+    fn anonymous_requests_only_see_public_albums() {
+        assert!(can_view_album(None, &album(Visibility::Public), None));
+        assert!(!can_view_album(None, &album(Visibility::Internal), None));
+        assert!(!can_view_album(None, &album(Visibility::Private), None));
+    }
+
+    #[test]
+    fn private_albums_are_seen_by_those_working_on_them() {
+        let private = album(Visibility::Private);
+        assert!(can_view_album(Some(&user(OWNER, Role::User)), &private, None));
+        assert!(can_view_album(Some(&user(OTHER, Role::Admin)), &private, None));
+        assert!(can_view_album(Some(&user(OTHER, Role::Reviewer)), &private, None));
+        assert!(can_view_album(Some(&user(OTHER, Role::Viewer)), &private, Some(AlbumRole::Tagger)));
+        assert!(!can_view_album(Some(&user(OTHER, Role::User)), &private, None));
+        assert!(!can_view_album(Some(&user(OTHER, Role::Viewer)), &private, None));
+
+        for role in &[Role::Admin, Role::Reviewer, Role::User, Role::Viewer] {
+            assert!(can_view_album(Some(&user(OTHER, *role)), &album(Visibility::Internal), None));
+        }
+    }
+
+    #[test]
+    fn only_owners_and_admins_delete_albums() {
+        let album = album(Visibility::Private);
+        assert!(can_delete_album(&user(OWNER, Role::User), &album));
+        assert!(can_delete_album(&user(OTHER, Role::Admin), &album));
+        assert!(!can_delete_album(&user(OTHER, Role::Reviewer), &album));
+        // Owners that became viewers lost their albums
+        assert!(!can_delete_album(&user(OWNER, Role::Viewer), &album));
+
+        let co_owner = user(OTHER, Role::User);
+        assert!(!can_delete_album(&co_owner, &album));
+        assert!(can_edit_album(&co_owner, &album, Some(AlbumRole::CoOwner)));
+        assert!(!can_edit_album(&co_owner, &album, Some(AlbumRole::Reviewer)));
+        assert!(!can_edit_album(&user(OTHER, Role::Viewer), &album, Some(AlbumRole::CoOwner)));
+        assert!(!can_edit_album(&user(OTHER, Role::Reviewer), &album, None));
+    }
+
+    #[test]
+    fn members_tag_and_reviewers_verify() {
+        let album = album(Visibility::Private);
+        let member = user(OTHER, Role::User);
+        let cases = vec![
+            (None, false, false),
+            (Some(AlbumRole::Tagger), true, false),
+            (Some(AlbumRole::Reviewer), true, true),
+            (Some(AlbumRole::CoOwner), true, true),
+        ];
+        for (role, tag, verify) in cases {
+            assert_eq!(can_tag(&member, &album, role), tag, "{:?}", role);
+            assert_eq!(can_verify(&member, &album, role), verify, "{:?}", role);
+        }
+
+        for role in &[Role::Admin, Role::Reviewer] {
+            assert!(can_tag(&user(OTHER, *role), &album, None));
+            assert!(can_verify(&user(OTHER, *role), &album, None));
+        }
+        assert!(can_verify(&user(OWNER, Role::User), &album, None));
+        assert!(!can_tag(&user(OTHER, Role::Viewer), &album, Some(AlbumRole::CoOwner)));
+        assert!(!can_verify(&user(OTHER, Role::Viewer), &album, Some(AlbumRole::Reviewer)));
+    }
+
+    #[test]
+    fn viewers_do_not_create_albums() {
+        assert!(can_create_album(&user(OTHER, Role::User)));
+        assert!(!can_create_album(&user(OTHER, Role::Viewer)));
+        assert!(can_use_admin_api(&user(OTHER, Role::Admin)));
+        assert!(!can_use_admin_api(&user(OTHER, Role::Reviewer)));
+    }
+}
@@ -0,0 +1,346 @@
+use crate::image_utils::ImageType;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use exif::{In, Tag, Value};
+use image::{DynamicImage, ImageOutputFormat};
+use img_parts::jpeg::{markers, Jpeg};
+use img_parts::png::Png;
+use img_parts::webp::WebP;
+use img_parts::{Bytes, DynImage, ImageEXIF};
+use std::io::Cursor;
+
+/*
+ * EXIF data of uploaded photos.
+ *
+ * Orientation, capture time and camera are read into the image meta. Photos
+ * are stored upright: a rotated or mirrored orientation is applied to the
+ * pixels, so annotation coordinates always refer to the displayed image.
+ * Albums with strip_metadata store photos without EXIF, XMP and text data,
+ * which may hold the GPS location, serial numbers or the name of the owner.
+ * JPEG, PNG and WebP are stripped without decoding. Formats that can not be
+ * written (WebP) or not be stripped (TIFF keeps its tags in the file
+ * structure) are stored as PNG when they have to be rewritten.
+ * All functions here are blocking.
+ */
+const ORIENTATION_TAG: u16 = 0x0112;
+const MAX_TEXT_LENGTH: usize = 100;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExifInfo {
+    pub orientation: Option<i16>,
+    pub captured_at: Option<NaiveDateTime>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+}
+
+// The file to store for an upload
+pub struct PreparedPhoto {
+    pub content: Vec<u8>,
+    pub image_type: ImageType,
+    pub exif: ExifInfo,
+}
+
+fn ascii_value(exif: &exif::Exif, tag: Tag) -> Option<&[u8]> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values.first().map(|value| value.as_slice()),
+        _ => None,
+    }
+}
+
+fn text_field(exif: &exif::Exif, tag: Tag) -> Option<String> {
+    let text = String::from_utf8_lossy(ascii_value(exif, tag)?);
+    let text: String = text.trim_matches(|c: char| c.is_whitespace() || c == '\0').chars().take(MAX_TEXT_LENGTH).collect();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+fn date_time_field(exif: &exif::Exif, tag: Tag) -> Option<NaiveDateTime> {
+    let value = exif::DateTime::from_ascii(ascii_value(exif, tag)?).ok()?;
+    NaiveDate::from_ymd_opt(i32::from(value.year), u32::from(value.month), u32::from(value.day))?
+        .and_hms_opt(u32::from(value.hour), u32::from(value.minute), u32::from(value.second))
+}
+
+// Photos without or with unreadable EXIF data have no values
+pub fn read_exif(content: &[u8]) -> ExifInfo {
+    let exif = match exif::Reader::new().read_from_container(&mut Cursor::new(content)) {
+        Ok(item) => item,
+        Err(_) => return ExifInfo::default(),
+    };
+    ExifInfo {
+        orientation: exif
+            .get_field(Tag::Orientation, In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0))
+            .filter(|orientation| (1..=8).contains(orientation))
+            .map(|orientation| orientation as i16),
+        captured_at: date_time_field(&exif, Tag::DateTimeOriginal).or_else(|| date_time_field(&exif, Tag::DateTime)),
+        camera_make: text_field(&exif, Tag::Make),
+        camera_model: text_field(&exif, Tag::Model),
+    }
+}
+
+// 1 is upright, 2-8 are the mirrored and rotated variants defined by EXIF
+fn apply_orientation(image: DynamicImage, orientation: i16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+// Size of the stored photo, orientations 5 to 8 are rotated by 90 degrees
+pub fn upright_size(width: i32, height: i32, orientation: Option<i16>) -> (i32, i32) {
+    if orientation.unwrap_or(1) > 4 {
+        (height, width)
+    } else {
+        (width, height)
+    }
+}
+
+// Encoding drops all metadata of the image
+fn encode(image: &DynamicImage, image_type: ImageType) -> Result<(Vec<u8>, ImageType), String> {
+    let (format, stored_type) = match image_type {
+        ImageType::Jpeg => (ImageOutputFormat::Jpeg(90), ImageType::Jpeg),
+        _ => (ImageOutputFormat::Png, ImageType::Png),
+    };
+    let mut content = Vec::new();
+    image
+        .write_to(&mut content, format)
+        .map_err(|e| format!("could not write {} image: {}", stored_type.extension(), e))?;
+    Ok((content, stored_type))
+}
+
+// Set the orientation in IFD0 of raw TIFF formatted EXIF data to upright
+fn reset_orientation(exif: &mut [u8]) {
+    let big_endian = match exif.get(0..2) {
+        Some(b"MM") => true,
+        Some(b"II") => false,
+        _ => return,
+    };
+    let read_u16 = |data: &[u8], at: usize| {
+        data.get(at..at + 2).map(|b| if big_endian {
+            u16::from_be_bytes([b[0], b[1]])
+        } else {
+            u16::from_le_bytes([b[0], b[1]])
+        })
+    };
+    let ifd = match exif.get(4..8) {
+        Some(b) if big_endian => u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize,
+        Some(b) => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize,
+        None => return,
+    };
+    let entries = read_u16(exif, ifd).unwrap_or(0) as usize;
+    for i in 0..entries {
+        let entry = ifd + 2 + i * 12;
+        if read_u16(exif, entry) == Some(ORIENTATION_TAG) {
+            let upright = if big_endian { 1u16.to_be_bytes() } else { 1u16.to_le_bytes() };
+            if let Some(value) = exif.get_mut(entry + 8..entry + 10) {
+                value.copy_from_slice(&upright);
+            }
+            return;
+        }
+    }
+}
+
+// Move the EXIF data of the upload into the rewritten file, marked as upright
+fn copy_exif(original: &[u8], rewritten: Vec<u8>) -> Vec<u8> {
+    let exif = DynImage::from_bytes(Bytes::copy_from_slice(original))
+        .ok()
+        .flatten()
+        .and_then(|image| image.exif());
+    let mut exif = match exif {
+        Some(item) => item.to_vec(),
+        None => return rewritten,
+    };
+    reset_orientation(&mut exif);
+    match DynImage::from_bytes(Bytes::from(rewritten.clone())) {
+        Ok(Some(mut image)) => {
+            image.set_exif(Some(Bytes::from(exif)));
+            image.encoder().bytes().to_vec()
+        }
+        _ => rewritten,
+    }
+}
+
+// Remove EXIF, XMP, IPTC and comments but keep the color profile
+fn strip(content: Vec<u8>, image_type: ImageType) -> Result<(Vec<u8>, ImageType), String> {
+    let invalid = |e: img_parts::Error| format!("corrupt {} image: {}", image_type.extension(), e);
+    let stripped = match image_type {
+        ImageType::Jpeg => {
+            let mut jpeg = Jpeg::from_bytes(Bytes::from(content)).map_err(invalid)?;
+            jpeg.segments_mut().retain(|segment| {
+                ![markers::APP1, markers::APP13, markers::COM].contains(&segment.marker())
+            });
+            jpeg.encoder().bytes()
+        }
+        ImageType::Png => {
+            let mut png = Png::from_bytes(Bytes::from(content)).map_err(invalid)?;
+            png.set_exif(None);
+            png.chunks_mut().retain(|chunk| ![*b"tEXt", *b"zTXt", *b"iTXt"].contains(&chunk.kind()));
+            png.encoder().bytes()
+        }
+        ImageType::Webp => {
+            let mut webp = WebP::from_bytes(Bytes::from(content)).map_err(invalid)?;
+            webp.set_exif(None);
+            webp.remove_chunks_by_id(*b"XMP ");
+            webp.encoder().bytes()
+        }
+        ImageType::Tiff => {
+            let image = image::load_from_memory_with_format(&content, image_type.format())
+                .map_err(|e| format!("corrupt tiff image: {}", e))?;
+            return encode(&image, image_type);
+        }
+    };
+    Ok((stripped.to_vec(), image_type))
+}
+
+// Turn an uploaded photo into the file to store, rotated upright and without
+// metadata if the album strips it
+pub fn prepare(content: Vec<u8>, image_type: ImageType, strip_metadata: bool) -> Result<PreparedPhoto, String> {
+    let exif = read_exif(&content);
+    let orientation = exif.orientation.unwrap_or(1);
+
+    let (content, image_type) = if orientation != 1 {
+        let image = image::load_from_memory_with_format(&content, image_type.format())
+            .map_err(|e| format!("corrupt {} image: {}", image_type.extension(), e))?;
+        let (rotated, stored_type) = encode(&apply_orientation(image, orientation), image_type)?;
+        if strip_metadata {
+            (rotated, stored_type)
+        } else {
+            (copy_exif(&content, rotated), stored_type)
+        }
+    } else if strip_metadata {
+        strip(content, image_type)?
+    } else {
+        (content, image_type)
+    };
+
+    Ok(PreparedPhoto {
+        content,
+        image_type,
+        exif,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_utils;
+    use image::{GenericImageView, Rgb, RgbImage};
+
+    // IFD0 with a make entry before the orientation entry
+    fn tiff_exif(big_endian: bool, orientation: u16) -> Vec<u8> {
+        let u16_bytes = |value: u16| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        let u32_bytes = |value: u32| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        let mut exif = if big_endian { b"MM".to_vec() } else { b"II".to_vec() };
+        exif.extend_from_slice(&u16_bytes(42));
+        exif.extend_from_slice(&u32_bytes(8));
+        exif.extend_from_slice(&u16_bytes(2));
+        // Make, ASCII with 4 bytes stored in the entry
+        exif.extend_from_slice(&u16_bytes(0x010F));
+        exif.extend_from_slice(&u16_bytes(2));
+        exif.extend_from_slice(&u32_bytes(4));
+        exif.extend_from_slice(b"Cam\0");
+        // Orientation, one SHORT padded to 4 bytes
+        exif.extend_from_slice(&u16_bytes(ORIENTATION_TAG));
+        exif.extend_from_slice(&u16_bytes(3));
+        exif.extend_from_slice(&u32_bytes(1));
+        exif.extend_from_slice(&u16_bytes(orientation));
+        exif.extend_from_slice(&[0, 0]);
+        exif.extend_from_slice(&u32_bytes(0));
+        exif
+    }
+
+    // 3 x 2 image, only the top left pixel is red
+    fn marked_image() -> DynamicImage {
+        let mut image = RgbImage::new(3, 2);
+        image.put_pixel(0, 0, Rgb([255, 0, 0]));
+        DynamicImage::ImageRgb8(image)
+    }
+
+    fn red_pixel(image: &DynamicImage) -> Option<(u32, u32)> {
+        image.pixels().find(|(_, _, pixel)| pixel[0] > 128).map(|(x, y, _)| (x, y))
+    }
+
+    #[test]
+    fn swaps_size_of_rotated_orientations() {
+        assert_eq!(upright_size(3, 2, None), (3, 2));
+        for orientation in 1..=4 {
+            assert_eq!(upright_size(3, 2, Some(orientation)), (3, 2));
+        }
+        for orientation in 5..=8 {
+            assert_eq!(upright_size(3, 2, Some(orientation)), (2, 3));
+        }
+    }
+
+    #[test]
+    fn applies_orientations_to_pixels() {
+        let cases = vec![
+            (1, (3, 2), (0, 0)),
+            (2, (3, 2), (2, 0)),
+            (3, (3, 2), (2, 1)),
+            (4, (3, 2), (0, 1)),
+            (5, (2, 3), (0, 0)),
+            (6, (2, 3), (1, 0)),
+            (7, (2, 3), (1, 2)),
+            (8, (2, 3), (0, 2)),
+        ];
+        for (orientation, size, red) in cases {
+            let image = apply_orientation(marked_image(), orientation);
+            assert_eq!(image.dimensions(), size, "orientation {}", orientation);
+            assert_eq!(red_pixel(&image), Some(red), "orientation {}", orientation);
+            let (width, height) = upright_size(3, 2, Some(orientation));
+            assert_eq!((width as u32, height as u32), size, "orientation {}", orientation);
+        }
+    }
+
+    #[test]
+    fn resets_orientation_in_both_byte_orders() {
+        for &big_endian in &[false, true] {
+            let mut exif = tiff_exif(big_endian, 6);
+            reset_orientation(&mut exif);
+            assert_eq!(exif, tiff_exif(big_endian, 1));
+        }
+    }
+
+    #[test]
+    fn ignores_invalid_exif() {
+        let mut exif = b"XX*\0\x08\0\0\0".to_vec();
+        reset_orientation(&mut exif);
+        assert_eq!(exif, b"XX*\0\x08\0\0\0".to_vec());
+
+        let mut truncated = tiff_exif(false, 6);
+        truncated.truncate(20);
+        reset_orientation(&mut truncated);
+        assert_eq!(truncated, tiff_exif(false, 6)[..20].to_vec());
+    }
+
+    #[test]
+    fn stores_rotated_photos_upright() {
+        let mut content = Vec::new();
+        marked_image().write_to(&mut content, ImageOutputFormat::Jpeg(90)).unwrap();
+        let mut jpeg = Jpeg::from_bytes(Bytes::from(content)).unwrap();
+        jpeg.set_exif(Some(Bytes::from(tiff_exif(false, 6))));
+        let content = jpeg.encoder().bytes().to_vec();
+        assert_eq!(read_exif(&content).orientation, Some(6));
+
+        let kept = prepare(content.clone(), ImageType::Jpeg, false).unwrap();
+        assert_eq!(kept.exif.orientation, Some(6));
+        assert_eq!(kept.exif.camera_make, Some("Cam".to_string()));
+        assert_eq!(image_utils::inspect_image(&kept.content), Ok((ImageType::Jpeg, (2, 3))));
+        assert_eq!(read_exif(&kept.content).orientation, Some(1));
+        assert_eq!(read_exif(&kept.content).camera_make, Some("Cam".to_string()));
+
+        let stripped = prepare(content, ImageType::Jpeg, true).unwrap();
+        assert_eq!(stripped.exif.orientation, Some(6));
+        assert_eq!(image_utils::inspect_image(&stripped.content), Ok((ImageType::Jpeg, (2, 3))));
+        assert_eq!(read_exif(&stripped.content), ExifInfo::default());
+    }
+}
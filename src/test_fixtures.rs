@@ -0,0 +1,37 @@
+// Rows for the tests against the database, removed again with delete_users
+use crate::db;
+use crate::user_models::{CreateImageMeta, CreateUser, Role, User};
+use crate::utils;
+
+// User with a unique username starting with name
+pub async fn create_user(client: &deadpool_postgres::Client, name: &str) -> User {
+    db::create_user(client, &CreateUser {
+        username: format!("{}_{}", name, rand::random::<u32>()),
+        password: "password".to_string(),
+        nickname: name.to_string(),
+        role: Role::User,
+    }).await.unwrap()
+}
+
+// Photo row of a 10x10 png without a file, visible once the transaction is committed
+pub async fn create_photo(transaction: &deadpool_postgres::Transaction<'_>, album_id: i32) -> (i32, String) {
+    let image_id = db::reserve_image_id(transaction).await.unwrap();
+    let file_path = utils::image_file_name(image_id, "png");
+    db::create_image_meta(transaction, &image_id, &CreateImageMeta {
+        album_id,
+        file_path: file_path.clone(),
+        width: Some(10),
+        height: Some(10),
+        ..Default::default()
+    }).await.unwrap();
+    (image_id, file_path)
+}
+
+// The users with their albums and photos
+pub async fn delete_users(client: &deadpool_postgres::Client, user_ids: &[i32]) {
+    client.execute(
+        "DELETE FROM image_metas WHERE album_id IN (SELECT id FROM albums WHERE users_id = ANY($1))",
+        &[&user_ids]).await.unwrap();
+    client.execute("DELETE FROM albums WHERE users_id = ANY($1)", &[&user_ids]).await.unwrap();
+    client.execute("DELETE FROM users WHERE id = ANY($1)", &[&user_ids]).await.unwrap();
+}
@@ -1,3 +1,5 @@
+use chrono::NaiveDateTime;
+use postgres_types::{FromSql, ToSql};
 use serde::{Deserialize, Serialize};
 use tokio_pg_mapper_derive::PostgresMapper;
 
@@ -17,14 +19,14 @@ pub struct User {
     pub username: String,
     pub nickname: String,
     pub password: String,
-    pub role: String, // TODO: Make an Enum out of it
+    pub role: Role,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateUserAdmin {
     pub nickname: String,
     pub password: String,
-    pub role: String, // TODO: Make an Enum out of it
+    pub role: Role,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -55,7 +57,74 @@ pub struct SendUser {
     pub id: i32,
     pub username: String,
     pub nickname: String,
-    pub role: String, // TODO: Make an Enum out of it
+    pub role: Role,
+}
+
+// Login session as listed to its user, the token is never shown
+#[derive(Debug, Clone, Serialize, Deserialize, PostgresMapper)]
+#[pg_mapper(table = "sessions")]
+pub struct Session {
+    pub id: i32,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    // The session of the request
+    pub current: bool,
+}
+
+// What a personal access token may be used for
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    // List and download albums, photos and exports
+    ReadAlbums,
+    // Create albums, add, replace and delete photos
+    Upload,
+    // Get photos for tagging, tag, verify and import annotations
+    Tag,
+    // Admin api, only for tokens of admins
+    Admin,
+}
+
+impl TokenScope {
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenScope::ReadAlbums => "read_albums",
+            TokenScope::Upload => "upload",
+            TokenScope::Tag => "tag",
+            TokenScope::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PostgresMapper)]
+#[pg_mapper(table = "api_tokens")]
+pub struct ApiToken {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+// e.g. {"name": "nightly import", "scopes": ["read_albums", "upload"], "expires_in_days": 90}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateApiToken {
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    // Never expires if missing
+    pub expires_in_days: Option<i32>,
+}
+
+// The token is only shown once, on creation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatedApiToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub api_token: ApiToken,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -63,23 +132,167 @@ pub struct CreateUser {
     pub username: String,
     pub password: String,
     pub nickname: String,
-    pub role: String, // TODO: Make an Enum out of it
+    pub role: Role,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+// Variant of a photo to serve, thumb and medium are downscaled JPEG copies
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PhotoSize {
+    Original,
+    Thumb,
+    Medium,
+}
+
+impl Default for PhotoSize {
+    fn default() -> Self {
+        PhotoSize::Original
+    }
+}
+
+impl PhotoSize {
+    pub fn name(self) -> &'static str {
+        match self {
+            PhotoSize::Original => "original",
+            PhotoSize::Thumb => "thumb",
+            PhotoSize::Medium => "medium",
+        }
+    }
+
+    // Longest edge in pixels
+    pub fn max_edge(self) -> Option<u32> {
+        match self {
+            PhotoSize::Original => None,
+            PhotoSize::Thumb => Some(256),
+            PhotoSize::Medium => Some(1024),
+        }
+    }
+}
+
+// e.g. ?size=thumb
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhotoSizeQuery {
+    #[serde(default)]
+    pub size: PhotoSize,
+}
+
+// Outcome of one file of a photo upload, rejected files carry the reason in `error`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadResult {
+    pub file: String,
+    pub accepted: bool,
+    pub id: Option<i32>,
+    pub file_path: Option<String>,
+    pub error: Option<String>,
+}
+
+// Outcome of filling in the file properties of previously stored photos
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackfillReport {
+    pub updated: usize,
+    pub missing_files: Vec<String>,
+    pub not_images: Vec<String>,
+}
+
+// Queued removal of the file of a deleted photo, or of the whole album folder
+#[derive(Debug, Clone, Serialize, Deserialize, PostgresMapper)]
+#[pg_mapper(table = "file_deletions")]
+pub struct FileDeletion {
+    pub id: i32,
+    pub album_id: i32,
+    pub file_path: Option<String>,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+}
+
+// What to do with stored files that no image meta points to
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrphanFileAction {
+    // Add an image meta, only possible in folders of existing albums
+    Import,
+    // Move below quarantine/ for manual review
+    Quarantine,
+    Delete,
+}
+
+// Repairs done by the consistency check, by default it only reports
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConsistencyRepair {
+    pub orphan_files: Option<OrphanFileAction>,
+    // Delete image metas whose file is missing
+    #[serde(default)]
+    pub delete_missing: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MissingFile {
+    pub image_id: i32,
+    pub album_id: i32,
+    pub file_path: String,
+}
+
+// Differences between the stored album files and the image metas, files are
+// listed by their storage key. Repaired entries are listed as well.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConsistencyReport {
+    pub orphan_files: Vec<String>,
+    pub missing_files: Vec<MissingFile>,
+    pub imported: Vec<String>,
+    pub quarantined: Vec<String>,
+    pub deleted_files: Vec<String>,
+    pub deleted_image_metas: Vec<i32>,
+    // Entries that could not be repaired with the reason
+    pub failed: Vec<String>,
+}
+
+// Outcome of one uploaded zip or tar.gz archive, entries are listed by their name in the archive.
+// not_stored lists extracted photos that could not be stored, nothing after them is added.
+// Archives that could not be extracted at all carry the reason in `error`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveUploadReport {
+    pub archive: String,
+    pub added: Vec<String>,
+    pub not_images: Vec<String>,
+    pub duplicates: Vec<String>,
+    pub too_large: Vec<String>,
+    pub not_stored: Vec<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct CreateImageMeta {
     pub album_id: i32,
     pub file_path: String,
-    pub coordinates: String,
+    pub original_filename: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub mime_type: Option<String>,
+    pub byte_size: Option<i64>,
+    pub checksum: Option<String>,
+    pub perceptual_hash: Option<i64>,
+    pub orientation: Option<i16>,
+    pub captured_at: Option<NaiveDateTime>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
 }
 #[derive(Debug, Serialize, Deserialize, Clone, PostgresMapper)]
 #[pg_mapper(table = "image_metas")]
 pub struct ImageMeta {
     pub id: i32,
     pub album_id: i32,
-    pub tag: String,
     pub file_path: String,
-    pub coordinates: String,
+    pub original_filename: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub mime_type: Option<String>,
+    pub byte_size: Option<i64>,
+    pub checksum: Option<String>,
+    pub perceptual_hash: Option<i64>,
+    pub orientation: Option<i16>,
+    pub captured_at: Option<NaiveDateTime>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
 }
 
 // Hash password, can be implemented for Structs containing .passwort attribut
@@ -162,5 +375,17 @@ impl Hash for CreateUser {
     }
 }
 
-// TODO: Make this a Sql serializable enum
-pub const ROLES: &'static [&'static str] = &["admin", "user"];
+// Stored as the user_role enum, what each role may do is decided in permissions.rs
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSql, FromSql)]
+#[serde(rename_all = "lowercase")]
+#[postgres(name = "user_role")]
+pub enum Role {
+    #[postgres(name = "admin")]
+    Admin,
+    #[postgres(name = "reviewer")]
+    Reviewer,
+    #[postgres(name = "user")]
+    User,
+    #[postgres(name = "viewer")]
+    Viewer,
+}
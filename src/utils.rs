@@ -1,6 +1,3 @@
-use std::convert::TryFrom;
-use std::fs;
-use log::{/* error, */ info};
 
 // pub fn validate_role(role: &str) -> bool {
 //     let mut is_role = false;
@@ -12,81 +9,13 @@ use log::{/* error, */ info};
 //     is_role
 // }
 
-pub fn get_filenames_in_folder(folder_path: &str) -> Vec<String> {
-
-    let mut filenames_folder = Vec::new();
-    
-    let paths = fs::read_dir(folder_path).unwrap();
-    let mut names =
-    paths.filter_map(|entry| {
-        entry.ok().and_then(|e|
-            e.path().file_name()
-            .and_then(|n| n.to_str().map(|s| String::from(s)))
-        )
-    }).collect::<Vec<String>>();
-    
-    if names.len() != 0 {
-        names.sort();
-    
-        for name in names.iter().rev() {
-            filenames_folder.push(name.clone());
-        }
-
-    }
-    return filenames_folder;
+// Photos are stored under the id of their row, ids are never handed out twice
+pub fn image_file_name(image_id: i32, extension: &str) -> String {
+    format!("{}.{}", image_id, extension)
 }
 
-pub fn calculate_next_filename_image (
-    filenames_folder: &Vec<String>, filenames_db: &Vec<String>
-) -> String {
-    let mut max_i: u32 = 0;
-    
-    let mut fn_folder_num: Vec<u32> = Vec::new();
-    for filename in filenames_folder {
-        let vec: Vec<&str> = filename.split(".").collect();
-        let fname: &str = vec[0];
-        if fname.parse::<u32>().is_ok() {
-            let num = fname.parse().unwrap();
-            fn_folder_num.push(num);
-            if max_i < num {
-                max_i = num;
-            }
-        }
-    }
-    fn_folder_num.sort();
-
-    let mut fn_db_num: Vec<u32> = Vec::new(); 
-    for filename in filenames_db {
-        let vec: Vec<&str> = filename.split(".").collect();
-        let fname: &str = vec[0];
-        if fname.parse::<u32>().is_ok() {
-            let num = fname.parse().unwrap();
-            fn_folder_num.push(num);
-            if max_i < num {
-                max_i = num;
-            }
-        }
-    }
-    fn_db_num.sort();
-
-    let mut free_slots: Vec<u32> = vec![0; usize::try_from(max_i).unwrap() + 1];
-    for num in fn_folder_num {
-        free_slots[ usize::try_from(num).unwrap()] = 1;
-    }
-    for num in fn_db_num {
-        free_slots[ usize::try_from(num).unwrap()] = 1;
-    }
-    
-    let mut next_filename = "0".to_string();
-    info!("Searching for first available photo name, default {} ...", next_filename);
-    for i in 1..max_i {
-        if free_slots[usize::try_from(i).unwrap()] == 0 {
-            // filename can be this number
-            next_filename = format!("{}", i);
-            return next_filename;
-        }
-    }
-
-    next_filename = format!("{}", (max_i + 1));
-    return next_filename;
-}
\ No newline at end of file
+// A replacement gets a name of its own, so the old file is still there if
+// storing the new one fails
+pub fn replaced_image_file_name(image_id: i32, extension: &str) -> String {
+    format!("{}_{:016x}.{}", image_id, rand::random::<u64>(), extension)
+}